@@ -4,10 +4,10 @@
 //! including message handling, async operations, and throughput.
 
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
-use tokio_lsp::Client;
 use std::io::Cursor;
 use std::sync::Arc;
 use tokio::runtime::Runtime;
+use tokio_lsp::{Client, ClientConfig};
 
 /// Benchmark client creation performance
 fn bench_client_creation(c: &mut Criterion) {
@@ -52,8 +52,8 @@ fn bench_message_receiving(c: &mut Criterion) {
             rt.block_on(async {
                 let reader = Cursor::new(black_box(single_message).as_bytes().to_vec());
                 let writer = Cursor::new(Vec::new());
-                let mut client = Client::new(reader, writer);
-                
+                let mut client = Client::with_config(reader, writer, forwarding_config());
+
                 // This should receive the message without timeout
                 let _message = client.receive_message().await;
             })
@@ -67,7 +67,7 @@ fn bench_message_receiving(c: &mut Criterion) {
                 let multiple_messages = create_multiple_messages(10);
                 let reader = Cursor::new(black_box(&multiple_messages).as_bytes().to_vec());
                 let writer = Cursor::new(Vec::new());
-                let mut client = Client::new(reader, writer);
+                let mut client = Client::with_config(reader, writer, forwarding_config());
 
                 for _ in 0..10 {
                     let _message = client.receive_message().await;
@@ -131,7 +131,7 @@ fn bench_concurrent_operations(c: &mut Criterion) {
                 let reader = Cursor::new(Vec::new());
                 let writer = Cursor::new(Vec::new());
                 let client = Arc::new(Client::new(reader, writer));
-                
+
                 let tasks: Vec<_> = (0..10)
                     .map(|_| {
                         let client = Arc::clone(&client);
@@ -211,7 +211,7 @@ fn bench_memory_patterns(c: &mut Criterion) {
                 let reusable_data = create_multiple_messages(50);
                 let reader = Cursor::new(black_box(&reusable_data).as_bytes().to_vec());
                 let writer = Cursor::new(Vec::new());
-                let mut client = Client::new(reader, writer);
+                let mut client = Client::with_config(reader, writer, forwarding_config());
 
                 for _ in 0..50 {
                     let _message = client.receive_message().await;
@@ -227,7 +227,7 @@ fn bench_memory_patterns(c: &mut Criterion) {
                 let fresh_data = create_multiple_messages(50);
                 let reader = Cursor::new(black_box(&fresh_data).as_bytes().to_vec());
                 let writer = Cursor::new(Vec::new());
-                let mut client = Client::new(reader, writer);
+                let mut client = Client::with_config(reader, writer, forwarding_config());
 
                 for _ in 0..50 {
                     let _message = client.receive_message().await;
@@ -241,6 +241,11 @@ fn bench_memory_patterns(c: &mut Criterion) {
 
 // Helper functions to create test data
 
+/// Forward server requests to `receive_message` instead of answering them
+fn forwarding_config() -> ClientConfig {
+    ClientConfig::default().with_forward_unhandled_requests(true)
+}
+
 fn create_multiple_messages(count: usize) -> String {
     let mut result = String::new();
     for i in 0..count {
@@ -275,7 +280,6 @@ fn create_large_messages(count: usize) -> String {
     result
 }
 
-
 criterion_group!(
     benches,
     bench_client_creation,
@@ -3,10 +3,11 @@
 //! This benchmark suite tests the performance of different transport
 //! implementations, async I/O operations, and message framing.
 
-use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
-use tokio_lsp::{transport::*, types::*};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use std::io::Cursor;
+use tokio::io::AsyncWriteExt;
 use tokio::runtime::Runtime;
+use tokio_lsp::{transport::*, types::*};
 
 /// Benchmark transport creation with different data sizes
 fn bench_transport_creation(c: &mut Criterion) {
@@ -245,6 +246,57 @@ fn bench_throughput(c: &mut Criterion) {
     group.finish();
 }
 
+/// Benchmark reading large payloads, from memory and from a pipe
+fn bench_large_payloads(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("large_payloads");
+
+    let payload_sizes = vec![("64KiB", 64 * 1024), ("1MiB", 1024 * 1024)];
+
+    for (size_name, size) in payload_sizes {
+        let message = create_large_message(size);
+        group.throughput(Throughput::Bytes(message.len() as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("cursor", size_name),
+            &message,
+            |b, message| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let reader = Cursor::new(black_box(message.as_slice()));
+                        let writer = Cursor::new(Vec::new());
+                        let mut transport = Transport::new(reader, writer);
+
+                        let _message = transport.read_message().await.unwrap();
+                    })
+                });
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("pipe", size_name),
+            &message,
+            |b, message| {
+                b.iter(|| {
+                    rt.block_on(async {
+                        let (mut server, client) = tokio::io::duplex(64 * 1024);
+                        let message = black_box(message.clone());
+                        let writer = tokio::spawn(async move {
+                            server.write_all(&message).await.unwrap();
+                        });
+                        let mut transport = Transport::new(client, Cursor::new(Vec::new()));
+
+                        let _message = transport.read_message().await.unwrap();
+                        writer.await.unwrap();
+                    })
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
 /// Benchmark header parsing performance
 fn bench_header_parsing(c: &mut Criterion) {
     let rt = Runtime::new().unwrap();
@@ -346,6 +398,20 @@ fn create_message_sequence(count: usize) -> String {
     result
 }
 
+fn create_large_message(size: usize) -> Vec<u8> {
+    let data = "x".repeat(size);
+    let message_body = format!(
+        "{{\"jsonrpc\":\"2.0\",\"method\":\"large\",\"params\":{{\"data\":\"{}\"}}}}",
+        data
+    );
+    format!(
+        "Content-Length: {}\r\n\r\n{}",
+        message_body.len(),
+        message_body
+    )
+    .into_bytes()
+}
+
 fn create_request_message() -> String {
     let message_body = r#"{"jsonrpc":"2.0","id":1,"method":"textDocument/hover","params":{"textDocument":{"uri":"file:///test.rs"},"position":{"line":10,"character":5}}}"#;
     format!("Content-Length: {}\n\n{}", message_body.len(), message_body)
@@ -375,6 +441,7 @@ criterion_group!(
     bench_message_writing,
     bench_different_transports,
     bench_throughput,
+    bench_large_payloads,
     bench_header_parsing,
     bench_error_handling
 );
@@ -3,30 +3,50 @@
 //! This example shows how to create and initialize an LSP client
 //! with the most basic configuration.
 
+use tokio_lsp::transport::{self, MessageReceiver, MessageSender};
 use tokio_lsp::{prelude::*, Client};
-use std::io::Cursor;
-use tokio::time::{timeout, Duration};
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     println!("🚀 Basic LSP Client Example");
     println!("==========================");
 
-    // Create a mock server response for the initialize request
-    let mock_server_response = r#"Content-Length: 187
-
-{"jsonrpc":"2.0","id":1,"result":{"capabilities":{"textDocumentSync":1,"hoverProvider":true,"completionProvider":{"triggerCharacters":["."]}},"serverInfo":{"name":"Mock Server","version":"1.0.0"}}}"#;
-
-    // Create transport using in-memory buffers (for demo purposes)
-    let reader = Cursor::new(mock_server_response.as_bytes());
-    let writer = Cursor::new(Vec::new());
-
-    // Create the LSP client
-    let mut client = Client::new(reader, writer);
+    // Connect the client to an in-process mock server (for demo purposes)
+    let (client_end, mut server) = transport::pair();
+    let client = Client::with_transport(client_end, ClientConfig::default());
     println!("✅ Client created successfully");
 
-    // Create initialization parameters (use defaults to match crate types)
-    let _init_params = InitializeParams {
+    // Play the part of the server: answer the initialize request
+    let mock_server = tokio::spawn(async move {
+        let result = InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::Full,
+                )),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                completion_provider: Some(CompletionOptions {
+                    resolve_provider: None,
+                    trigger_characters: Some(vec![".".to_string()]),
+                    all_commit_characters: None,
+                }),
+            },
+            server_info: Some(ServerInfo {
+                name: "Mock Server".to_string(),
+                version: Some("1.0.0".to_string()),
+            }),
+        };
+        let result = serde_json::to_value(result).expect("result serializes");
+        while let Ok(Some(message)) = server.recv().await {
+            if let RpcMessage::Request(request) = message {
+                if request.method == "initialize" {
+                    let response = ResponseMessage::success(request.id, result.clone());
+                    let _ = server.send(&RpcMessage::Response(response)).await;
+                }
+            }
+        }
+    });
+
+    let init_params = InitializeParams {
         process_id: Some(std::process::id()),
         client_info: Some(ClientInfo {
             name: "Basic Example Client".to_string(),
@@ -43,65 +63,44 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 
     println!("📤 Sending initialize request...");
 
-    // Simulate receiving the initialize response
-    // In a real scenario, you would call client.initialize(init_params)
-    // but since we're using mock data, we'll just read the response
-    match timeout(Duration::from_millis(100), client.receive_message()).await {
-        Ok(Some(message)) => {
-            match message {
-                RpcMessage::Response(response) => {
-                    if let Some(result) = response.result {
-                        match serde_json::from_value::<InitializeResult>(result) {
-                            Ok(init_result) => {
-                                println!("✅ Initialize response received!");
-                                println!("🔧 Server capabilities:");
+    match client.initialize(init_params).await {
+        Ok(init_result) => {
+            println!("✅ Initialize response received!");
+            println!("🔧 Server capabilities:");
 
-                                if let Some(sync) = init_result.capabilities.text_document_sync {
-                                    println!("  📄 Text Document Sync: {:?}", sync);
-                                }
+            if let Some(sync) = init_result.capabilities.text_document_sync {
+                println!("  📄 Text Document Sync: {:?}", sync);
+            }
 
-                                // Print a simple summary of a couple of known capabilities if present
-                                if init_result.capabilities.hover_provider.is_some() {
-                                    println!("  🔍 Hover: Supported");
-                                }
-                                if init_result.capabilities.completion_provider.is_some() {
-                                    println!("  💡 Completion: Supported");
-                                }
+            // Print a simple summary of a couple of known capabilities if present
+            if init_result.capabilities.hover_provider.is_some() {
+                println!("  🔍 Hover: Supported");
+            }
+            if init_result.capabilities.completion_provider.is_some() {
+                println!("  💡 Completion: Supported");
+            }
 
-                                if let Some(server_info) = init_result.server_info {
-                                    println!(
-                                        "  🖥️  Server: {} v{}",
-                                        server_info.name,
-                                        server_info
-                                            .version
-                                            .unwrap_or_else(|| "unknown".to_string())
-                                    );
-                                }
-                            }
-                            Err(e) => {
-                                println!("❌ Failed to parse initialize result: {}", e);
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    println!("⚠️  Received unexpected message type");
-                }
+            if let Some(server_info) = init_result.server_info {
+                println!(
+                    "  🖥️  Server: {} v{}",
+                    server_info.name,
+                    server_info.version.unwrap_or_else(|| "unknown".to_string())
+                );
             }
         }
-        Ok(None) => {
-            println!("📭 No message received");
-        }
-        Err(_) => {
-            println!("⏰ Timeout waiting for response");
+        Err(e) => {
+            println!("❌ Initialize failed: {}", e);
         }
     }
 
+    drop(client);
+    mock_server.await?;
+
     println!("\n🏁 Basic client example completed!");
     println!("\n💡 Next steps:");
     println!("   - Try the file_operations example for document sync");
     println!("   - Try the diagnostics example for error handling");
-    println!("   - Connect to a real language server by replacing the mock transport");
+    println!("   - Connect to a real language server with Client::spawn");
 
     Ok(())
 }
@@ -3,73 +3,63 @@
 //! This example demonstrates how to handle diagnostic messages from
 //! language servers, including errors, warnings, and informational messages.
 
-use tokio_lsp::{prelude::*, Client};
-use std::io::Cursor;
+use futures::StreamExt;
 use tokio::time::{timeout, Duration};
+use tokio_lsp::transport::{self, MessageSender};
+use tokio_lsp::types::notification::PublishDiagnostics;
+use tokio_lsp::{prelude::*, Client};
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     println!("🔍 Diagnostics Handling Example");
     println!("===============================");
 
-    // Create mock diagnostic notifications with various severity levels
-    let mock_diagnostics = r#"Content-Length: 280
-
-{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///example.rs","diagnostics":[{"range":{"start":{"line":2,"character":8},"end":{"line":2,"character":9}},"message":"unused variable: `x`","severity":2,"code":"unused_variables","source":"rustc"}]}}Content-Length: 320
-
-{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///example.rs","diagnostics":[{"range":{"start":{"line":5,"character":4},"end":{"line":5,"character":20}},"message":"cannot find function `undefined_func` in this scope","severity":1,"code":"E0425","source":"rustc","relatedInformation":[{"location":{"uri":"file:///example.rs","range":{"start":{"line":1,"character":0},"end":{"line":1,"character":10}}},"message":"consider importing this function"}]}]}}Content-Length: 250
-
-{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///helper.rs","diagnostics":[{"range":{"start":{"line":0,"character":0},"end":{"line":0,"character":5}},"message":"this function could be marked as `const`","severity":3,"code":"clippy::missing_const_for_fn","source":"clippy"}]}}Content-Length: 180
+    // Mock diagnostic notifications with various severity levels
+    let mock_diagnostics = [
+        r#"{"uri":"file:///example.rs","diagnostics":[{"range":{"start":{"line":2,"character":8},"end":{"line":2,"character":9}},"message":"unused variable: `x`","severity":2,"code":"unused_variables","source":"rustc"}]}"#,
+        r#"{"uri":"file:///example.rs","diagnostics":[{"range":{"start":{"line":5,"character":4},"end":{"line":5,"character":20}},"message":"cannot find function `undefined_func` in this scope","severity":1,"code":"E0425","source":"rustc"}]}"#,
+        r#"{"uri":"file:///helper.rs","diagnostics":[{"range":{"start":{"line":0,"character":0},"end":{"line":0,"character":5}},"message":"this function could be marked as `const`","severity":3,"code":"clippy::missing_const_for_fn","source":"clippy"}]}"#,
+        r#"{"uri":"file:///example.rs","diagnostics":[{"range":{"start":{"line":10,"character":0},"end":{"line":10,"character":12}},"message":"TODO: implement error handling","severity":4,"source":"todo-comments"}]}"#,
+    ];
 
-{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///example.rs","diagnostics":[{"range":{"start":{"line":10,"character":0},"end":{"line":10,"character":12}},"message":"TODO: implement error handling","severity":4,"source":"todo-comments"}]}}"#;
+    // Connect the client to an in-process server
+    let (client_end, mut server) = transport::pair();
+    let client = Client::with_transport(client_end, ClientConfig::default());
 
-    let reader = Cursor::new(mock_diagnostics.as_bytes());
-    let writer = Cursor::new(Vec::new());
-    let mut client = Client::new(reader, writer);
+    // Subscribe before the server starts publishing
+    let mut diagnostics = client.subscribe::<PublishDiagnostics>();
 
     println!("✅ Client created for diagnostics handling");
 
+    // Play the part of the server
+    for params in mock_diagnostics {
+        let notification = NotificationMessage::with_params(
+            PublishDiagnostics::METHOD,
+            serde_json::from_str(params)?,
+        );
+        server.send(&RpcMessage::Notification(notification)).await?;
+    }
+
     // Process multiple diagnostic notifications
     println!("\n📊 Processing diagnostic notifications...\n");
 
-    for i in 1..=4 {
-        match timeout(Duration::from_millis(50), client.receive_message()).await {
-            Ok(Some(RpcMessage::Notification(notif))) => {
-                if notif.method == "textDocument/publishDiagnostics" {
-                    println!("📋 Diagnostic Set {}", i);
-                    if let Some(params) = notif.params {
-                        println!("   📄 Received diagnostics notification");
-                        println!("   🔍 Raw params: {}", params);
-
-                        // In a real implementation, you would parse the diagnostics here
-                        // and extract information like:
-                        // - File URI
-                        // - Error/Warning/Info messages
-                        // - Line/character positions
-                        // - Diagnostic codes and sources
-
-                        // Simulate some diagnostic analysis
-                        if params.to_string().contains("unused variable") {
-                            println!("   ⚠️  Found: Unused variable warning");
-                        }
-                        if params.to_string().contains("cannot find function") {
-                            println!("   ❌ Found: Function not found error");
-                        }
-                        if params.to_string().contains("clippy") {
-                            println!("   ℹ️  Found: Clippy suggestion");
-                        }
-                        if params.to_string().contains("TODO") {
-                            println!("   💡 Found: TODO comment");
-                        }
-
-                        println!(); // Extra spacing between diagnostic sets
-                    }
-                } else {
-                    println!("⚠️  Received non-diagnostic notification: {}", notif.method);
+    for i in 1..=mock_diagnostics.len() {
+        match timeout(Duration::from_millis(50), diagnostics.next()).await {
+            Ok(Some(params)) => {
+                println!("📋 Diagnostic Set {} for {}", i, params.uri);
+                for diagnostic in &params.diagnostics {
+                    let icon = match diagnostic.severity {
+                        Some(DiagnosticSeverity::Error) => "❌",
+                        Some(DiagnosticSeverity::Warning) => "⚠️ ",
+                        Some(DiagnosticSeverity::Information) => "ℹ️ ",
+                        Some(DiagnosticSeverity::Hint) | None => "💡",
+                    };
+                    println!(
+                        "   {} line {}: {}",
+                        icon, diagnostic.range.start.line, diagnostic.message
+                    );
                 }
-            }
-            Ok(Some(_)) => {
-                println!("⚠️  Received non-notification message");
+                println!(); // Extra spacing between diagnostic sets
             }
             Ok(None) => {
                 println!("📭 No more messages");
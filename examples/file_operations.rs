@@ -1,138 +1,114 @@
 //! File operations and text document synchronization example
 //!
-//! This example demonstrates the concepts of text document synchronization
-//! and shows how you would handle different file operations with the client.
+//! This example shows how the client's document store keeps a language
+//! server in sync with the documents open in an editor.
 
+use tokio_lsp::transport::{self, MessageReceiver};
 use tokio_lsp::{prelude::*, Client};
-use std::io::Cursor;
-use tokio::time::{timeout, Duration};
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
     println!("📄 File Operations Example");
     println!("==========================");
 
-    // Create mock server responses for various file operations
-    let mock_responses = r#"Content-Length: 150
-
-{"jsonrpc":"2.0","id":1,"result":{"capabilities":{"textDocumentSync":{"openClose":true,"change":2,"save":{"includeText":true}},"hoverProvider":true}}}Content-Length: 85
-
-{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///example.rs","diagnostics":[]}}Content-Length: 120
-
-{"jsonrpc":"2.0","id":2,"result":{"contents":{"kind":"markdown","value":"**Function**: `main`\n\nEntry point of the program"},"range":{"start":{"line":0,"character":0},"end":{"line":0,"character":4}}}}"#;
-
-    let reader = Cursor::new(mock_responses.as_bytes());
-    let writer = Cursor::new(Vec::new());
-    let mut client = Client::new(reader, writer);
-
+    // Connect the client to an in-process mock server (for demo purposes)
+    let (client_end, mut server) = transport::pair();
+    let client = Client::with_transport(client_end, ClientConfig::default());
     println!("✅ Client created for file operations");
 
-    // Step 1: Simulate opening a document
-    println!("\n📂 Step 1: Opening a text document");
-
-    let document_uri = "file:///example.rs";
-    let document_text = r#"fn main() {
-    println!("Hello, world!");
-    let x = 42;
-    println!("The answer is {}", x);
-}"#;
-
-    // In a real scenario, you'd create and send a textDocument/didOpen notification
-    println!("📤 Would send textDocument/didOpen notification");
-    println!("   URI: {}", document_uri);
-    println!("   Language: rust");
-    println!("   Version: 1");
-    println!("   Text length: {} characters", document_text.len());
-
-    // Simulate receiving diagnostics response
-    match timeout(Duration::from_millis(50), client.receive_message()).await {
-        Ok(Some(RpcMessage::Response(_response))) => {
-            println!("✅ Received initialize response (capabilities)");
-        }
-        _ => {}
-    }
-
-    // Step 2: Simulate receiving diagnostics
-    println!("\n🔍 Step 2: Receiving diagnostics");
-
-    match timeout(Duration::from_millis(50), client.receive_message()).await {
-        Ok(Some(RpcMessage::Notification(notif))) => {
-            if notif.method == "textDocument/publishDiagnostics" {
-                println!("📊 Received diagnostics notification");
-                if let Some(_params) = notif.params {
-                    println!("   📄 Would parse diagnostics here");
-                    println!("   ✅ No issues found in this demo!");
+    // Play the part of the server: show the notifications it receives
+    let mock_server = tokio::spawn(async move {
+        while let Ok(Some(message)) = server.recv().await {
+            if let RpcMessage::Notification(notification) = message {
+                let params = notification.params.unwrap_or_default();
+                match params["textDocument"]["version"].as_i64() {
+                    Some(version) => println!(
+                        "   📥 Server received {} (version {})",
+                        notification.method, version
+                    ),
+                    None => println!("   📥 Server received {}", notification.method),
                 }
             }
         }
-        _ => println!("⚠️  No diagnostics received"),
-    }
+    });
 
-    // Step 3: Simulate document changes
-    println!("\n✏️  Step 3: Making document changes");
+    let documents = client.documents();
+    let document_uri = "file:///example.rs";
 
+    // Step 1: Open a document
+    println!("\n📂 Step 1: Opening a text document");
+    let document_text = r#"fn main() {
+    println!("Hello, world!");
+}
+"#;
+    documents
+        .open(TextDocumentItem::new(
+            document_uri,
+            "rust",
+            1,
+            document_text,
+        ))
+        .await?;
+
+    // Step 2: Edit part of the document
+    println!("\n✏️  Step 2: Making an incremental change");
+    let greeting = Range::new(Position::new(1, 14), Position::new(1, 27));
+    let version = documents
+        .update(
+            document_uri,
+            vec![TextDocumentContentChangeEvent::incremental(
+                greeting,
+                "Hello, LSP world!",
+            )],
+        )
+        .await?;
+    println!("   📝 Document is now at version {}", version);
+
+    // Step 3: Replace the whole document with an editor-assigned version
+    println!("\n✏️  Step 3: Replacing the document content");
     let new_text = r#"fn main() {
-    println!("Hello, LSP world!");
     let x = 42;
-    let y = x * 2;
-    println!("The answer is {} and double is {}", x, y);
-}"#;
-
-    println!("📝 Would send textDocument/didChange notification");
-    println!("   Version: 2");
-    println!("   Change type: Full document replacement");
-    println!("   New text length: {} characters", new_text.len());
-
-    // Step 4: Simulate hover request
-    println!("\n🔍 Step 4: Requesting hover information");
-
-    let hover_position = Position {
-        line: 0,
-        character: 3, // Position at "main"
-    };
-
-    println!("📤 Would send textDocument/hover request");
-    println!(
-        "   Position: line {}, character {} (at 'main')",
-        hover_position.line, hover_position.character
-    );
-
-    // Simulate receiving hover response
-    match timeout(Duration::from_millis(50), client.receive_message()).await {
-        Ok(Some(RpcMessage::Response(response))) => {
-            if let Some(_result) = response.result {
-                println!("✅ Received hover response");
-                println!("   📋 Would contain hover information here");
-            }
-        }
-        _ => println!("⚠️  No hover response received"),
+    println!("The answer is {}", x);
+}
+"#;
+    documents
+        .change(
+            VersionedTextDocumentIdentifier::new(document_uri, 5),
+            vec![TextDocumentContentChangeEvent::full(new_text)],
+        )
+        .await?;
+
+    // Step 4: Out-of-order versions are rejected before reaching the server
+    println!("\n🚫 Step 4: Sending a stale change");
+    let stale = documents
+        .change(
+            VersionedTextDocumentIdentifier::new(document_uri, 4),
+            vec![TextDocumentContentChangeEvent::full("")],
+        )
+        .await;
+    if let Err(e) = stale {
+        println!("   ⚠️  {}", e);
+    }
+    if let Some(document) = documents.get(document_uri).await {
+        println!(
+            "   📋 Store holds version {} ({} characters)",
+            document.version,
+            document.text.len()
+        );
     }
 
-    // Step 5: Simulate saving the document
-    println!("\n💾 Step 5: Saving the document");
-
-    println!("📤 Would send textDocument/didSave notification");
-    println!("   Including text content in save notification");
-
-    // Step 6: Simulate closing the document
-    println!("\n📁 Step 6: Closing the document");
+    // Step 5: Close the document
+    println!("\n📁 Step 5: Closing the document");
+    documents.close(document_uri).await?;
 
-    println!("📤 Would send textDocument/didClose notification");
-    println!("   Document will be removed from server memory");
+    drop(client);
+    mock_server.await?;
 
     println!("\n🏁 File operations example completed!");
     println!("\n📚 What we demonstrated:");
     println!("   ✅ Document lifecycle management");
-    println!("   ✅ Text synchronization");
-    println!("   ✅ Change notifications");
-    println!("   ✅ Language features (hover)");
-    println!("   ✅ Save/close operations");
-
-    println!("\n💡 In a real application:");
-    println!("   - Connect to an actual language server");
-    println!("   - Handle responses asynchronously");
-    println!("   - Process diagnostics for error highlighting");
-    println!("   - Use hover data for tooltips and documentation");
+    println!("   ✅ Incremental and full text synchronization");
+    println!("   ✅ Version checking");
 
     Ok(())
 }
@@ -4,161 +4,502 @@
 //! with language servers according to the LSP specification.
 
 use crate::error::{LspError, Result};
-use crate::transport::Transport;
+use crate::rpc::{into_result, method_not_found, to_params, Outgoing, RequestHandlers};
+use crate::transport::{
+    MessageReceiver, MessageSender, MessageTransport, Traced, Tracer, Transport, TransportConfig,
+};
+use crate::types::notification::{Exit, Initialized, SetTrace};
+use crate::types::request::{Initialize, Shutdown};
 use crate::types::{
-    ClientCapabilities, ClientInfo, Id, InitializeParams, InitializeResult, NotificationMessage,
-    RequestMessage, ResponseMessage, RpcMessage,
+    ClientCapabilities, ClientInfo, Id, InitializeParams, InitializeResult, InitializedParams,
+    Notification, NotificationMessage, Request, RequestMessage, ResponseMessage, RpcMessage,
+    SetTraceParams, TraceValue,
 };
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicI64, Ordering};
+use futures::FutureExt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+mod dispatch;
+mod documents;
+mod lifecycle;
+mod process;
+mod queue;
+mod socket;
+mod subscriptions;
+
+pub use crate::rpc::{RequestCanceller, RequestHandle};
+pub use dispatch::DispatchMode;
+use dispatch::Dispatcher;
+pub use documents::DocumentStore;
+pub use lifecycle::ClientState;
+use lifecycle::Lifecycle;
+pub use process::ServerProcess;
+use queue::MessageQueue;
+pub use queue::OverflowPolicy;
+#[cfg(unix)]
+pub use socket::UnixServerListener;
+pub use socket::{ConnectConfig, TcpServerListener};
+pub use subscriptions::Subscription;
+use subscriptions::Subscriptions;
+
+/// The request timeout used by [`ClientConfig::default`].
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long [`Client::shutdown`] waits for the server to close the connection
+/// after the `exit` notification before the message task is aborted.
+const EXIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Configuration options for a [`Client`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// How long to wait for a response before a request fails with
+    /// [`LspError::Timeout`]. `None` waits indefinitely.
+    pub request_timeout: Option<Duration>,
+    /// Whether server requests without a registered handler are passed to
+    /// [`Client::receive_message`] instead of being answered with a
+    /// `METHOD_NOT_FOUND` error. Forwarded requests must be answered with
+    /// [`Client::send_response`].
+    pub forward_unhandled_requests: bool,
+    /// How handlers registered with [`Client::on_request`] are scheduled.
+    pub request_dispatch: DispatchMode,
+    /// How many handlers registered with [`Client::on_request`] may run at
+    /// once. Further requests wait for a handler to complete. `None` runs any
+    /// number of handlers.
+    pub max_in_flight_handlers: Option<usize>,
+    /// How many incoming messages [`Client::receive_message`] and each
+    /// [`Subscription`] queue up before `overflow_policy` applies.
+    /// `None`, the default, queues without limit, so no message is dropped.
+    pub message_capacity: Option<usize>,
+    /// What happens when a message arrives while its queue is full. Only
+    /// applies once a `message_capacity` is set, and defaults to
+    /// [`OverflowPolicy::DropOldest`].
+    pub overflow_policy: OverflowPolicy,
+    /// Options for reading and writing messages on the transport.
+    pub transport: TransportConfig,
+    /// Records every message exchanged with the server, if set.
+    pub tracer: Option<Tracer>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            forward_unhandled_requests: false,
+            request_dispatch: DispatchMode::Concurrent,
+            max_in_flight_handlers: None,
+            message_capacity: None,
+            overflow_policy: OverflowPolicy::DropOldest,
+            transport: TransportConfig::default(),
+            tracer: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Set the default timeout applied to every request.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Wait indefinitely for responses unless a request overrides it.
+    pub fn without_request_timeout(mut self) -> Self {
+        self.request_timeout = None;
+        self
+    }
+
+    /// Set whether unhandled server requests are forwarded to
+    /// [`Client::receive_message`].
+    pub fn with_forward_unhandled_requests(mut self, forward: bool) -> Self {
+        self.forward_unhandled_requests = forward;
+        self
+    }
+
+    /// Set how handlers registered with [`Client::on_request`] are scheduled.
+    pub fn with_request_dispatch(mut self, mode: DispatchMode) -> Self {
+        self.request_dispatch = mode;
+        self
+    }
+
+    /// Set how many handlers registered with [`Client::on_request`] may run
+    /// at once. A limit of zero is treated as one.
+    pub fn with_max_in_flight_handlers(mut self, limit: usize) -> Self {
+        self.max_in_flight_handlers = Some(limit);
+        self
+    }
+
+    /// Set how many incoming messages each queue holds. Messages arriving
+    /// while a queue is full are handled according to the overflow policy.
+    pub fn with_message_capacity(mut self, capacity: usize) -> Self {
+        self.message_capacity = Some(capacity);
+        self
+    }
+
+    /// Queue incoming messages without limit.
+    pub fn with_unbounded_messages(mut self) -> Self {
+        self.message_capacity = None;
+        self
+    }
+
+    /// Set what happens when a message arrives while its queue is full.
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// Set the options for reading and writing messages on the transport.
+    pub fn with_transport_config(mut self, transport: TransportConfig) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Record every message exchanged with the server with the given tracer.
+    pub fn with_tracer(mut self, tracer: Tracer) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+}
+
+/// State shared between the client, its background tasks and request handles.
+struct Shared {
+    /// The sending half of the transport and the requests awaiting responses.
+    outgoing: Arc<Outgoing>,
+    /// The lifecycle state of the connection.
+    lifecycle: Arc<Lifecycle>,
+    /// Set once the message task has stopped reading from the server.
+    closed: watch::Sender<bool>,
+    /// Handlers for requests sent by the server.
+    handlers: RequestHandlers<()>,
+    /// Schedules the handlers for requests sent by the server.
+    dispatcher: Dispatcher,
+    /// Subscribers to notifications sent by the server.
+    subscriptions: Subscriptions,
+}
+
+impl Shared {
+    /// Write a message through the sending half of the transport.
+    async fn write(&self, message: &RpcMessage) -> Result<()> {
+        self.outgoing.write(message).await
+    }
+
+    /// Send a notification, if the lifecycle state allows it.
+    async fn send_notification(
+        &self,
+        method: impl Into<String>,
+        params: Option<serde_json::Value>,
+    ) -> Result<()> {
+        let method = method.into();
+        self.lifecycle.check_notification(&method)?;
+        let notification = match params {
+            Some(params) => NotificationMessage::with_params(method, params),
+            None => NotificationMessage::new(method),
+        };
+        self.write(&RpcMessage::Notification(notification)).await
+    }
 
-/// Pending request information.
-struct PendingRequest {
-    sender: oneshot::Sender<ResponseMessage>,
+    /// Answer a request from the server with its registered handler.
+    ///
+    /// Returns the request if it has no handler and should be forwarded to
+    /// the caller; otherwise it is answered on a separate task, scheduled by
+    /// the dispatcher.
+    fn handle_request(
+        self: &Arc<Self>,
+        request: RequestMessage,
+        forward_unhandled: bool,
+    ) -> Option<RpcMessage> {
+        let (response, dispatched) = match self.handlers.dispatch(request.clone(), ()) {
+            Some(response) => (response.boxed(), true),
+            None if forward_unhandled => return Some(RpcMessage::Request(request)),
+            None => {
+                log::debug!("No handler for request {}", request.method);
+                let response = method_not_found(&request);
+                (futures::future::ready(response).boxed(), false)
+            }
+        };
+        let shared = Arc::clone(self);
+        let id = request.id.clone();
+        let respond = async move {
+            let response = response.await;
+            if let Err(e) = shared.write(&RpcMessage::Response(response)).await {
+                log::warn!("Failed to respond to request {}: {}", id, e);
+            }
+        };
+        if dispatched {
+            self.dispatcher.spawn(&request, respond);
+        } else {
+            tokio::spawn(respond);
+        }
+        None
+    }
 }
 
 /// The main LSP client for communicating with language servers.
-pub struct Client<R, W> {
-    /// The underlying transport for reading and writing messages.
-    transport: Arc<RwLock<Transport<R, W>>>,
-    /// Counter for generating unique request IDs.
-    request_id_counter: AtomicI64,
-    /// Pending requests waiting for responses.
-    pending_requests: Arc<RwLock<HashMap<Id, PendingRequest>>>,
-    /// Channel for receiving incoming messages.
-    message_receiver: Option<mpsc::UnboundedReceiver<RpcMessage>>,
-    /// Channel for sending outgoing messages.
-    #[allow(dead_code)]
-    message_sender: mpsc::UnboundedSender<RpcMessage>,
-    /// Handle for the message processing task.
-    _message_task: tokio::task::JoinHandle<()>,
+///
+/// The client talks to the server through any [`MessageTransport`]; the
+/// stream-based constructors use the base protocol [`Transport`]. Incoming
+/// messages are read by a dedicated background task that owns the receiving
+/// half of the transport, while outgoing messages go through the sending
+/// half. Sending a request or notification therefore never waits for the
+/// server to send something first.
+///
+/// The client follows the LSP lifecycle: requests other than `initialize`
+/// are rejected until the server is initialized, and nothing but `exit` may
+/// be sent once [`Client::shutdown`] has started.
+pub struct Client {
+    /// State shared with the background tasks.
+    shared: Arc<Shared>,
+    /// Client configuration.
+    config: ClientConfig,
+    /// Queue of incoming messages for [`Client::receive_message`].
+    messages: Arc<MessageQueue>,
+    /// The number of incoming messages dropped by any queue.
+    dropped_messages: Arc<AtomicU64>,
+    /// Handle for the message processing task, taken when it is joined.
+    message_task: std::sync::Mutex<Option<JoinHandle<()>>>,
+    /// The documents open on the server.
+    documents: DocumentStore,
 }
 
-impl<R, W> Client<R, W>
-where
-    R: AsyncRead + Unpin + Send + Sync + 'static,
-    W: AsyncWrite + Unpin + Send + Sync + 'static,
-{
-    /// Create a new LSP client with the given transport.
-    pub fn new(reader: R, writer: W) -> Self {
-        let transport = Arc::new(RwLock::new(Transport::new(reader, writer)));
-        let pending_requests: Arc<RwLock<HashMap<Id, PendingRequest>>> =
-            Arc::new(RwLock::new(HashMap::new()));
-        let (message_sender, message_receiver) = mpsc::unbounded_channel::<RpcMessage>();
-        let message_sender_clone = message_sender.clone();
+impl Client {
+    /// Create a new LSP client speaking the base protocol over the given
+    /// streams.
+    pub fn new<R, W>(reader: R, writer: W) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        Self::with_config(reader, writer, ClientConfig::default())
+    }
+
+    /// Create a new LSP client speaking the base protocol over the given
+    /// streams, with the given configuration.
+    pub fn with_config<R, W>(reader: R, writer: W, config: ClientConfig) -> Self
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let transport = Transport::with_config(reader, writer, config.transport.clone());
+        Self::with_transport(transport, config)
+    }
+
+    /// Create a new LSP client communicating through the given transport.
+    ///
+    /// [`ClientConfig::transport`] only applies to the stream-based
+    /// constructors; a transport passed here is used as configured.
+    pub fn with_transport(transport: impl MessageTransport, config: ClientConfig) -> Self {
+        let (receiver, sender) = transport.split();
+        Self::from_parts(receiver, sender, config)
+    }
+
+    /// Create a new LSP client from the already split halves of a transport.
+    pub fn from_parts(
+        receiver: impl MessageReceiver,
+        sender: impl MessageSender,
+        config: ClientConfig,
+    ) -> Self {
+        let (mut receiver, sender): (Box<dyn MessageReceiver>, Box<dyn MessageSender>) =
+            match &config.tracer {
+                Some(tracer) => (
+                    Box::new(Traced::new(receiver, tracer.clone())),
+                    Box::new(Traced::new(sender, tracer.clone())),
+                ),
+                None => (Box::new(receiver), Box::new(sender)),
+            };
+        let dropped_messages = Arc::new(AtomicU64::new(0));
+        let lifecycle = Arc::new(Lifecycle::new());
+        let request_lifecycle = Arc::clone(&lifecycle);
+        let shared = Arc::new(Shared {
+            outgoing: Outgoing::new(sender, move |method, succeeded| {
+                request_lifecycle.end_request(method, succeeded)
+            }),
+            lifecycle,
+            closed: watch::Sender::new(false),
+            handlers: RequestHandlers::default(),
+            dispatcher: Dispatcher::new(config.request_dispatch, config.max_in_flight_handlers),
+            subscriptions: Subscriptions::new(
+                config.message_capacity,
+                config.overflow_policy.clone(),
+                Arc::clone(&dropped_messages),
+            ),
+        });
+        let messages = Arc::new(MessageQueue::new(
+            config.message_capacity,
+            config.overflow_policy.clone(),
+            Arc::clone(&dropped_messages),
+        ));
+        let messages_clone = Arc::clone(&messages);
 
         // Spawn task to handle incoming messages
-        let transport_clone = Arc::clone(&transport);
-        let pending_requests_clone = Arc::clone(&pending_requests);
+        let shared_clone = Arc::clone(&shared);
+        let forward_unhandled_requests = config.forward_unhandled_requests;
+        let tracer = config.tracer.clone();
         let message_task = tokio::spawn(async move {
             loop {
-                let message = {
-                    let mut transport = transport_clone.write().await;
-                    match transport.read_message().await {
-                        Ok(msg) => msg,
-                        Err(e) => {
-                            log::error!("Failed to read message: {}", e);
-                            break;
-                        }
+                let rpc_message = match receiver.recv().await {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => {
+                        log::debug!("Server closed the connection");
+                        break;
                     }
-                };
-
-                let rpc_message = match message.parse_rpc_message() {
-                    Ok(msg) => msg,
-                    Err(e) => {
+                    Err(LspError::Transport(e)) => {
+                        // The receiver has skipped the rejected message
+                        log::error!("Rejected message from server: {}", e);
+                        continue;
+                    }
+                    Err(LspError::Json(e)) => {
                         log::error!("Failed to parse RPC message: {}", e);
                         continue;
                     }
+                    Err(e) => {
+                        log::error!("Failed to read message: {}", e);
+                        break;
+                    }
                 };
 
-                match &rpc_message {
+                let forward = match rpc_message {
                     RpcMessage::Response(response) => {
                         if let Some(id) = &response.id {
-                            let mut pending = pending_requests_clone.write().await;
-                            if let Some(pending_request) = pending.remove(id) {
-                                if let Err(e) = pending_request.sender.send(response.clone()) {
-                                    log::warn!(
-                                        "Failed to send response to pending request: {:?}",
-                                        e
-                                    );
-                                }
-                            } else {
+                            if !shared_clone.outgoing.complete(id, Ok(response.clone())) {
                                 log::warn!("Received response for unknown request ID: {}", id);
                             }
                         }
+                        None
                     }
-                    RpcMessage::Request(_) | RpcMessage::Notification(_) => {
-                        // Forward to client for handling
-                        if message_sender_clone.send(rpc_message).is_err() {
-                            log::error!("Message receiver dropped, stopping message loop");
-                            break;
-                        }
+                    RpcMessage::Request(request) => {
+                        shared_clone.handle_request(request, forward_unhandled_requests)
+                    }
+                    RpcMessage::Notification(notification) => shared_clone
+                        .subscriptions
+                        .publish(notification)
+                        .await
+                        .map(RpcMessage::Notification),
+                };
+
+                // Forward to client for handling
+                if let Some(message) = forward {
+                    if !messages_clone.push(message).await {
+                        log::error!("Message receiver dropped, stopping message loop");
+                        break;
                     }
                 }
             }
+
+            shared_clone
+                .outgoing
+                .fail_pending("Connection to the server was closed");
+            shared_clone.subscriptions.close();
+            messages_clone.close();
+            if let Some(tracer) = tracer {
+                if let Err(e) = tracer.flush() {
+                    log::warn!("Failed to flush message trace: {}", e);
+                }
+            }
+            shared_clone.closed.send_replace(true);
         });
 
         Self {
-            transport,
-            request_id_counter: AtomicI64::new(1),
-            pending_requests,
-            message_receiver: Some(message_receiver),
-            message_sender,
-            _message_task: message_task,
+            documents: DocumentStore::new(Arc::clone(&shared)),
+            shared,
+            config,
+            messages,
+            dropped_messages,
+            message_task: std::sync::Mutex::new(Some(message_task)),
         }
     }
 
-    /// Generate a new unique request ID.
-    fn next_request_id(&self) -> Id {
-        Id::Number(self.request_id_counter.fetch_add(1, Ordering::SeqCst))
+    /// Get the current lifecycle state of the connection.
+    pub fn state(&self) -> ClientState {
+        self.shared.lifecycle.state()
+    }
+
+    /// Get the store of documents open on the server, which sends
+    /// `textDocument/didOpen`, `didChange` and `didClose` for them.
+    pub fn documents(&self) -> &DocumentStore {
+        &self.documents
+    }
+
+    /// Get the configuration this client was created with.
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
     }
 
     /// Send a request and wait for the response.
+    ///
+    /// The request fails with [`LspError::Timeout`] if no response arrives
+    /// within the client's configured [`ClientConfig::request_timeout`].
     pub async fn send_request(
         &self,
         method: impl Into<String>,
         params: Option<serde_json::Value>,
     ) -> Result<ResponseMessage> {
-        let id = self.next_request_id();
-        let request = match params {
-            Some(params) => RequestMessage::with_params(id.clone(), method, params),
-            None => RequestMessage::new(id.clone(), method),
-        };
+        self.send_request_inner(method.into(), params, self.config.request_timeout)
+            .await
+    }
 
-        let (response_sender, response_receiver) = oneshot::channel();
-
-        // Register the pending request
-        {
-            let mut pending = self.pending_requests.write().await;
-            pending.insert(
-                id.clone(),
-                PendingRequest {
-                    sender: response_sender,
-                },
-            );
-        }
+    /// Send a request and wait at most `timeout` for the response, overriding
+    /// the client's default request timeout.
+    pub async fn send_request_with_timeout(
+        &self,
+        method: impl Into<String>,
+        params: Option<serde_json::Value>,
+        timeout: Duration,
+    ) -> Result<ResponseMessage> {
+        self.send_request_inner(method.into(), params, Some(timeout))
+            .await
+    }
 
-        // Send the request
-        {
-            let mut transport = self.transport.write().await;
-            transport
-                .write_rpc_message(&RpcMessage::Request(request))
-                .await?;
-        }
+    async fn send_request_inner(
+        &self,
+        method: String,
+        params: Option<serde_json::Value>,
+        timeout: Option<Duration>,
+    ) -> Result<ResponseMessage> {
+        self.shared.lifecycle.begin_request(&method)?;
+        self.shared
+            .outgoing
+            .send_request(method, params, timeout)
+            .await
+    }
 
-        // Wait for the response
-        match response_receiver.await {
-            Ok(response) => Ok(response),
-            Err(_) => {
-                // Clean up the pending request if it wasn't already removed
-                let mut pending = self.pending_requests.write().await;
-                pending.remove(&id);
-                Err(LspError::Other("Response receiver dropped".to_string()))
-            }
+    /// Send a typed request and wait for its result.
+    ///
+    /// The parameters are serialized according to the request type, and an
+    /// error response from the server is returned as [`LspError::Protocol`].
+    pub async fn request<Q: Request>(&self, params: Q::Params) -> Result<Q::Result> {
+        into_result::<Q>(self.send_request(Q::METHOD, to_params(params)?).await?)
+    }
+
+    /// Send a typed notification.
+    pub async fn notify<N: Notification>(&self, params: N::Params) -> Result<()> {
+        self.send_notification(N::METHOD, to_params(params)?).await
+    }
+
+    /// Change the trace setting of the server with `$/setTrace`, and the
+    /// trace level of [`ClientConfig::tracer`] if one is set.
+    pub async fn set_trace(&self, value: TraceValue) -> Result<()> {
+        if let Some(tracer) = &self.config.tracer {
+            tracer.set_trace(value);
         }
+        self.notify::<SetTrace>(SetTraceParams { value }).await
+    }
+
+    /// Send a request and return a handle to its pending response.
+    ///
+    /// Unlike [`Client::send_request`], no timeout is applied. Dropping the
+    /// handle before the response arrives cancels the request.
+    pub async fn start_request(
+        &self,
+        method: impl Into<String>,
+        params: Option<serde_json::Value>,
+    ) -> Result<RequestHandle> {
+        let method = method.into();
+        self.shared.lifecycle.begin_request(&method)?;
+        self.shared.outgoing.start(method, params).await
     }
 
     /// Send a notification (no response expected).
@@ -167,25 +508,64 @@ where
         method: impl Into<String>,
         params: Option<serde_json::Value>,
     ) -> Result<()> {
-        let notification = match params {
-            Some(params) => NotificationMessage::with_params(method, params),
-            None => NotificationMessage::new(method),
-        };
+        self.shared.send_notification(method, params).await
+    }
 
-        let mut transport = self.transport.write().await;
-        transport
-            .write_rpc_message(&RpcMessage::Notification(notification))
-            .await
+    /// Register a handler answering requests of type `R` sent by the server.
+    ///
+    /// The handler replaces any handler previously registered for the same
+    /// method. Errors it returns are sent back to the server: an
+    /// [`LspError::Protocol`] error is sent as is, any other error or a panic
+    /// becomes an `INTERNAL_ERROR` response.
+    ///
+    /// Handlers run on tasks of their own, scheduled according to
+    /// [`ClientConfig::request_dispatch`] and
+    /// [`ClientConfig::max_in_flight_handlers`], so a slow handler does not
+    /// hold up messages from the server.
+    ///
+    /// Requests without a handler are answered with `METHOD_NOT_FOUND`,
+    /// unless [`ClientConfig::forward_unhandled_requests`] is set.
+    pub fn on_request<Q, F, Fut>(&self, handler: F)
+    where
+        Q: Request,
+        F: Fn(Q::Params) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Q::Result>> + Send + 'static,
+    {
+        self.shared
+            .handlers
+            .insert::<Q, _, _>(move |params, ()| handler(params));
+    }
+
+    /// Remove the handler registered for `method`.
+    /// Returns `false` if no handler was registered.
+    pub fn remove_request_handler(&self, method: &str) -> bool {
+        self.shared.handlers.remove(method)
+    }
+
+    /// Subscribe to notifications of type `N` sent by the server.
+    ///
+    /// Every subscriber to a method receives each of its notifications.
+    /// Notifications nobody is subscribed to are delivered through
+    /// [`Client::receive_message`] instead. Subscribing after the connection
+    /// was closed returns a subscription that has already ended.
+    pub fn subscribe<N: Notification>(&self) -> Subscription<N> {
+        self.shared.subscriptions.subscribe::<N>()
     }
 
     /// Receive the next incoming message (request or notification from server).
     /// This method should be called in a loop to handle all incoming messages.
+    ///
+    /// Only notifications without a [`Client::subscribe`] subscriber and
+    /// requests without a handler (if forwarding is enabled) arrive here.
     pub async fn receive_message(&mut self) -> Option<RpcMessage> {
-        if let Some(ref mut receiver) = self.message_receiver {
-            receiver.recv().await
-        } else {
-            None
-        }
+        self.messages.recv().await
+    }
+
+    /// Get the number of incoming messages dropped or coalesced because a
+    /// queue was full, across [`Client::receive_message`] and all
+    /// subscriptions.
+    pub fn dropped_messages(&self) -> u64 {
+        self.dropped_messages.load(Ordering::Relaxed)
     }
 
     /// Send a response to a request from the server.
@@ -201,33 +581,54 @@ where
             ResponseMessage::success(id, result.unwrap_or(serde_json::Value::Null))
         };
 
-        let mut transport = self.transport.write().await;
-        transport
-            .write_rpc_message(&RpcMessage::Response(response))
-            .await
+        self.write(&RpcMessage::Response(response)).await
+    }
+
+    /// Write a message through the sending half of the transport.
+    async fn write(&self, message: &RpcMessage) -> Result<()> {
+        self.shared.write(message).await
+    }
+
+    /// Check whether the connection to the server has been closed.
+    pub fn is_closed(&self) -> bool {
+        *self.shared.closed.borrow()
+    }
+
+    /// Wait until the connection to the server is closed.
+    ///
+    /// This resolves once the server closes its end of the transport or
+    /// reading from it fails, after pending requests have been failed.
+    pub async fn closed(&self) {
+        let mut closed = self.shared.closed.subscribe();
+        let _ = closed.wait_for(|closed| *closed).await;
     }
 
     /// Check if there are any pending requests.
     pub async fn has_pending_requests(&self) -> bool {
-        !self.pending_requests.read().await.is_empty()
+        self.shared.outgoing.pending_count() > 0
     }
 
     /// Get the number of pending requests.
     pub async fn pending_request_count(&self) -> usize {
-        self.pending_requests.read().await.len()
+        self.shared.outgoing.pending_count()
     }
 
     /// Cancel all pending requests.
+    ///
+    /// Every waiting caller receives an [`LspError::Protocol`] error with the
+    /// `REQUEST_CANCELLED` code, and the server is sent a `$/cancelRequest`
+    /// notification for each request.
     pub async fn cancel_all_requests(&self) {
-        let mut pending = self.pending_requests.write().await;
-        pending.clear();
+        for id in self.shared.outgoing.pending_ids() {
+            self.shared.outgoing.cancel(&id).await;
+        }
     }
 
     /// Initialize the LSP server with the given parameters.
     /// This is typically the first method called after creating the client.
     pub async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
         let response = self
-            .send_request("initialize", Some(serde_json::to_value(params)?))
+            .send_request(Initialize::METHOD, Some(serde_json::to_value(params)?))
             .await?;
 
         if let Some(error) = response.error {
@@ -249,8 +650,7 @@ where
     /// Send the 'initialized' notification to the server.
     /// This should be called after a successful 'initialize' request.
     pub async fn initialized(&self) -> Result<()> {
-        self.send_notification("initialized", Some(serde_json::json!({})))
-            .await
+        self.notify::<Initialized>(InitializedParams {}).await
     }
 
     /// Complete the initialization handshake with default parameters.
@@ -281,17 +681,58 @@ where
         self.initialized().await?;
         Ok(result)
     }
+
+    /// Shut down the server and close the connection.
+    ///
+    /// This sends the `shutdown` request, waits for its response, and then
+    /// sends the `exit` notification. Any request still pending afterwards
+    /// fails with [`LspError::Connection`], and the message task is joined
+    /// once the server closes its side of the connection.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.request::<Shutdown>(()).await?;
+        self.notify::<Exit>(()).await?;
+
+        self.shared
+            .outgoing
+            .fail_pending("The client has shut down");
+
+        let message_task = self
+            .message_task
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(mut message_task) = message_task {
+            if tokio::time::timeout(EXIT_TIMEOUT, &mut message_task)
+                .await
+                .is_err()
+            {
+                log::warn!("Server did not close the connection after exit");
+                message_task.abort();
+            }
+        }
+        Ok(())
+    }
 }
 
-impl<R, W> Drop for Client<R, W> {
+impl Drop for Client {
     fn drop(&mut self) {
-        // The join handle will be cancelled when dropped
+        let message_task = self
+            .message_task
+            .get_mut()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        if let Some(message_task) = message_task {
+            message_task.abort();
+        }
+
+        self.shared.outgoing.fail_pending("The client was dropped");
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::error_codes;
     use serde_json::json;
     use std::io::Cursor;
 
@@ -312,8 +753,8 @@ mod tests {
 
         let client = Client::new(reader, writer);
 
-        let id1 = client.next_request_id();
-        let id2 = client.next_request_id();
+        let id1 = client.shared.outgoing.next_id();
+        let id2 = client.shared.outgoing.next_id();
 
         assert_ne!(id1, id2);
         // IDs should be increasing
@@ -339,10 +780,17 @@ mod tests {
         assert!(result2.is_ok());
     }
 
-    #[test]
-    fn test_pending_request_struct() {
-        let (sender, _receiver) = oneshot::channel();
-        let _pending = PendingRequest { sender };
-        // Just test that the struct can be created
+    #[tokio::test]
+    async fn test_lifecycle_gates_requests() {
+        let client = Client::new(Cursor::new(vec![]), Cursor::new(Vec::new()));
+        assert_eq!(client.state(), ClientState::Uninitialized);
+
+        match client.send_request("textDocument/hover", None).await {
+            Err(LspError::Protocol(error)) => {
+                assert_eq!(error.code, error_codes::SERVER_NOT_INITIALIZED)
+            }
+            other => panic!("Expected protocol error, got {:?}", other),
+        }
+        assert_eq!(client.pending_request_count().await, 0);
     }
 }
@@ -0,0 +1,210 @@
+//! Scheduling of handlers for requests sent from the server to the client.
+
+use crate::types::RequestMessage;
+use futures::future::Shared;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{oneshot, Semaphore};
+
+/// How the handlers registered with [`Client::on_request`](crate::Client::on_request)
+/// are scheduled.
+///
+/// Each response is sent as soon as its handler completes, so responses may
+/// reach the server in a different order than the requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DispatchMode {
+    /// Handle requests one at a time, in the order they arrive.
+    Sequential,
+    /// Handle requests concurrently.
+    #[default]
+    Concurrent,
+    /// Handle requests concurrently, except that requests about the same
+    /// document are handled one at a time, in the order they arrive.
+    ///
+    /// The document is taken from the `textDocument.uri` or `uri` parameter.
+    /// Requests naming no document are handled concurrently.
+    PerDocument,
+}
+
+/// The request handled last for an ordering key, which the next request with
+/// the same key waits for.
+type Tail = (u64, Shared<oneshot::Receiver<()>>);
+
+/// Spawns request handlers according to a [`DispatchMode`].
+pub(crate) struct Dispatcher {
+    mode: DispatchMode,
+    /// Permits for running handlers, if their number is limited.
+    limit: Option<Arc<Semaphore>>,
+    tails: Arc<Mutex<HashMap<String, Tail>>>,
+    next_ticket: AtomicU64,
+}
+
+impl Dispatcher {
+    /// Create a dispatcher running at most `max_in_flight` handlers at once.
+    pub(crate) fn new(mode: DispatchMode, max_in_flight: Option<usize>) -> Self {
+        Self {
+            mode,
+            limit: max_in_flight.map(|limit| Arc::new(Semaphore::new(limit.max(1)))),
+            tails: Arc::new(Mutex::new(HashMap::new())),
+            next_ticket: AtomicU64::new(0),
+        }
+    }
+
+    /// Spawn a task running `handled`, the handling of `request`, once its
+    /// turn has come.
+    pub(crate) fn spawn<F>(&self, request: &RequestMessage, handled: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // The turn is taken before spawning, so that requests with the same
+        // key run in the order they were dispatched
+        let turn = self.ordering_key(request).map(|key| self.take_turn(key));
+        let limit = self.limit.clone();
+        tokio::spawn(async move {
+            let _turn = match turn {
+                Some(mut turn) => {
+                    if let Some(previous) = turn.previous.take() {
+                        let _ = previous.await;
+                    }
+                    Some(turn)
+                }
+                None => None,
+            };
+            let _permit = match limit {
+                Some(limit) => limit.acquire_owned().await.ok(),
+                None => None,
+            };
+            handled.await;
+        });
+    }
+
+    fn ordering_key(&self, request: &RequestMessage) -> Option<String> {
+        match self.mode {
+            DispatchMode::Sequential => Some(String::new()),
+            DispatchMode::Concurrent => None,
+            DispatchMode::PerDocument => {
+                let params = request.params.as_ref()?;
+                let uri = params
+                    .get("textDocument")
+                    .and_then(|document| document.get("uri"))
+                    .or_else(|| params.get("uri"))?;
+                uri.as_str().map(str::to_string)
+            }
+        }
+    }
+
+    fn take_turn(&self, key: String) -> Turn {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let (done, receiver) = oneshot::channel();
+        let previous = self
+            .tails
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.clone(), (ticket, receiver.shared()))
+            .map(|(_, previous)| previous);
+        Turn {
+            key,
+            ticket,
+            previous,
+            _done: done,
+            tails: Arc::clone(&self.tails),
+        }
+    }
+}
+
+/// A place in the order of requests sharing an ordering key. Dropping it lets
+/// the next request run, even if the handler panicked or was aborted.
+struct Turn {
+    key: String,
+    ticket: u64,
+    previous: Option<Shared<oneshot::Receiver<()>>>,
+    _done: oneshot::Sender<()>,
+    tails: Arc<Mutex<HashMap<String, Tail>>>,
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        let mut tails = self.tails.lock().unwrap_or_else(|e| e.into_inner());
+        if tails
+            .get(&self.key)
+            .is_some_and(|(ticket, _)| *ticket == self.ticket)
+        {
+            tails.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    /// Dispatch requests that sleep for the given durations, returning the
+    /// indices of the requests in the order they completed.
+    async fn run(dispatcher: Dispatcher, requests: Vec<(serde_json::Value, u64)>) -> Vec<usize> {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let count = requests.len();
+        for (index, (params, delay)) in requests.into_iter().enumerate() {
+            let request = RequestMessage::with_params(index as i64, "test", params);
+            let sender = sender.clone();
+            dispatcher.spawn(&request, async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                let _ = sender.send(index);
+            });
+        }
+        let mut completed = Vec::new();
+        for _ in 0..count {
+            completed.push(receiver.recv().await.unwrap());
+        }
+        completed
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sequential_handles_in_arrival_order() {
+        let dispatcher = Dispatcher::new(DispatchMode::Sequential, None);
+        let requests = vec![(json!({}), 30), (json!({}), 10), (json!({}), 20)];
+        assert_eq!(run(dispatcher, requests).await, vec![0, 1, 2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_handles_in_completion_order() {
+        let dispatcher = Dispatcher::new(DispatchMode::Concurrent, None);
+        let requests = vec![(json!({}), 30), (json!({}), 10), (json!({}), 20)];
+        assert_eq!(run(dispatcher, requests).await, vec![1, 2, 0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_document_orders_requests_about_the_same_document() {
+        let dispatcher = Dispatcher::new(DispatchMode::PerDocument, None);
+        let tails = Arc::clone(&dispatcher.tails);
+        let a = json!({"textDocument": {"uri": "file:///a.rs"}});
+        let b = json!({"uri": "file:///b.rs"});
+        let requests = vec![
+            (a.clone(), 30),
+            (b.clone(), 20),
+            (a, 5),
+            (json!({}), 1),
+            (b, 5),
+        ];
+        assert_eq!(run(dispatcher, requests).await, vec![3, 1, 4, 0, 2]);
+        // Orderings are forgotten once their requests are handled
+        assert!(tails.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_in_flight_limit() {
+        let dispatcher = Dispatcher::new(DispatchMode::Concurrent, Some(2));
+        let requests = vec![(json!({}), 30), (json!({}), 20), (json!({}), 5)];
+        // The third request only starts once the second completes, at 20ms
+        assert_eq!(run(dispatcher, requests).await, vec![1, 2, 0]);
+
+        let dispatcher = Dispatcher::new(DispatchMode::Concurrent, Some(1));
+        let requests = vec![(json!({}), 30), (json!({}), 20), (json!({}), 5)];
+        assert_eq!(run(dispatcher, requests).await, vec![0, 1, 2]);
+    }
+}
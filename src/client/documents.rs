@@ -0,0 +1,277 @@
+//! Bookkeeping of the documents open on the server.
+
+use super::Shared;
+use crate::error::{DocumentError, Result};
+use crate::rpc::to_params;
+use crate::types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument,
+};
+use crate::types::{
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentUri, Notification, TextDocumentContentChangeEvent, TextDocumentIdentifier,
+    TextDocumentItem, VersionedTextDocumentIdentifier,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// The documents a [`Client`](crate::Client) has open on the server.
+///
+/// The store keeps the URI, language, version and text of each open document
+/// and sends `textDocument/didOpen`, `didChange` and `didClose` as documents
+/// are opened, changed and closed. Operations the protocol forbids, such as
+/// opening a document twice or changing it without increasing its version,
+/// are rejected with a [`DocumentError`] before anything is sent.
+///
+/// Notifications are sent in the order the store is updated, so concurrent
+/// changes reach the server with increasing versions.
+pub struct DocumentStore {
+    shared: Arc<Shared>,
+    documents: Mutex<HashMap<DocumentUri, TextDocumentItem>>,
+}
+
+impl DocumentStore {
+    pub(super) fn new(shared: Arc<Shared>) -> Self {
+        Self {
+            shared,
+            documents: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Open a document, sending `textDocument/didOpen`.
+    pub async fn open(&self, document: TextDocumentItem) -> Result<()> {
+        let mut documents = self.documents.lock().await;
+        if documents.contains_key(&document.uri) {
+            return Err(DocumentError::AlreadyOpen { uri: document.uri }.into());
+        }
+        let params = DidOpenTextDocumentParams {
+            text_document: document.clone(),
+        };
+        self.send::<DidOpenTextDocument>(params).await?;
+        documents.insert(document.uri.clone(), document);
+        Ok(())
+    }
+
+    /// Track a document as open without sending `textDocument/didOpen`, so
+    /// that it is reopened when the server is restarted. Returns `false` if
+    /// the document was already open.
+    pub(crate) async fn track(&self, document: TextDocumentItem) -> bool {
+        let mut documents = self.documents.lock().await;
+        if documents.contains_key(&document.uri) {
+            return false;
+        }
+        documents.insert(document.uri.clone(), document);
+        true
+    }
+
+    /// Change an open document, sending `textDocument/didChange`.
+    ///
+    /// `document.version` is the version after the changes are applied, and
+    /// must be greater than the current version of the document.
+    pub async fn change(
+        &self,
+        document: VersionedTextDocumentIdentifier,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<()> {
+        let mut documents = self.documents.lock().await;
+        self.apply(&mut documents, document, changes).await
+    }
+
+    /// Change an open document as its next version, sending
+    /// `textDocument/didChange`. Returns the new version.
+    pub async fn update(
+        &self,
+        uri: &str,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<i32> {
+        let mut documents = self.documents.lock().await;
+        let Some(current) = documents.get(uri) else {
+            return Err(DocumentError::NotOpen { uri: uri.into() }.into());
+        };
+        let version = current.version + 1;
+        let document = VersionedTextDocumentIdentifier::new(uri, version);
+        self.apply(&mut documents, document, changes).await?;
+        Ok(version)
+    }
+
+    /// Apply a change to a document while holding the lock on the store.
+    async fn apply(
+        &self,
+        documents: &mut HashMap<DocumentUri, TextDocumentItem>,
+        document: VersionedTextDocumentIdentifier,
+        changes: Vec<TextDocumentContentChangeEvent>,
+    ) -> Result<()> {
+        let Some(current) = documents.get_mut(&document.uri) else {
+            return Err(DocumentError::NotOpen { uri: document.uri }.into());
+        };
+        if document.version <= current.version {
+            return Err(DocumentError::OutOfOrderVersion {
+                uri: document.uri,
+                current: current.version,
+                version: document.version,
+            }
+            .into());
+        }
+
+        let mut text = current.text.clone();
+        for change in &changes {
+            change.apply(&mut text);
+        }
+        let version = document.version;
+        let params = DidChangeTextDocumentParams {
+            text_document: document,
+            content_changes: changes,
+        };
+        self.send::<DidChangeTextDocument>(params).await?;
+        current.version = version;
+        current.text = text;
+        Ok(())
+    }
+
+    /// Close an open document, sending `textDocument/didClose`.
+    pub async fn close(&self, uri: &str) -> Result<()> {
+        let mut documents = self.documents.lock().await;
+        if !documents.contains_key(uri) {
+            return Err(DocumentError::NotOpen { uri: uri.into() }.into());
+        }
+        let params = DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri),
+        };
+        self.send::<DidCloseTextDocument>(params).await?;
+        documents.remove(uri);
+        Ok(())
+    }
+
+    /// Get an open document, with its current version and text.
+    pub async fn get(&self, uri: &str) -> Option<TextDocumentItem> {
+        self.documents.lock().await.get(uri).cloned()
+    }
+
+    /// Check whether a document is open.
+    pub async fn is_open(&self, uri: &str) -> bool {
+        self.documents.lock().await.contains_key(uri)
+    }
+
+    /// Get the URIs of the open documents.
+    pub async fn uris(&self) -> Vec<DocumentUri> {
+        self.documents.lock().await.keys().cloned().collect()
+    }
+
+    /// Get a copy of every open document.
+    pub async fn snapshot(&self) -> Vec<TextDocumentItem> {
+        self.documents.lock().await.values().cloned().collect()
+    }
+
+    async fn send<N: Notification>(&self, params: N::Params) -> Result<()> {
+        self.shared
+            .send_notification(N::METHOD, to_params(params)?)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LspError;
+    use crate::transport::{pair, ChannelTransport, MessageReceiver};
+    use crate::types::{Position, Range, RpcMessage};
+    use crate::{Client, ClientConfig};
+    use serde_json::json;
+
+    const URI: &str = "file:///test.rs";
+
+    async fn next_notification(server: &mut ChannelTransport) -> (String, serde_json::Value) {
+        match server.recv().await.unwrap() {
+            Some(RpcMessage::Notification(notification)) => {
+                (notification.method, notification.params.unwrap())
+            }
+            other => panic!("Expected notification, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_store_sends_document_notifications() {
+        let (client_end, mut server) = pair();
+        let client = Client::with_transport(client_end, ClientConfig::default());
+        let documents = client.documents();
+
+        documents
+            .open(TextDocumentItem::new(URI, "rust", 1, "fn main() {}\n"))
+            .await
+            .unwrap();
+        let (method, params) = next_notification(&mut server).await;
+        assert_eq!(method, "textDocument/didOpen");
+        assert_eq!(params["textDocument"]["languageId"], "rust");
+
+        let range = Range::new(Position::new(0, 3), Position::new(0, 7));
+        let version = documents
+            .update(
+                URI,
+                vec![TextDocumentContentChangeEvent::incremental(range, "start")],
+            )
+            .await
+            .unwrap();
+        assert_eq!(version, 2);
+        let (method, params) = next_notification(&mut server).await;
+        assert_eq!(method, "textDocument/didChange");
+        assert_eq!(params["textDocument"], json!({"uri": URI, "version": 2}));
+        assert_eq!(params["contentChanges"][0]["text"], "start");
+
+        let document = VersionedTextDocumentIdentifier::new(URI, 5);
+        let changes = vec![TextDocumentContentChangeEvent::full("fn run() {}\n")];
+        documents.change(document, changes).await.unwrap();
+        next_notification(&mut server).await;
+        let document = documents.get(URI).await.unwrap();
+        assert_eq!(
+            (document.version, document.text.as_str()),
+            (5, "fn run() {}\n")
+        );
+
+        documents.close(URI).await.unwrap();
+        let (method, params) = next_notification(&mut server).await;
+        assert_eq!(method, "textDocument/didClose");
+        assert_eq!(params, json!({"textDocument": {"uri": URI}}));
+        assert!(!documents.is_open(URI).await);
+        assert!(documents.uris().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_store_rejects_invalid_operations() {
+        let (client_end, mut server) = pair();
+        let client = Client::with_transport(client_end, ClientConfig::default());
+        let documents = client.documents();
+        let item = TextDocumentItem::new(URI, "rust", 3, "");
+
+        let result = documents.close(URI).await;
+        assert!(matches!(
+            result,
+            Err(LspError::Document(DocumentError::NotOpen { .. }))
+        ));
+        documents.open(item.clone()).await.unwrap();
+        let result = documents.open(item).await;
+        assert!(matches!(
+            result,
+            Err(LspError::Document(DocumentError::AlreadyOpen { .. }))
+        ));
+
+        let document = VersionedTextDocumentIdentifier::new(URI, 3);
+        let result = documents
+            .change(document, vec![TextDocumentContentChangeEvent::full("x")])
+            .await;
+        assert!(matches!(
+            result,
+            Err(LspError::Document(DocumentError::OutOfOrderVersion {
+                current: 3,
+                version: 3,
+                ..
+            }))
+        ));
+        assert_eq!(documents.get(URI).await.unwrap().text, "");
+
+        // Only the first open was sent
+        let (method, _) = next_notification(&mut server).await;
+        assert_eq!(method, "textDocument/didOpen");
+        drop(client);
+        assert!(server.recv().await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,213 @@
+//! Connection lifecycle tracking for the client.
+//!
+//! The LSP specification requires the `initialize` request to be the first
+//! request sent to a server, and forbids anything but the `exit` notification
+//! once the `shutdown` request has been sent. This module enforces those rules
+//! on the client side.
+
+use crate::error::{LspError, ResponseError, Result};
+use crate::types::notification::{Cancel, Exit};
+use crate::types::request::{Initialize, Shutdown};
+use crate::types::{Notification, Request};
+use std::sync::Mutex;
+
+/// The lifecycle state of a client connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClientState {
+    /// The `initialize` request has not been sent yet.
+    Uninitialized,
+    /// The `initialize` request has been sent but not answered yet.
+    Initializing,
+    /// The server has been initialized and accepts requests.
+    Initialized,
+    /// The `shutdown` request has been sent but not answered yet.
+    ShuttingDown,
+    /// The server has acknowledged the `shutdown` request.
+    ShutDown,
+    /// The `exit` notification has been sent.
+    Exited,
+}
+
+/// Tracks the lifecycle state and validates outgoing messages against it.
+pub(crate) struct Lifecycle {
+    state: Mutex<ClientState>,
+}
+
+impl Lifecycle {
+    /// Create a lifecycle in the uninitialized state.
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(ClientState::Uninitialized),
+        }
+    }
+
+    /// Get the current state.
+    pub(crate) fn state(&self) -> ClientState {
+        *self.lock()
+    }
+
+    /// Check that a request may be sent, advancing the state for the
+    /// `initialize` and `shutdown` requests.
+    pub(crate) fn begin_request(&self, method: &str) -> Result<()> {
+        let mut state = self.lock();
+        match *state {
+            ClientState::Uninitialized if method == Initialize::METHOD => {
+                *state = ClientState::Initializing;
+                Ok(())
+            }
+            ClientState::Uninitialized | ClientState::Initializing => {
+                Err(LspError::Protocol(ResponseError::server_not_initialized(
+                    format!("Cannot send '{}' before the server is initialized", method),
+                )))
+            }
+            ClientState::Initialized if method == Initialize::METHOD => Err(LspError::Protocol(
+                ResponseError::invalid_request("The server is already initialized"),
+            )),
+            ClientState::Initialized => {
+                if method == Shutdown::METHOD {
+                    *state = ClientState::ShuttingDown;
+                }
+                Ok(())
+            }
+            ClientState::ShuttingDown | ClientState::ShutDown | ClientState::Exited => {
+                Err(LspError::Protocol(ResponseError::invalid_request(format!(
+                    "Cannot send '{}' after shutdown",
+                    method
+                ))))
+            }
+        }
+    }
+
+    /// Record the outcome of a request, completing or rolling back the
+    /// transition started by [`Lifecycle::begin_request`].
+    pub(crate) fn end_request(&self, method: &str, succeeded: bool) {
+        let mut state = self.lock();
+        *state = match *state {
+            ClientState::Initializing if method == Initialize::METHOD => {
+                if succeeded {
+                    ClientState::Initialized
+                } else {
+                    ClientState::Uninitialized
+                }
+            }
+            ClientState::ShuttingDown if method == Shutdown::METHOD => {
+                if succeeded {
+                    ClientState::ShutDown
+                } else {
+                    ClientState::Initialized
+                }
+            }
+            other => other,
+        };
+    }
+
+    /// Check that a notification may be sent, advancing the state for the
+    /// `exit` notification.
+    pub(crate) fn check_notification(&self, method: &str) -> Result<()> {
+        let mut state = self.lock();
+        match *state {
+            ClientState::Exited => Err(LspError::Protocol(ResponseError::invalid_request(
+                format!("Cannot send '{}' after exit", method),
+            ))),
+            ClientState::ShuttingDown | ClientState::ShutDown
+                if method != Exit::METHOD && method != Cancel::METHOD =>
+            {
+                Err(LspError::Protocol(ResponseError::invalid_request(format!(
+                    "Cannot send '{}' after shutdown",
+                    method
+                ))))
+            }
+            _ => {
+                if method == Exit::METHOD {
+                    *state = ClientState::Exited;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ClientState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::error_codes;
+
+    fn error_code(result: Result<()>) -> i32 {
+        match result {
+            Err(LspError::Protocol(error)) => error.code,
+            other => panic!("Expected protocol error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_requests_before_initialize_are_rejected() {
+        let lifecycle = Lifecycle::new();
+        assert_eq!(
+            error_code(lifecycle.begin_request("textDocument/hover")),
+            error_codes::SERVER_NOT_INITIALIZED
+        );
+        assert_eq!(
+            error_code(lifecycle.begin_request(Shutdown::METHOD)),
+            error_codes::SERVER_NOT_INITIALIZED
+        );
+        assert!(lifecycle.check_notification("textDocument/didOpen").is_ok());
+    }
+
+    #[test]
+    fn test_full_lifecycle() {
+        let lifecycle = Lifecycle::new();
+
+        lifecycle.begin_request(Initialize::METHOD).unwrap();
+        assert_eq!(lifecycle.state(), ClientState::Initializing);
+        assert_eq!(
+            error_code(lifecycle.begin_request("textDocument/hover")),
+            error_codes::SERVER_NOT_INITIALIZED
+        );
+        lifecycle.end_request(Initialize::METHOD, true);
+        assert_eq!(lifecycle.state(), ClientState::Initialized);
+
+        lifecycle.begin_request("textDocument/hover").unwrap();
+        assert_eq!(
+            error_code(lifecycle.begin_request(Initialize::METHOD)),
+            error_codes::INVALID_REQUEST
+        );
+
+        lifecycle.begin_request(Shutdown::METHOD).unwrap();
+        lifecycle.end_request(Shutdown::METHOD, true);
+        assert_eq!(lifecycle.state(), ClientState::ShutDown);
+        assert_eq!(
+            error_code(lifecycle.begin_request("textDocument/hover")),
+            error_codes::INVALID_REQUEST
+        );
+        assert_eq!(
+            error_code(lifecycle.check_notification("textDocument/didOpen")),
+            error_codes::INVALID_REQUEST
+        );
+
+        lifecycle.check_notification(Exit::METHOD).unwrap();
+        assert_eq!(lifecycle.state(), ClientState::Exited);
+        assert_eq!(
+            error_code(lifecycle.check_notification(Exit::METHOD)),
+            error_codes::INVALID_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_failed_transitions_roll_back() {
+        let lifecycle = Lifecycle::new();
+
+        lifecycle.begin_request(Initialize::METHOD).unwrap();
+        lifecycle.end_request(Initialize::METHOD, false);
+        assert_eq!(lifecycle.state(), ClientState::Uninitialized);
+
+        lifecycle.begin_request(Initialize::METHOD).unwrap();
+        lifecycle.end_request(Initialize::METHOD, true);
+        lifecycle.begin_request(Shutdown::METHOD).unwrap();
+        lifecycle.end_request(Shutdown::METHOD, false);
+        assert_eq!(lifecycle.state(), ClientState::Initialized);
+    }
+}
@@ -0,0 +1,172 @@
+//! Spawning and supervising language server child processes.
+
+use super::{Client, ClientConfig, Shared};
+use crate::error::{LspError, Result};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStderr, Command};
+use tokio::sync::{mpsc, oneshot, watch};
+
+/// The number of stderr lines buffered before further lines are dropped.
+const STDERR_BUFFER_LINES: usize = 1024;
+
+/// A language server running as a child process.
+///
+/// The process is killed when the `ServerProcess` is dropped. Its standard
+/// error output is forwarded to the `log` crate and can also be consumed as a
+/// stream of lines through [`ServerProcess::take_stderr`].
+pub struct ServerProcess {
+    id: Option<u32>,
+    status: watch::Receiver<Option<ExitStatus>>,
+    stderr: Option<mpsc::Receiver<String>>,
+    kill_sender: Option<oneshot::Sender<()>>,
+}
+
+impl ServerProcess {
+    /// Get the OS-assigned process ID of the server.
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
+    /// Get the exit status of the server if it has exited.
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        *self.status.borrow()
+    }
+
+    /// Wait for the server to exit and return its exit status.
+    pub async fn wait(&mut self) -> Result<ExitStatus> {
+        let status = self
+            .status
+            .wait_for(Option::is_some)
+            .await
+            .map_err(|_| LspError::Other("Server process monitor stopped".to_string()))?;
+        Ok(status.expect("status is set"))
+    }
+
+    /// Kill the server and wait for it to exit.
+    pub async fn kill(&mut self) -> Result<ExitStatus> {
+        if let Some(kill_sender) = self.kill_sender.take() {
+            let _ = kill_sender.send(());
+        }
+        self.wait().await
+    }
+
+    /// Take the stream of lines the server writes to its standard error.
+    ///
+    /// Lines are buffered until taken; once the buffer is full further lines
+    /// are only logged. Returns `None` if the stream was already taken.
+    pub fn take_stderr(&mut self) -> Option<mpsc::Receiver<String>> {
+        self.stderr.take()
+    }
+}
+
+impl Client {
+    /// Spawn a language server and connect a client to its standard input and
+    /// output.
+    ///
+    /// `envs` are added to the inherited environment, and `cwd` sets the
+    /// working directory of the server if given.
+    pub fn spawn<I, S, E, K, V>(
+        program: impl AsRef<OsStr>,
+        args: I,
+        envs: E,
+        cwd: Option<&Path>,
+    ) -> Result<(Self, ServerProcess)>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+        E: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        let mut command = Command::new(program);
+        command.args(args).envs(envs);
+        if let Some(cwd) = cwd {
+            command.current_dir(cwd);
+        }
+        Self::spawn_command(command, ClientConfig::default())
+    }
+
+    /// Spawn a language server from a prepared command.
+    ///
+    /// The command's standard streams are replaced with pipes. If the server
+    /// exits while requests are pending, they fail with
+    /// [`LspError::Connection`].
+    pub fn spawn_command(
+        mut command: Command,
+        config: ClientConfig,
+    ) -> Result<(Self, ServerProcess)> {
+        command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = command.spawn()?;
+
+        let stdin = child.stdin.take().ok_or_else(|| missing_pipe("stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| missing_pipe("stdout"))?;
+        let stderr = child.stderr.take().ok_or_else(|| missing_pipe("stderr"))?;
+
+        let client = Client::with_config(stdout, stdin, config);
+        let process = monitor(child, stderr, Arc::clone(&client.shared));
+        Ok((client, process))
+    }
+}
+
+fn missing_pipe(name: &str) -> LspError {
+    LspError::Connection(format!("Server process has no {} pipe", name))
+}
+
+/// Spawn the tasks that watch the child's exit and forward its stderr.
+fn monitor(mut child: Child, stderr: ChildStderr, shared: Arc<Shared>) -> ServerProcess {
+    let id = child.id();
+    let (status_sender, status) = watch::channel(None);
+    let (kill_sender, kill_receiver) = oneshot::channel::<()>();
+    tokio::spawn(async move {
+        let result = tokio::select! {
+            result = child.wait() => result,
+            _ = kill_receiver => match child.kill().await {
+                Ok(()) => child.wait().await,
+                Err(e) => Err(e),
+            },
+        };
+        match result {
+            Ok(exit_status) => {
+                log::info!("Language server exited with {}", exit_status);
+                shared
+                    .outgoing
+                    .fail_pending(&format!("Language server exited with {}", exit_status));
+                let _ = status_sender.send(Some(exit_status));
+            }
+            Err(e) => log::error!("Failed to wait for language server: {}", e),
+        }
+    });
+
+    let (line_sender, line_receiver) = mpsc::channel(STDERR_BUFFER_LINES);
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        loop {
+            match lines.next_line().await {
+                Ok(Some(line)) => {
+                    log::debug!(target: "tokio_lsp::server", "{}", line);
+                    let _ = line_sender.try_send(line);
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Failed to read language server stderr: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+
+    ServerProcess {
+        id,
+        status,
+        stderr: Some(line_receiver),
+        kill_sender: Some(kill_sender),
+    }
+}
@@ -0,0 +1,325 @@
+//! Bounded queues for messages received from the server.
+
+use crate::types::{NotificationMessage, RpcMessage};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::Notify;
+
+/// The method of the notification coalesced by [`OverflowPolicy::coalesce_diagnostics`].
+const PUBLISH_DIAGNOSTICS: &str = "textDocument/publishDiagnostics";
+
+type CoalesceKey = Arc<dyn Fn(&NotificationMessage) -> Option<String> + Send + Sync>;
+
+/// What happens when a message arrives while its queue is full.
+///
+/// Requests forwarded by the client are never dropped or coalesced, as the
+/// server waits for their response. If a full queue holds nothing but
+/// requests, the reader waits for room regardless of the policy.
+#[derive(Clone)]
+pub enum OverflowPolicy {
+    /// Stop reading from the server until the consumer catches up.
+    ///
+    /// Responses are not processed while the reader waits, so the consumer
+    /// must not wait on a request while leaving the queue full.
+    Block,
+    /// Drop the oldest queued notification to make room.
+    DropOldest,
+    /// Replace a queued notification of the same method that has the same
+    /// key. Notifications without a key, or arriving while the queue is full
+    /// without a match, make room by dropping the oldest notification.
+    Coalesce(CoalesceKey),
+}
+
+impl OverflowPolicy {
+    /// Coalesce notifications by the key returned from `key`.
+    pub fn coalesce<F>(key: F) -> Self
+    where
+        F: Fn(&NotificationMessage) -> Option<String> + Send + Sync + 'static,
+    {
+        OverflowPolicy::Coalesce(Arc::new(key))
+    }
+
+    /// Keep only the latest `textDocument/publishDiagnostics` per document URI.
+    pub fn coalesce_diagnostics() -> Self {
+        Self::coalesce(|notification| {
+            if notification.method != PUBLISH_DIAGNOSTICS {
+                return None;
+            }
+            let uri = notification.params.as_ref()?.get("uri")?;
+            uri.as_str().map(str::to_string)
+        })
+    }
+}
+
+impl fmt::Debug for OverflowPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OverflowPolicy::Block => write!(f, "Block"),
+            OverflowPolicy::DropOldest => write!(f, "DropOldest"),
+            OverflowPolicy::Coalesce(_) => write!(f, "Coalesce(..)"),
+        }
+    }
+}
+
+impl PartialEq for OverflowPolicy {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (OverflowPolicy::Block, OverflowPolicy::Block) => true,
+            (OverflowPolicy::DropOldest, OverflowPolicy::DropOldest) => true,
+            (OverflowPolicy::Coalesce(a), OverflowPolicy::Coalesce(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for OverflowPolicy {}
+
+struct QueueState {
+    messages: VecDeque<RpcMessage>,
+    /// Set when no more messages will be pushed.
+    closed: bool,
+    /// Cleared when the consumer has gone away.
+    receiving: bool,
+}
+
+/// A single-consumer queue of incoming messages with an overflow policy.
+pub(crate) struct MessageQueue {
+    state: Mutex<QueueState>,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    readable: Notify,
+    writable: Notify,
+    dropped: AtomicU64,
+    /// Counter shared by all queues of a client.
+    total_dropped: Arc<AtomicU64>,
+}
+
+impl MessageQueue {
+    /// Create a queue holding at most `capacity` messages, or any number if `None`.
+    pub(crate) fn new(
+        capacity: Option<usize>,
+        policy: OverflowPolicy,
+        total_dropped: Arc<AtomicU64>,
+    ) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                messages: VecDeque::new(),
+                closed: false,
+                receiving: true,
+            }),
+            capacity: capacity.map(|capacity| capacity.max(1)),
+            policy,
+            readable: Notify::new(),
+            writable: Notify::new(),
+            dropped: AtomicU64::new(0),
+            total_dropped,
+        }
+    }
+
+    /// Add a message to the queue, applying the overflow policy if it is full.
+    ///
+    /// Returns `false` if the consumer has gone away.
+    pub(crate) async fn push(&self, message: RpcMessage) -> bool {
+        loop {
+            {
+                let mut state = self.lock_state();
+                if !state.receiving {
+                    return false;
+                }
+                if self.coalesce(&mut state.messages, &message) {
+                    self.count_dropped();
+                    state.messages.push_back(message);
+                    self.readable.notify_one();
+                    return true;
+                }
+                let full = self
+                    .capacity
+                    .is_some_and(|capacity| state.messages.len() >= capacity);
+                let made_room = full
+                    && !matches!(self.policy, OverflowPolicy::Block)
+                    && remove_oldest_notification(&mut state.messages);
+                if made_room {
+                    self.count_dropped();
+                }
+                if !full || made_room {
+                    state.messages.push_back(message);
+                    self.readable.notify_one();
+                    return true;
+                }
+            }
+            self.writable.notified().await;
+        }
+    }
+
+    /// Take the next message, waiting for one to arrive.
+    ///
+    /// Returns `None` once the queue is closed and empty.
+    pub(crate) async fn recv(&self) -> Option<RpcMessage> {
+        loop {
+            {
+                let mut state = self.lock_state();
+                if let Some(message) = state.messages.pop_front() {
+                    self.writable.notify_one();
+                    return Some(message);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            self.readable.notified().await;
+        }
+    }
+
+    /// Stop accepting messages; the consumer receives those already queued.
+    pub(crate) fn close(&self) {
+        self.lock_state().closed = true;
+        self.readable.notify_one();
+    }
+
+    /// Mark the consumer as gone, discarding queued messages.
+    pub(crate) fn close_receiver(&self) {
+        let mut state = self.lock_state();
+        state.receiving = false;
+        state.messages.clear();
+        self.writable.notify_one();
+    }
+
+    /// Whether the consumer is still receiving messages.
+    pub(crate) fn is_receiving(&self) -> bool {
+        self.lock_state().receiving
+    }
+
+    /// The number of messages dropped or coalesced by this queue.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn lock_state(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn count_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        self.total_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Remove a queued notification that `message` supersedes.
+    fn coalesce(&self, messages: &mut VecDeque<RpcMessage>, message: &RpcMessage) -> bool {
+        let (OverflowPolicy::Coalesce(key), RpcMessage::Notification(notification)) =
+            (&self.policy, message)
+        else {
+            return false;
+        };
+        let Some(new_key) = key(notification) else {
+            return false;
+        };
+        let position = messages.iter().position(|queued| match queued {
+            RpcMessage::Notification(queued) => {
+                queued.method == notification.method
+                    && key(queued).as_deref() == Some(new_key.as_str())
+            }
+            _ => false,
+        });
+        match position {
+            Some(position) => {
+                messages.remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn remove_oldest_notification(messages: &mut VecDeque<RpcMessage>) -> bool {
+    match messages.iter().position(RpcMessage::is_notification) {
+        Some(position) => {
+            messages.remove(position);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn queue(capacity: usize, policy: OverflowPolicy) -> MessageQueue {
+        MessageQueue::new(Some(capacity), policy, Arc::new(AtomicU64::new(0)))
+    }
+
+    fn diagnostics(uri: &str, message: &str) -> RpcMessage {
+        RpcMessage::Notification(NotificationMessage::with_params(
+            PUBLISH_DIAGNOSTICS,
+            json!({"uri": uri, "diagnostics": [], "marker": message}),
+        ))
+    }
+
+    async fn drain(queue: &MessageQueue) -> Vec<serde_json::Value> {
+        queue.close();
+        let mut messages = Vec::new();
+        while let Some(message) = queue.recv().await {
+            messages.push(serde_json::to_value(message).unwrap());
+        }
+        messages
+    }
+
+    fn to_json(message: RpcMessage) -> serde_json::Value {
+        serde_json::to_value(message).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_keeps_requests() {
+        let queue = queue(2, OverflowPolicy::DropOldest);
+        let request = RpcMessage::Request(crate::types::RequestMessage::new(1, "test/request"));
+        assert!(queue.push(request.clone()).await);
+        assert!(queue.push(diagnostics("a", "1")).await);
+        assert!(queue.push(diagnostics("b", "2")).await);
+
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(
+            drain(&queue).await,
+            vec![to_json(request), to_json(diagnostics("b", "2"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_keeps_latest_per_key() {
+        let queue = queue(8, OverflowPolicy::coalesce_diagnostics());
+        queue.push(diagnostics("a", "1")).await;
+        queue.push(diagnostics("b", "2")).await;
+        queue.push(diagnostics("a", "3")).await;
+
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(
+            drain(&queue).await,
+            vec![
+                to_json(diagnostics("b", "2")),
+                to_json(diagnostics("a", "3"))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_block_waits_for_room() {
+        let queue = Arc::new(queue(1, OverflowPolicy::Block));
+        queue.push(diagnostics("a", "1")).await;
+
+        let pusher = {
+            let queue = Arc::clone(&queue);
+            tokio::spawn(async move { queue.push(diagnostics("b", "2")).await })
+        };
+        tokio::task::yield_now().await;
+        assert!(!pusher.is_finished());
+
+        let first = queue.recv().await.unwrap();
+        assert_eq!(to_json(first), to_json(diagnostics("a", "1")));
+        assert!(pusher.await.unwrap());
+        let second = queue.recv().await.unwrap();
+        assert_eq!(to_json(second), to_json(diagnostics("b", "2")));
+        assert_eq!(queue.dropped(), 0);
+    }
+}
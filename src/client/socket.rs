@@ -0,0 +1,284 @@
+//! Connecting to language servers over TCP and Unix domain sockets.
+//!
+//! A client either connects to a server that is already listening, or listens
+//! itself and waits for the server to connect back, as servers started with
+//! `--port` or `--pipe` arguments commonly expect.
+
+use super::{Client, ClientConfig};
+use crate::error::{LspError, Result};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+#[cfg(feature = "websocket")]
+use crate::transport::websocket::{self, WebSocketStream, WebSocketTransport};
+#[cfg(feature = "websocket")]
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// How a socket connection to a language server is established.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectConfig {
+    /// The number of connection attempts before giving up. Retrying covers
+    /// servers that are still starting up when the client connects.
+    pub max_attempts: usize,
+    /// Delay between connection attempts.
+    pub retry_delay: Duration,
+    /// How long a listening client waits for the server to connect back.
+    /// `None` waits indefinitely.
+    pub accept_timeout: Option<Duration>,
+}
+
+impl Default for ConnectConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            retry_delay: Duration::from_millis(200),
+            accept_timeout: None,
+        }
+    }
+}
+
+impl ConnectConfig {
+    /// Make up to `max_attempts` connection attempts, `delay` apart.
+    pub fn with_retries(mut self, max_attempts: usize, delay: Duration) -> Self {
+        self.max_attempts = max_attempts;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Set how long a listening client waits for the server to connect back.
+    pub fn with_accept_timeout(mut self, timeout: Duration) -> Self {
+        self.accept_timeout = Some(timeout);
+        self
+    }
+
+    /// Run `connect` until it succeeds or the attempts are exhausted,
+    /// returning the error of the last attempt.
+    async fn retry<T, F, Fut>(&self, mut connect: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::io::Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match connect().await {
+                Ok(stream) => return Ok(stream),
+                Err(e) if attempt < self.max_attempts => {
+                    log::debug!(
+                        "Connection attempt {} of {} failed: {}",
+                        attempt,
+                        self.max_attempts,
+                        e
+                    );
+                    attempt += 1;
+                    tokio::time::sleep(self.retry_delay).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Wait for `accept` to complete within the accept timeout.
+    async fn accept<T>(&self, accept: impl Future<Output = std::io::Result<T>>) -> Result<T> {
+        let Some(timeout) = self.accept_timeout else {
+            return Ok(accept.await?);
+        };
+        match tokio::time::timeout(timeout, accept).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(LspError::Connection(format!(
+                "Server did not connect within {:?}",
+                timeout
+            ))),
+        }
+    }
+}
+
+impl Client {
+    /// Connect to a language server listening on a TCP address.
+    pub async fn connect_tcp(addr: impl ToSocketAddrs + Clone) -> Result<Self> {
+        Self::connect_tcp_with_config(addr, ConnectConfig::default(), ClientConfig::default()).await
+    }
+
+    /// Connect to a language server listening on a TCP address, retrying
+    /// according to `connect`.
+    pub async fn connect_tcp_with_config(
+        addr: impl ToSocketAddrs + Clone,
+        connect: ConnectConfig,
+        config: ClientConfig,
+    ) -> Result<Self> {
+        let stream = connect.retry(|| TcpStream::connect(addr.clone())).await?;
+        Ok(Self::from_tcp_stream(stream, config))
+    }
+
+    /// Listen on a TCP address for a language server to connect back.
+    ///
+    /// Bind to port 0 to let the OS pick a free port, and pass
+    /// [`TcpServerListener::local_addr`] to the server.
+    pub async fn listen_tcp(addr: impl ToSocketAddrs) -> Result<TcpServerListener> {
+        Ok(TcpServerListener {
+            listener: TcpListener::bind(addr).await?,
+        })
+    }
+
+    fn from_tcp_stream(stream: TcpStream, config: ClientConfig) -> Self {
+        if let Err(e) = stream.set_nodelay(true) {
+            log::debug!("Failed to disable Nagle's algorithm: {}", e);
+        }
+        let (reader, writer) = stream.into_split();
+        Client::with_config(reader, writer, config)
+    }
+}
+
+/// A TCP listener waiting for a language server to connect back.
+pub struct TcpServerListener {
+    listener: TcpListener,
+}
+
+impl TcpServerListener {
+    /// Get the address the listener is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Wait for the server to connect and create a client for the connection.
+    pub async fn accept(&self, connect: &ConnectConfig, config: ClientConfig) -> Result<Client> {
+        let (stream, peer) = connect.accept(self.listener.accept()).await?;
+        log::debug!("Language server connected from {}", peer);
+        Ok(Client::from_tcp_stream(stream, config))
+    }
+}
+
+#[cfg(unix)]
+impl Client {
+    /// Connect to a language server listening on a Unix domain socket.
+    pub async fn connect_unix(path: impl AsRef<Path>) -> Result<Self> {
+        Self::connect_unix_with_config(path, ConnectConfig::default(), ClientConfig::default())
+            .await
+    }
+
+    /// Connect to a language server listening on a Unix domain socket,
+    /// retrying according to `connect`.
+    pub async fn connect_unix_with_config(
+        path: impl AsRef<Path>,
+        connect: ConnectConfig,
+        config: ClientConfig,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let stream = connect.retry(|| UnixStream::connect(path)).await?;
+        let (reader, writer) = stream.into_split();
+        Ok(Client::with_config(reader, writer, config))
+    }
+
+    /// Listen on a Unix domain socket for a language server to connect back.
+    ///
+    /// The socket file is removed when the listener is dropped.
+    pub fn listen_unix(path: impl AsRef<Path>) -> Result<UnixServerListener> {
+        let path = path.as_ref().to_path_buf();
+        Ok(UnixServerListener {
+            listener: UnixListener::bind(&path)?,
+            path,
+        })
+    }
+}
+
+/// A Unix domain socket listener waiting for a language server to connect
+/// back.
+#[cfg(unix)]
+pub struct UnixServerListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixServerListener {
+    /// Get the path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Wait for the server to connect and create a client for the connection.
+    pub async fn accept(&self, connect: &ConnectConfig, config: ClientConfig) -> Result<Client> {
+        let (stream, _) = connect.accept(self.listener.accept()).await?;
+        let (reader, writer) = stream.into_split();
+        Ok(Client::with_config(reader, writer, config))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixServerListener {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl Client {
+    /// Create a client for an established WebSocket connection, sending one
+    /// message per text frame.
+    pub fn from_websocket<S>(stream: WebSocketStream<S>, config: ClientConfig) -> Self
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        Client::with_transport(WebSocketTransport::new(stream), config)
+    }
+
+    /// Connect to a language server behind a WebSocket URL such as
+    /// `ws://localhost:8080/lsp`.
+    pub async fn connect_websocket(url: &str) -> Result<Self> {
+        Self::connect_websocket_with_config(url, ConnectConfig::default(), ClientConfig::default())
+            .await
+    }
+
+    /// Connect to a language server behind a WebSocket URL, retrying
+    /// according to `connect`.
+    pub async fn connect_websocket_with_config(
+        url: &str,
+        connect: ConnectConfig,
+        config: ClientConfig,
+    ) -> Result<Self> {
+        let (stream, _) = connect
+            .retry(|| async {
+                tokio_tungstenite::connect_async(url)
+                    .await
+                    .map_err(websocket::io_error)
+            })
+            .await?;
+        Ok(Self::from_websocket(stream, config))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_retry_returns_last_error() {
+        let connect = ConnectConfig::default().with_retries(3, Duration::ZERO);
+        let mut attempts = 0;
+        let result: Result<()> = connect
+            .retry(|| {
+                attempts += 1;
+                let attempt = attempts;
+                async move { Err(std::io::Error::other(format!("attempt {}", attempt))) }
+            })
+            .await;
+
+        assert_eq!(attempts, 3);
+        assert!(matches!(result, Err(LspError::Io(e)) if e.to_string() == "attempt 3"));
+    }
+
+    #[tokio::test]
+    async fn test_accept_timeout() {
+        let connect = ConnectConfig::default().with_accept_timeout(Duration::from_millis(10));
+        let result = connect
+            .accept(std::future::pending::<std::io::Result<()>>())
+            .await;
+        assert!(matches!(result, Err(LspError::Connection(_))));
+    }
+}
@@ -0,0 +1,182 @@
+//! Typed subscriptions to notifications sent by the server.
+
+use super::queue::{MessageQueue, OverflowPolicy};
+use crate::types::{Notification, NotificationMessage, RpcMessage};
+use futures::future::BoxFuture;
+use futures::{FutureExt, Stream};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+/// Subscribers to notifications, keyed by method.
+pub(crate) struct Subscriptions {
+    subscribers: Mutex<Subscribers>,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+    total_dropped: Arc<AtomicU64>,
+}
+
+#[derive(Default)]
+struct Subscribers {
+    queues: HashMap<String, Vec<Arc<MessageQueue>>>,
+    /// Set once the subscriptions were closed, after which new subscribers
+    /// get a closed queue.
+    closed: bool,
+}
+
+impl Subscriptions {
+    /// Create an empty registry whose subscribers queue up to `capacity`
+    /// notifications each.
+    pub(crate) fn new(
+        capacity: Option<usize>,
+        policy: OverflowPolicy,
+        total_dropped: Arc<AtomicU64>,
+    ) -> Self {
+        Self {
+            subscribers: Mutex::new(Subscribers::default()),
+            capacity,
+            policy,
+            total_dropped,
+        }
+    }
+
+    /// Add a subscriber for notifications of type `N`.
+    ///
+    /// Once the subscriptions were closed, the subscription ends right away.
+    pub(crate) fn subscribe<N: Notification>(&self) -> Subscription<N> {
+        let queue = Arc::new(MessageQueue::new(
+            self.capacity,
+            self.policy.clone(),
+            Arc::clone(&self.total_dropped),
+        ));
+        let mut subscribers = self.lock_subscribers();
+        if subscribers.closed {
+            queue.close();
+        } else {
+            subscribers
+                .queues
+                .entry(N::METHOD.to_string())
+                .or_default()
+                .push(Arc::clone(&queue));
+        }
+        drop(subscribers);
+        Subscription {
+            queue,
+            next: None,
+            _notification: PhantomData,
+        }
+    }
+
+    /// Deliver a notification to every subscriber of its method.
+    ///
+    /// Returns the notification if nobody is subscribed to it, so it can be
+    /// passed on to the catch-all stream.
+    pub(crate) async fn publish(
+        &self,
+        notification: NotificationMessage,
+    ) -> Option<NotificationMessage> {
+        let queues = self
+            .lock_subscribers()
+            .queues
+            .get(&notification.method)
+            .cloned();
+        let Some(queues) = queues else {
+            return Some(notification);
+        };
+
+        let mut delivered = false;
+        let mut gone = false;
+        for queue in &queues {
+            let message = RpcMessage::Notification(notification.clone());
+            if queue.push(message).await {
+                delivered = true;
+            } else {
+                gone = true;
+            }
+        }
+
+        if gone {
+            let mut subscribers = self.lock_subscribers();
+            if let Some(queues) = subscribers.queues.get_mut(&notification.method) {
+                queues.retain(|queue| queue.is_receiving());
+                if queues.is_empty() {
+                    subscribers.queues.remove(&notification.method);
+                }
+            }
+        }
+        // Without a live subscriber, the notification goes to the catch-all
+        (!delivered).then_some(notification)
+    }
+
+    /// Close every subscription, ending their streams once drained, and end
+    /// any later subscription right away.
+    pub(crate) fn close(&self) {
+        let queues = {
+            let mut subscribers = self.lock_subscribers();
+            subscribers.closed = true;
+            std::mem::take(&mut subscribers.queues)
+        };
+        for queue in queues.values().flatten() {
+            queue.close();
+        }
+    }
+
+    fn lock_subscribers(&self) -> MutexGuard<'_, Subscribers> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A stream of the parameters of notifications of type `N`.
+///
+/// Created by [`Client::subscribe`](crate::Client::subscribe). The stream
+/// ends when the connection to the server is closed. Notifications whose
+/// parameters do not match `N::Params` are logged and skipped.
+pub struct Subscription<N: Notification> {
+    queue: Arc<MessageQueue>,
+    next: Option<BoxFuture<'static, Option<RpcMessage>>>,
+    _notification: PhantomData<fn() -> N>,
+}
+
+impl<N: Notification> Subscription<N> {
+    /// The number of notifications this subscription dropped or coalesced
+    /// because it was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped()
+    }
+}
+
+impl<N: Notification> Stream for Subscription<N> {
+    type Item = N::Params;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let queue = Arc::clone(&self.queue);
+            let next = self
+                .next
+                .get_or_insert_with(|| async move { queue.recv().await }.boxed());
+            let message = match next.poll_unpin(cx) {
+                Poll::Ready(message) => message,
+                Poll::Pending => return Poll::Pending,
+            };
+            self.next = None;
+
+            let Some(RpcMessage::Notification(notification)) = message else {
+                return Poll::Ready(None);
+            };
+            let params = notification.params.unwrap_or(serde_json::Value::Null);
+            match serde_json::from_value(params) {
+                Ok(params) => return Poll::Ready(Some(params)),
+                Err(e) => log::warn!("Invalid parameters for {}: {}", N::METHOD, e),
+            }
+        }
+    }
+}
+
+impl<N: Notification> Drop for Subscription<N> {
+    fn drop(&mut self) {
+        self.queue.close_receiver();
+    }
+}
@@ -3,7 +3,9 @@
 //! This module defines all error types that can occur during LSP communication,
 //! from transport-level errors to protocol-level failures.
 
+use crate::types::{DocumentUri, Id};
 use std::fmt;
+use std::time::Duration;
 use thiserror::Error;
 
 /// A specialized Result type for LSP operations.
@@ -26,15 +28,26 @@ pub enum LspError {
 
     /// Transport protocol errors (malformed headers, etc.)
     #[error("Transport error: {0}")]
-    Transport(String),
+    Transport(#[from] TransportError),
+
+    /// Open-document bookkeeping errors
+    #[error("Document error: {0}")]
+    Document(#[from] DocumentError),
 
     /// Connection errors
     #[error("Connection error: {0}")]
     Connection(String),
 
-    /// Request timeout
-    #[error("Request timeout")]
-    Timeout,
+    /// A request did not receive a response within its timeout
+    #[error("Request '{method}' (id {id}) timed out after {elapsed:?}")]
+    Timeout {
+        /// The method of the request that timed out.
+        method: String,
+        /// The ID of the request that timed out.
+        id: Id,
+        /// How long the client waited before giving up.
+        elapsed: Duration,
+    },
 
     /// Server initialization failed
     #[error("Server initialization failed: {0}")]
@@ -45,6 +58,83 @@ pub enum LspError {
     Other(String),
 }
 
+impl LspError {
+    /// Convert the error into the error sent in response to a request.
+    /// Protocol errors are passed on as is, anything else is an internal error.
+    pub(crate) fn into_response_error(self) -> ResponseError {
+        match self {
+            LspError::Protocol(error) => error,
+            other => ResponseError::internal_error(other.to_string()),
+        }
+    }
+}
+
+/// A message rejected by the transport.
+///
+/// The transport skips the rest of a rejected message where it can, so reading
+/// may continue with the next one.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TransportError {
+    /// A header line was longer than the configured limit.
+    #[error("Header line exceeds {limit} bytes")]
+    HeaderLineTooLong {
+        /// The maximum length of a header line in bytes.
+        limit: usize,
+    },
+
+    /// A message had more header fields than the configured limit.
+    #[error("Message has more than {limit} header fields")]
+    TooManyHeaders {
+        /// The maximum number of header fields.
+        limit: usize,
+    },
+
+    /// A message declared a Content-Length larger than the configured limit.
+    #[error("Content-Length {length} exceeds the limit of {limit} bytes")]
+    ContentTooLarge {
+        /// The declared length of the content.
+        length: usize,
+        /// The maximum length of the content in bytes.
+        limit: usize,
+    },
+
+    /// Malformed headers or content
+    #[error("{0}")]
+    Malformed(String),
+}
+
+/// A document operation rejected by a
+/// [`DocumentStore`](crate::client::DocumentStore).
+///
+/// Nothing is sent to the server for a rejected operation.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DocumentError {
+    /// The document is already open.
+    #[error("Document {uri} is already open")]
+    AlreadyOpen {
+        /// The URI of the document.
+        uri: DocumentUri,
+    },
+
+    /// The document is not open.
+    #[error("Document {uri} is not open")]
+    NotOpen {
+        /// The URI of the document.
+        uri: DocumentUri,
+    },
+
+    /// A change did not increase the version of the document.
+    #[error("Version {version} of document {uri} does not follow version {current}")]
+    OutOfOrderVersion {
+        /// The URI of the document.
+        uri: DocumentUri,
+        /// The version of the document before the change.
+        current: i32,
+        /// The version the change would have given the document.
+        version: i32,
+    },
+}
+
 /// LSP ResponseError as defined by the JSON-RPC specification.
 /// This corresponds to the error object in LSP response messages.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
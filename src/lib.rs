@@ -13,6 +13,7 @@
 //! - Type-safe message handling with serde
 //! - Comprehensive error handling
 //! - Transport layer abstraction
+//! - A server role for building language servers on the same types
 //!
 //! ## Example
 //!
@@ -35,15 +36,22 @@
 
 pub mod client;
 pub mod error;
+mod rpc;
+pub mod server;
+pub mod supervisor;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod transport;
 pub mod types;
 
-pub use client::Client;
-pub use error::{LspError, Result};
+pub use client::{Client, ClientConfig, ClientState, DispatchMode, ServerProcess, Subscription};
+pub use error::{DocumentError, LspError, Result, TransportError};
+pub use server::{ClientHandle, Server, ServerBuilder};
+pub use supervisor::Supervisor;
 
 /// Re-export commonly used types
 pub mod prelude {
-    pub use crate::client::Client;
+    pub use crate::client::{Client, ClientConfig, ClientState, ServerProcess, Subscription};
     pub use crate::error::{LspError, Result};
     pub use crate::types::*;
 }
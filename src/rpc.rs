@@ -0,0 +1,20 @@
+//! JSON-RPC plumbing shared by the client and server roles.
+//!
+//! Both roles send requests to their peer and wait for the responses, and
+//! both answer the peer's requests with typed handlers. The bookkeeping for
+//! either lives here, so that the two roles behave the same.
+
+mod handlers;
+mod requests;
+
+pub(crate) use handlers::{method_not_found, NotificationHandlers, RequestHandlers};
+pub(crate) use requests::{into_result, Outgoing};
+pub use requests::{RequestCanceller, RequestHandle};
+
+/// Serialize typed parameters, omitting them entirely when they are `null`.
+pub(crate) fn to_params<T: serde::Serialize>(
+    params: T,
+) -> crate::error::Result<Option<serde_json::Value>> {
+    let params = serde_json::to_value(params)?;
+    Ok((!params.is_null()).then_some(params))
+}
@@ -0,0 +1,190 @@
+//! Registries of typed handlers for requests and notifications from the peer.
+//!
+//! Handlers receive the parameters of a message and a context of type `C`,
+//! which is `()` for the client and a [`ClientHandle`](crate::ClientHandle)
+//! for the server.
+
+use crate::error::{LspError, ResponseError, Result};
+use crate::types::{Notification, Request, RequestMessage, ResponseMessage};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, RwLock};
+
+type HandlerResult = std::result::Result<serde_json::Value, ResponseError>;
+
+/// A request handler with its parameter and result types erased.
+type ErasedRequestHandler<C> =
+    Arc<dyn Fn(Option<serde_json::Value>, C) -> BoxFuture<'static, HandlerResult> + Send + Sync>;
+
+/// A notification handler with its parameter type erased.
+type ErasedNotificationHandler<C> =
+    Arc<dyn Fn(Option<serde_json::Value>, C) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// Registered request handlers, keyed by method.
+pub(crate) struct RequestHandlers<C> {
+    handlers: RwLock<HashMap<String, ErasedRequestHandler<C>>>,
+}
+
+impl<C> Default for RequestHandlers<C> {
+    fn default() -> Self {
+        Self {
+            handlers: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl<C: Send + 'static> RequestHandlers<C> {
+    /// Register a handler for `R`, replacing any previous handler.
+    pub(crate) fn insert<R, F, Fut>(&self, handler: F)
+    where
+        R: Request,
+        F: Fn(R::Params, C) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R::Result>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let erased: ErasedRequestHandler<C> = Arc::new(move |params, context| {
+            let handler = Arc::clone(&handler);
+            async move {
+                let params = serde_json::from_value(params.unwrap_or(serde_json::Value::Null))
+                    .map_err(|e| {
+                        ResponseError::invalid_params(format!(
+                            "Invalid parameters for {}: {}",
+                            R::METHOD,
+                            e
+                        ))
+                    })?;
+                let result = handler(params, context)
+                    .await
+                    .map_err(LspError::into_response_error)?;
+                serde_json::to_value(result)
+                    .map_err(|e| ResponseError::internal_error(e.to_string()))
+            }
+            .boxed()
+        });
+        self.handlers
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(R::METHOD.to_string(), erased);
+    }
+
+    /// Remove the handler for `method`. Returns `false` if none was registered.
+    pub(crate) fn remove(&self, method: &str) -> bool {
+        self.handlers
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(method)
+            .is_some()
+    }
+
+    /// Check whether a handler is registered for `method`.
+    pub(crate) fn contains(&self, method: &str) -> bool {
+        self.handlers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(method)
+    }
+
+    /// Get a future answering `request`, or `None` if no handler is registered
+    /// for its method.
+    pub(crate) fn dispatch(
+        &self,
+        request: RequestMessage,
+        context: C,
+    ) -> Option<impl Future<Output = ResponseMessage> + Send + 'static> {
+        let handler = self
+            .handlers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&request.method)
+            .cloned()?;
+        Some(async move {
+            let method = request.method;
+            let result = AssertUnwindSafe(handler(request.params, context))
+                .catch_unwind()
+                .await
+                .unwrap_or_else(|_| {
+                    log::error!("Handler for {} panicked", method);
+                    Err(ResponseError::internal_error(format!(
+                        "Handler for {} panicked",
+                        method
+                    )))
+                });
+            match result {
+                Ok(result) => ResponseMessage::success(request.id, result),
+                Err(error) => ResponseMessage::error(Some(request.id), error),
+            }
+        })
+    }
+}
+
+/// Registered notification handlers, keyed by method.
+pub(crate) struct NotificationHandlers<C> {
+    handlers: HashMap<String, ErasedNotificationHandler<C>>,
+}
+
+impl<C> Default for NotificationHandlers<C> {
+    fn default() -> Self {
+        Self {
+            handlers: HashMap::new(),
+        }
+    }
+}
+
+impl<C: Send + 'static> NotificationHandlers<C> {
+    /// Register a handler for `N`, replacing any previous handler.
+    pub(crate) fn insert<N, F, Fut>(&mut self, handler: F)
+    where
+        N: Notification,
+        F: Fn(N::Params, C) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let erased: ErasedNotificationHandler<C> = Arc::new(move |params, context| {
+            let handler = Arc::clone(&handler);
+            async move {
+                let params = params.unwrap_or(serde_json::Value::Null);
+                let result = match serde_json::from_value(params) {
+                    Ok(params) => handler(params, context).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = result {
+                    log::warn!("Failed to handle {} notification: {}", N::METHOD, e);
+                }
+            }
+            .boxed()
+        });
+        self.handlers.insert(N::METHOD.to_string(), erased);
+    }
+
+    /// Get a future handling a notification, or `None` if no handler is
+    /// registered for its method.
+    pub(crate) fn dispatch(
+        &self,
+        method: &str,
+        params: Option<serde_json::Value>,
+        context: C,
+    ) -> Option<BoxFuture<'static, ()>> {
+        let handler = self.handlers.get(method)?;
+        let method = method.to_string();
+        let handled = AssertUnwindSafe(handler(params, context)).catch_unwind();
+        Some(
+            async move {
+                if handled.await.is_err() {
+                    log::error!("Handler for {} panicked", method);
+                }
+            }
+            .boxed(),
+        )
+    }
+}
+
+/// The response sent for requests without a registered handler.
+pub(crate) fn method_not_found(request: &RequestMessage) -> ResponseMessage {
+    ResponseMessage::error(
+        Some(request.id.clone()),
+        ResponseError::method_not_found(format!("Unhandled method {}", request.method)),
+    )
+}
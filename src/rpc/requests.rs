@@ -0,0 +1,321 @@
+//! Requests sent to the peer and awaiting its response.
+
+use crate::error::{LspError, ResponseError, Result};
+use crate::transport::MessageSender;
+use crate::types::notification::Cancel;
+use crate::types::{
+    CancelParams, Id, Notification, NotificationMessage, Request, RequestMessage, ResponseMessage,
+    RpcMessage,
+};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+/// Called with the method of each request once it completes, and whether
+/// it succeeded.
+type CompletionHook = Box<dyn Fn(&str, bool) + Send + Sync>;
+
+/// A request waiting for its response.
+struct PendingRequest {
+    method: String,
+    sender: oneshot::Sender<Result<ResponseMessage>>,
+}
+
+/// The sending half of a connection, and the requests sent through it that
+/// are waiting for a response.
+pub(crate) struct Outgoing {
+    /// The sending half of the transport, shared by all senders.
+    sender: Mutex<Box<dyn MessageSender>>,
+    /// Requests waiting for responses.
+    pending: std::sync::Mutex<HashMap<Id, PendingRequest>>,
+    /// Counter for generating unique request IDs.
+    request_id_counter: AtomicI64,
+    on_complete: CompletionHook,
+    /// Channel used by request handles to cancel their request.
+    cancel_sender: mpsc::UnboundedSender<Id>,
+}
+
+impl Outgoing {
+    /// Create the outgoing side of a connection. `on_complete` is called with
+    /// the method of each request once it completes, and whether it
+    /// succeeded.
+    pub(crate) fn new(
+        sender: Box<dyn MessageSender>,
+        on_complete: impl Fn(&str, bool) + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        let (cancel_sender, mut cancel_receiver) = mpsc::unbounded_channel::<Id>();
+        let outgoing = Arc::new(Self {
+            sender: Mutex::new(sender),
+            pending: std::sync::Mutex::new(HashMap::new()),
+            request_id_counter: AtomicI64::new(1),
+            on_complete: Box::new(on_complete),
+            cancel_sender,
+        });
+
+        // Cancel requests whose handles were cancelled or dropped, until the
+        // connection is gone
+        let weak = Arc::downgrade(&outgoing);
+        tokio::spawn(async move {
+            while let Some(id) = cancel_receiver.recv().await {
+                let Some(outgoing) = weak.upgrade() else {
+                    break;
+                };
+                outgoing.cancel(&id).await;
+            }
+        });
+        outgoing
+    }
+
+    /// Write a message through the sending half of the transport.
+    pub(crate) async fn write(&self, message: &RpcMessage) -> Result<()> {
+        self.sender.lock().await.send(message).await
+    }
+
+    /// Generate a new unique request ID.
+    pub(crate) fn next_id(&self) -> Id {
+        Id::Number(self.request_id_counter.fetch_add(1, Ordering::SeqCst))
+    }
+
+    /// Send a request and return a handle to its pending response.
+    pub(crate) async fn start(
+        &self,
+        method: String,
+        params: Option<serde_json::Value>,
+    ) -> Result<RequestHandle> {
+        let id = self.next_id();
+        let request = match params {
+            Some(params) => RequestMessage::with_params(id.clone(), method.clone(), params),
+            None => RequestMessage::new(id.clone(), method.clone()),
+        };
+
+        let (response_sender, response_receiver) = oneshot::channel();
+        self.lock_pending().insert(
+            id.clone(),
+            PendingRequest {
+                method: method.clone(),
+                sender: response_sender,
+            },
+        );
+        if let Err(e) = self.write(&RpcMessage::Request(request)).await {
+            if let Some(pending_request) = self.lock_pending().remove(&id) {
+                (self.on_complete)(&pending_request.method, false);
+            }
+            return Err(e);
+        }
+
+        Ok(RequestHandle {
+            canceller: RequestCanceller {
+                id: id.clone(),
+                sender: self.cancel_sender.clone(),
+            },
+            id,
+            method,
+            receiver: response_receiver,
+            completed: false,
+        })
+    }
+
+    /// Send a request and wait for the response, cancelling the request if
+    /// no response arrives within `timeout`.
+    pub(crate) async fn send_request(
+        &self,
+        method: String,
+        params: Option<serde_json::Value>,
+        timeout: Option<Duration>,
+    ) -> Result<ResponseMessage> {
+        let started = Instant::now();
+        let mut handle = self.start(method, params).await?;
+
+        let Some(timeout) = timeout else {
+            return handle.await;
+        };
+        match tokio::time::timeout(timeout, &mut handle).await {
+            Ok(response) => response,
+            Err(_) => {
+                let id = handle.id().clone();
+                self.cancel(&id).await;
+                Err(LspError::Timeout {
+                    method: handle.method().to_string(),
+                    id,
+                    elapsed: started.elapsed(),
+                })
+            }
+        }
+    }
+
+    /// Complete a pending request with the given result.
+    /// Returns `false` if no request with this ID was pending.
+    pub(crate) fn complete(&self, id: &Id, result: Result<ResponseMessage>) -> bool {
+        let Some(pending_request) = self.lock_pending().remove(id) else {
+            return false;
+        };
+        let succeeded = matches!(&result, Ok(response) if !response.is_error());
+        (self.on_complete)(&pending_request.method, succeeded);
+        if pending_request.sender.send(result).is_err() {
+            log::debug!("Caller of request {} is no longer waiting", id);
+        }
+        true
+    }
+
+    /// Fail every pending request with a connection error.
+    pub(crate) fn fail_pending(&self, reason: &str) {
+        let failed: Vec<_> = self.lock_pending().drain().collect();
+        for (_, pending_request) in failed {
+            (self.on_complete)(&pending_request.method, false);
+            let _ = pending_request
+                .sender
+                .send(Err(LspError::Connection(reason.to_string())));
+        }
+    }
+
+    /// Complete a pending request as cancelled and notify the peer.
+    pub(crate) async fn cancel(&self, id: &Id) {
+        if self.complete(id, Err(request_cancelled(id))) {
+            let result = match cancel_request_message(id) {
+                Ok(message) => self.write(&message).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                log::warn!("Failed to cancel request {}: {}", id, e);
+            }
+        }
+    }
+
+    /// Get the IDs of the pending requests.
+    pub(crate) fn pending_ids(&self) -> Vec<Id> {
+        self.lock_pending().keys().cloned().collect()
+    }
+
+    /// Get the number of pending requests.
+    pub(crate) fn pending_count(&self) -> usize {
+        self.lock_pending().len()
+    }
+
+    fn lock_pending(&self) -> std::sync::MutexGuard<'_, HashMap<Id, PendingRequest>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Get the typed result of a response, returning an error response as
+/// [`LspError::Protocol`].
+pub(crate) fn into_result<Q: Request>(response: ResponseMessage) -> Result<Q::Result> {
+    if let Some(error) = response.error {
+        return Err(LspError::Protocol(error));
+    }
+    let result = response.result.unwrap_or(serde_json::Value::Null);
+    Ok(serde_json::from_value(result)?)
+}
+
+/// A request that has been sent to the server and is awaiting its response.
+///
+/// The handle is a future resolving to the server's response. Cancelling it,
+/// either explicitly through [`RequestHandle::cancel`] or a
+/// [`RequestCanceller`], or implicitly by dropping it before the response
+/// arrives, sends `$/cancelRequest` to the server.
+pub struct RequestHandle {
+    id: Id,
+    method: String,
+    receiver: oneshot::Receiver<Result<ResponseMessage>>,
+    canceller: RequestCanceller,
+    completed: bool,
+}
+
+impl RequestHandle {
+    /// Get the ID of the request.
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
+    /// Get the method of the request.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Get a canceller that can cancel this request from elsewhere.
+    pub fn canceller(&self) -> RequestCanceller {
+        self.canceller.clone()
+    }
+
+    /// Cancel the request. Awaiting the handle afterwards yields an
+    /// [`LspError::Protocol`] error with the `REQUEST_CANCELLED` code.
+    pub fn cancel(&self) {
+        self.canceller.cancel();
+    }
+}
+
+impl Future for RequestHandle {
+    type Output = Result<ResponseMessage>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = match Pin::new(&mut self.receiver).poll(cx) {
+            Poll::Ready(Ok(result)) => result,
+            Poll::Ready(Err(_)) => Err(LspError::Other("Response receiver dropped".to_string())),
+            Poll::Pending => return Poll::Pending,
+        };
+        self.completed = true;
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for RequestHandle {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.canceller.cancel();
+        }
+    }
+}
+
+/// Cancels an in-flight request, notifying the server with `$/cancelRequest`.
+///
+/// Cancelling a request that already completed has no effect.
+#[derive(Clone)]
+pub struct RequestCanceller {
+    id: Id,
+    sender: mpsc::UnboundedSender<Id>,
+}
+
+impl RequestCanceller {
+    /// Cancel the request.
+    pub fn cancel(&self) {
+        // The cancellation task only stops once the connection is gone, in
+        // which case there is nothing left to cancel.
+        let _ = self.sender.send(self.id.clone());
+    }
+}
+
+/// Build the error returned to callers of a cancelled request.
+fn request_cancelled(id: &Id) -> LspError {
+    LspError::Protocol(ResponseError::request_cancelled(format!(
+        "Request {} was cancelled",
+        id
+    )))
+}
+
+/// Build the `$/cancelRequest` notification for the given request ID.
+fn cancel_request_message(id: &Id) -> Result<RpcMessage> {
+    let params = serde_json::to_value(CancelParams { id: id.clone() })?;
+    Ok(RpcMessage::Notification(NotificationMessage::with_params(
+        Cancel::METHOD,
+        params,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_request_struct() {
+        let (sender, _receiver) = oneshot::channel();
+        let _pending = PendingRequest {
+            method: "test/method".to_string(),
+            sender,
+        };
+        // Just test that the struct can be created
+    }
+}
@@ -0,0 +1,889 @@
+//! The server role, for building language servers on the same types.
+//!
+//! A [`Server`] reads requests and notifications from a client, usually over
+//! standard input and output, and dispatches them to async handlers
+//! registered on a [`ServerBuilder`]. It follows the LSP lifecycle: requests
+//! before `initialize` has succeeded and the client has sent `initialized`
+//! are answered with `SERVER_NOT_INITIALIZED`, requests after `shutdown` with
+//! `INVALID_REQUEST`, and serving ends with `exit`.
+//!
+//! Handlers receive a [`ClientHandle`] to send notifications and requests
+//! back to the client.
+
+use crate::client::DEFAULT_REQUEST_TIMEOUT;
+use crate::error::{LspError, ResponseError, Result};
+use crate::rpc::{
+    into_result, method_not_found, to_params, NotificationHandlers, Outgoing, RequestHandlers,
+};
+use crate::transport::{MessageReceiver, MessageTransport, Transport};
+use crate::types::notification::{Cancel, Exit, Initialized};
+use crate::types::request::{Initialize, Shutdown};
+use crate::types::{
+    CancelParams, Id, InitializeResult, Notification, NotificationMessage, Request, RequestMessage,
+    ResponseMessage, RpcMessage, ServerCapabilities, ServerInfo,
+};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::AbortHandle;
+
+/// Declares the capabilities and handlers of a [`Server`].
+pub struct ServerBuilder {
+    capabilities: ServerCapabilities,
+    server_info: Option<ServerInfo>,
+    request_timeout: Option<Duration>,
+    handlers: Handlers,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self {
+            capabilities: ServerCapabilities {
+                text_document_sync: None,
+                completion_provider: None,
+                hover_provider: None,
+            },
+            server_info: None,
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            handlers: Handlers::default(),
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Create a builder for a server without capabilities or handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the capabilities sent in response to `initialize`.
+    pub fn with_capabilities(mut self, capabilities: ServerCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    /// Set the server information sent in response to `initialize`.
+    pub fn with_server_info(mut self, name: impl Into<String>, version: Option<String>) -> Self {
+        self.server_info = Some(ServerInfo {
+            name: name.into(),
+            version,
+        });
+        self
+    }
+
+    /// Set how long requests sent to the client wait for a response.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Wait indefinitely for responses to requests sent to the client.
+    pub fn without_request_timeout(mut self) -> Self {
+        self.request_timeout = None;
+        self
+    }
+
+    /// Register a handler answering requests of type `R`.
+    ///
+    /// Requests are handled concurrently, each on its own task, once the
+    /// notifications received before them have been handled. A request the
+    /// client cancels with `$/cancelRequest` has its handler aborted. Errors
+    /// the handler returns are sent back to the client: an
+    /// [`LspError::Protocol`] error is sent as is, any other error or a panic
+    /// becomes an `INTERNAL_ERROR` response.
+    ///
+    /// Handlers for `initialize` and `shutdown` replace the default replies,
+    /// which send the configured capabilities and `null` respectively.
+    /// The server only counts as initialized once `initialize` is answered
+    /// successfully, so a client may retry after an error response.
+    /// Requests without a handler are answered with `METHOD_NOT_FOUND`.
+    pub fn on_request<R, F, Fut>(self, handler: F) -> Self
+    where
+        R: Request,
+        F: Fn(R::Params, ClientHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R::Result>> + Send + 'static,
+    {
+        self.handlers.requests.insert::<R, F, Fut>(handler);
+        self
+    }
+
+    /// Register a handler for notifications of type `N`.
+    ///
+    /// Notifications are handled one at a time, in the order they arrive, so
+    /// document changes are applied in sequence. Requests received after a
+    /// notification wait for its handler to complete. Errors are logged.
+    pub fn on_notification<N, F, Fut>(mut self, handler: F) -> Self
+    where
+        N: Notification,
+        F: Fn(N::Params, ClientHandle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.handlers.notifications.insert::<N, F, Fut>(handler);
+        self
+    }
+
+    /// Build the server.
+    pub fn build(self) -> Server {
+        Server {
+            initialize_result: InitializeResult {
+                capabilities: self.capabilities,
+                server_info: self.server_info,
+            },
+            request_timeout: self.request_timeout,
+            handlers: Arc::new(self.handlers),
+        }
+    }
+}
+
+/// The handlers registered on a [`ServerBuilder`].
+#[derive(Default)]
+struct Handlers {
+    requests: RequestHandlers<ClientHandle>,
+    notifications: NotificationHandlers<ClientHandle>,
+}
+
+/// A language server dispatching client messages to registered handlers.
+///
+/// ```no_run
+/// use tokio_lsp::server::Server;
+/// use tokio_lsp::types::notification::{DidOpenTextDocument, PublishDiagnostics};
+/// use tokio_lsp::types::PublishDiagnosticsParams;
+///
+/// # #[tokio::main]
+/// # async fn main() -> tokio_lsp::Result<()> {
+/// Server::builder()
+///     .with_server_info("example-server", Some("0.1.0".to_string()))
+///     .on_notification::<DidOpenTextDocument, _, _>(|params, client| async move {
+///         let diagnostics = PublishDiagnosticsParams {
+///             uri: params.text_document.uri,
+///             version: Some(params.text_document.version),
+///             diagnostics: Vec::new(),
+///         };
+///         client.notify::<PublishDiagnostics>(diagnostics).await
+///     })
+///     .build()
+///     .serve_stdio()
+///     .await
+/// # }
+/// ```
+pub struct Server {
+    initialize_result: InitializeResult,
+    request_timeout: Option<Duration>,
+    handlers: Arc<Handlers>,
+}
+
+impl Server {
+    /// Create a builder declaring the server's capabilities and handlers.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    /// Serve a client over standard input and output.
+    ///
+    /// See [`Server::serve_transport`] for when serving ends.
+    pub async fn serve_stdio(self) -> Result<()> {
+        self.serve(tokio::io::stdin(), tokio::io::stdout()).await
+    }
+
+    /// Serve a client speaking the base protocol over the given streams.
+    pub async fn serve<R, W>(self, reader: R, writer: W) -> Result<()>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        self.serve_transport(Transport::new(reader, writer)).await
+    }
+
+    /// Serve a client communicating through the given transport.
+    ///
+    /// Returns `Ok` once the client sends `exit` after `shutdown`. If the
+    /// client exits without shutting down first, or the connection is lost,
+    /// an error is returned instead, so that a server returning it from
+    /// `main` exits with a failure code as the protocol asks.
+    pub async fn serve_transport(self, transport: impl MessageTransport) -> Result<()> {
+        let (mut receiver, sender) = transport.split();
+        let client = ClientHandle {
+            shared: Arc::new(Shared {
+                outgoing: Outgoing::new(Box::new(sender), |_, _| {}),
+                request_timeout: self.request_timeout,
+            }),
+        };
+
+        // Notifications are handled in order on a task of their own, so that
+        // their handlers can wait for responses from the client. Requests
+        // take their turn on the same queue before their handlers start.
+        let (notification_sender, mut notification_receiver) =
+            mpsc::unbounded_channel::<BoxFuture<'static, ()>>();
+        let notification_task = tokio::spawn(async move {
+            while let Some(handled) = notification_receiver.recv().await {
+                handled.await;
+            }
+        });
+
+        let mut connection = Connection {
+            server: self,
+            client,
+            state: Arc::new(std::sync::Mutex::new(State::Uninitialized)),
+            in_flight: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            notifications: notification_sender,
+        };
+        let result = loop {
+            let message = match receiver.recv().await {
+                Ok(Some(message)) => message,
+                Ok(None) => {
+                    break Err(LspError::Connection(
+                        "The client closed the connection".to_string(),
+                    ))
+                }
+                Err(e @ (LspError::Transport(_) | LspError::Json(_))) => {
+                    // The receiver has skipped the rejected message
+                    log::error!("Rejected message from client: {}", e);
+                    continue;
+                }
+                Err(e) => break Err(e),
+            };
+            match connection.handle(message).await {
+                Ok(Some(result)) => break result,
+                Ok(None) => {}
+                Err(e) => break Err(e),
+            }
+        };
+
+        connection.close(notification_task).await;
+        result
+    }
+}
+
+/// The lifecycle state of a connection, as seen by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for the `initialize` request.
+    Uninitialized,
+    /// The `initialize` request is being handled.
+    Initializing,
+    /// `initialize` was answered; waiting for the `initialized` notification.
+    Initialized,
+    /// Serving requests.
+    Running,
+    /// The `shutdown` request was received; waiting for `exit`.
+    ShutDown,
+}
+
+/// The state of a connection being served.
+struct Connection {
+    server: Server,
+    client: ClientHandle,
+    /// The lifecycle state, shared with the handler task of `initialize`.
+    state: Arc<std::sync::Mutex<State>>,
+    /// Handler tasks of requests that have not been answered yet.
+    in_flight: Arc<std::sync::Mutex<HashMap<Id, AbortHandle>>>,
+    /// Queue of the notification task: notification handlers, and the turns
+    /// of requests waiting for the notifications received before them.
+    notifications: mpsc::UnboundedSender<BoxFuture<'static, ()>>,
+}
+
+impl Connection {
+    /// Handle a message from the client. Returns the result of serving once
+    /// the client has sent `exit`.
+    async fn handle(&mut self, message: RpcMessage) -> Result<Option<Result<()>>> {
+        match message {
+            RpcMessage::Request(request) => self.handle_request(request).await?,
+            RpcMessage::Notification(notification) if notification.method == Exit::METHOD => {
+                return Ok(Some(match *self.lock_state() {
+                    State::ShutDown => Ok(()),
+                    _ => Err(LspError::Protocol(ResponseError::invalid_request(
+                        "The client sent exit before shutdown",
+                    ))),
+                }));
+            }
+            RpcMessage::Notification(notification) if notification.method == Cancel::METHOD => {
+                self.cancel(notification).await?
+            }
+            RpcMessage::Notification(notification) => self.handle_notification(notification),
+            RpcMessage::Response(response) => self.client.shared.complete(response),
+        }
+        Ok(None)
+    }
+
+    async fn handle_request(&mut self, request: RequestMessage) -> Result<()> {
+        let method = request.method.as_str();
+        if let Some(error) = self.begin_request(method) {
+            let response = ResponseMessage::error(Some(request.id), error);
+            return self.client.write(&RpcMessage::Response(response)).await;
+        }
+
+        let handlers = &self.server.handlers;
+        let response = if method == Initialize::METHOD && !handlers.requests.contains(method) {
+            let result = serde_json::to_value(&self.server.initialize_result)?;
+            futures::future::ready(ResponseMessage::success(request.id.clone(), result)).boxed()
+        } else if method == Shutdown::METHOD && !handlers.requests.contains(method) {
+            let response = ResponseMessage::success(request.id.clone(), serde_json::Value::Null);
+            futures::future::ready(response).boxed()
+        } else {
+            match handlers
+                .requests
+                .dispatch(request.clone(), self.client.clone())
+            {
+                Some(response) => response.boxed(),
+                None => futures::future::ready(method_not_found(&request)).boxed(),
+            }
+        };
+
+        // The server counts as initialized once `initialize` succeeded, and
+        // may be initialized again if it failed
+        let initializing = (method == Initialize::METHOD).then(|| Arc::clone(&self.state));
+
+        // The handler only starts once the notifications received before the
+        // request have been handled, so it sees the documents they changed
+        let (turn_sender, turn) = oneshot::channel();
+        let _ = self.notifications.send(
+            async move {
+                let _ = turn_sender.send(());
+            }
+            .boxed(),
+        );
+
+        // Whoever removes the request from the in-flight requests answers it,
+        // either the handler task or a cancellation
+        let id = request.id;
+        let key = id.clone();
+        let in_flight = Arc::clone(&self.in_flight);
+        let client = self.client.clone();
+        let mut in_flight_guard = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        let task = tokio::spawn(async move {
+            let _ = turn.await;
+            let response = response.await;
+            if let Some(state) = initializing {
+                settle_initialize(&state, !response.is_error());
+            }
+            let removed = in_flight
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(&id);
+            if removed.is_some() {
+                if let Err(e) = client.write(&RpcMessage::Response(response)).await {
+                    log::warn!("Failed to respond to request {}: {}", id, e);
+                }
+            }
+        });
+        in_flight_guard.insert(key, task.abort_handle());
+        Ok(())
+    }
+
+    /// Check that a request may be handled in the current state, advancing
+    /// the state for `initialize` and `shutdown`. Returns the error to answer
+    /// the request with otherwise.
+    fn begin_request(&self, method: &str) -> Option<ResponseError> {
+        let mut state = self.lock_state();
+        match *state {
+            State::Uninitialized if method == Initialize::METHOD => {
+                *state = State::Initializing;
+                None
+            }
+            State::Initializing if method == Initialize::METHOD => Some(
+                ResponseError::invalid_request("The server is already being initialized"),
+            ),
+            State::Uninitialized | State::Initializing => {
+                Some(ResponseError::server_not_initialized(format!(
+                    "Received {} before initialize",
+                    method
+                )))
+            }
+            State::Initialized | State::Running if method == Initialize::METHOD => Some(
+                ResponseError::invalid_request("The server is already initialized"),
+            ),
+            State::Initialized | State::Running if method == Shutdown::METHOD => {
+                *state = State::ShutDown;
+                None
+            }
+            State::Initialized => Some(ResponseError::server_not_initialized(format!(
+                "Received {} before initialized",
+                method
+            ))),
+            State::Running => None,
+            State::ShutDown => Some(ResponseError::invalid_request(format!(
+                "Received {} after shutdown",
+                method
+            ))),
+        }
+    }
+
+    fn handle_notification(&mut self, notification: NotificationMessage) {
+        let is_initialized = notification.method == Initialized::METHOD;
+        {
+            let mut state = self.lock_state();
+            match *state {
+                State::Initialized if is_initialized => *state = State::Running,
+                State::Running | State::ShutDown if is_initialized => {
+                    log::warn!("Ignoring repeated initialized notification");
+                    return;
+                }
+                State::Running | State::ShutDown => {}
+                State::Uninitialized | State::Initializing | State::Initialized => {
+                    log::debug!(
+                        "Dropping {} notification received before initialized",
+                        notification.method
+                    );
+                    return;
+                }
+            }
+        }
+        let handled = self.server.handlers.notifications.dispatch(
+            &notification.method,
+            notification.params,
+            self.client.clone(),
+        );
+        match handled {
+            Some(handled) => {
+                let _ = self.notifications.send(handled);
+            }
+            None => log::debug!("No handler for notification {}", notification.method),
+        }
+    }
+
+    /// Abort the handler of a cancelled request and answer it as cancelled.
+    async fn cancel(&mut self, notification: NotificationMessage) -> Result<()> {
+        let params = notification.params.unwrap_or(serde_json::Value::Null);
+        let Ok(CancelParams { id }) = serde_json::from_value(params) else {
+            log::warn!("Ignoring $/cancelRequest with invalid parameters");
+            return Ok(());
+        };
+        let task = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&id);
+        if let Some(task) = task {
+            task.abort();
+            // Only `initialize` can be in flight while initializing
+            settle_initialize(&self.state, false);
+            let error = ResponseError::request_cancelled(format!("Request {} was cancelled", id));
+            let response = ResponseMessage::error(Some(id), error);
+            self.client.write(&RpcMessage::Response(response)).await?;
+        }
+        Ok(())
+    }
+
+    fn lock_state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stop handling requests and wait for queued notifications.
+    async fn close(self, notification_task: tokio::task::JoinHandle<()>) {
+        let in_flight: Vec<_> = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain()
+            .collect();
+        for (_, task) in in_flight {
+            task.abort();
+        }
+        // Requests to the client can no longer be answered
+        self.client.shared.fail_pending();
+        drop(self.notifications);
+        let _ = notification_task.await;
+    }
+}
+
+/// Leave the initializing state once `initialize` has been answered.
+fn settle_initialize(state: &std::sync::Mutex<State>, succeeded: bool) {
+    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+    if *state == State::Initializing {
+        *state = if succeeded {
+            State::Initialized
+        } else {
+            State::Uninitialized
+        };
+    }
+}
+
+/// State shared between the connection and every [`ClientHandle`].
+struct Shared {
+    /// The sending half of the transport and the requests sent to the client
+    /// that are waiting for responses.
+    outgoing: Arc<Outgoing>,
+    /// How long requests to the client wait for a response.
+    request_timeout: Option<Duration>,
+}
+
+impl Shared {
+    fn complete(&self, response: ResponseMessage) {
+        let Some(id) = response.id.clone() else {
+            log::warn!("Received response without ID from client");
+            return;
+        };
+        if !self.outgoing.complete(&id, Ok(response)) {
+            log::warn!("Received response for unknown request ID: {}", id);
+        }
+    }
+
+    fn fail_pending(&self) {
+        self.outgoing
+            .fail_pending("The connection to the client was closed");
+    }
+}
+
+/// A handle for sending notifications and requests to the client.
+///
+/// Handlers receive a handle with each message; it can be cloned and kept
+/// for as long as the connection is served.
+#[derive(Clone)]
+pub struct ClientHandle {
+    shared: Arc<Shared>,
+}
+
+impl ClientHandle {
+    async fn write(&self, message: &RpcMessage) -> Result<()> {
+        self.shared.outgoing.write(message).await
+    }
+
+    /// Send a notification to the client.
+    pub async fn send_notification(
+        &self,
+        method: impl Into<String>,
+        params: Option<serde_json::Value>,
+    ) -> Result<()> {
+        let notification = match params {
+            Some(params) => NotificationMessage::with_params(method, params),
+            None => NotificationMessage::new(method),
+        };
+        self.write(&RpcMessage::Notification(notification)).await
+    }
+
+    /// Send a typed notification to the client.
+    pub async fn notify<N: Notification>(&self, params: N::Params) -> Result<()> {
+        self.send_notification(N::METHOD, to_params(params)?).await
+    }
+
+    /// Send a request to the client and wait for the response.
+    ///
+    /// The request fails with [`LspError::Timeout`] if no response arrives
+    /// within the server's request timeout, after sending `$/cancelRequest`
+    /// for it, and with [`LspError::Connection`] if serving ends first.
+    /// The request is also cancelled if the handler waiting for it is
+    /// aborted, for example because the client cancelled the handler's own
+    /// request.
+    pub async fn send_request(
+        &self,
+        method: impl Into<String>,
+        params: Option<serde_json::Value>,
+    ) -> Result<ResponseMessage> {
+        self.shared
+            .outgoing
+            .send_request(method.into(), params, self.shared.request_timeout)
+            .await
+    }
+
+    /// Send a typed request to the client and deserialize its result.
+    ///
+    /// An error response is returned as [`LspError::Protocol`].
+    pub async fn request<Q: Request>(&self, params: Q::Params) -> Result<Q::Result> {
+        into_result::<Q>(self.send_request(Q::METHOD, to_params(params)?).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::error_codes;
+    use crate::transport::{pair, ChannelTransport, MessageSender};
+    use crate::types::notification::DidChangeTextDocument;
+    use crate::types::request::WorkspaceConfiguration;
+    use crate::types::ConfigurationParams;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicI64, Ordering};
+
+    /// Serve a client on a task, returning the client's end of the connection.
+    fn start(builder: ServerBuilder) -> (ChannelTransport, tokio::task::JoinHandle<Result<()>>) {
+        let (client, server) = pair();
+        (
+            client,
+            tokio::spawn(builder.build().serve_transport(server)),
+        )
+    }
+
+    async fn request(client: &mut ChannelTransport, id: i64, method: &str) -> ResponseMessage {
+        request_with_params(client, id, method, json!({})).await
+    }
+
+    async fn request_with_params(
+        client: &mut ChannelTransport,
+        id: i64,
+        method: &str,
+        params: serde_json::Value,
+    ) -> ResponseMessage {
+        let request = RpcMessage::Request(RequestMessage::with_params(id, method, params));
+        client.send(&request).await.unwrap();
+        match client.recv().await.unwrap() {
+            Some(RpcMessage::Response(response)) => response,
+            other => panic!("Expected response, got {:?}", other),
+        }
+    }
+
+    async fn notify(client: &mut ChannelTransport, method: &str) {
+        let notification = RpcMessage::Notification(NotificationMessage::new(method));
+        client.send(&notification).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_is_enforced() {
+        let (mut client, server) = start(Server::builder());
+
+        let response = request(&mut client, 1, "textDocument/hover").await;
+        assert_eq!(
+            response.error.unwrap().code,
+            error_codes::SERVER_NOT_INITIALIZED
+        );
+
+        let response = request(&mut client, 2, "initialize").await;
+        assert!(response.result.unwrap()["capabilities"].is_object());
+        let response = request(&mut client, 3, "initialize").await;
+        assert_eq!(response.error.unwrap().code, error_codes::INVALID_REQUEST);
+        let response = request(&mut client, 7, "textDocument/hover").await;
+        assert_eq!(
+            response.error.unwrap().code,
+            error_codes::SERVER_NOT_INITIALIZED
+        );
+        notify(&mut client, "initialized").await;
+
+        let response = request(&mut client, 4, "textDocument/hover").await;
+        assert_eq!(response.error.unwrap().code, error_codes::METHOD_NOT_FOUND);
+
+        let response = request(&mut client, 5, "shutdown").await;
+        assert_eq!(response.result, Some(serde_json::Value::Null));
+        let response = request(&mut client, 6, "textDocument/hover").await;
+        assert_eq!(response.error.unwrap().code, error_codes::INVALID_REQUEST);
+
+        notify(&mut client, "exit").await;
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_initialize_can_be_retried() {
+        let attempts = Arc::new(AtomicI64::new(0));
+        let counter = Arc::clone(&attempts);
+        let builder = Server::builder().on_request::<Initialize, _, _>(move |_, _| {
+            let attempt = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match attempt {
+                    0 => Err(LspError::Other("Not ready".to_string())),
+                    1 => panic!("Still not ready"),
+                    _ => Ok(InitializeResult {
+                        capabilities: ServerBuilder::default().capabilities,
+                        server_info: None,
+                    }),
+                }
+            }
+        });
+        let (mut client, _server) = start(builder);
+        let params = json!({"processId": null, "rootUri": null, "capabilities": {}});
+        let response = request_with_params(&mut client, 1, "initialize", params.clone()).await;
+        assert_eq!(response.error.unwrap().code, error_codes::INTERNAL_ERROR);
+        let response = request_with_params(&mut client, 2, "initialize", params.clone()).await;
+        assert_eq!(response.error.unwrap().code, error_codes::INTERNAL_ERROR);
+        let response = request_with_params(&mut client, 3, "initialize", params.clone()).await;
+        assert!(response.result.unwrap()["capabilities"].is_object());
+        let response = request_with_params(&mut client, 4, "initialize", params.clone()).await;
+        assert_eq!(response.error.unwrap().code, error_codes::INVALID_REQUEST);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_exit_without_shutdown_is_an_error() {
+        let (mut client, server) = start(Server::builder());
+        notify(&mut client, "exit").await;
+        assert!(matches!(
+            server.await.unwrap(),
+            Err(LspError::Protocol(error)) if error.code == error_codes::INVALID_REQUEST
+        ));
+
+        let (client, server) = start(Server::builder());
+        drop(client);
+        assert!(matches!(
+            server.await.unwrap(),
+            Err(LspError::Connection(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handlers_can_request_from_client() {
+        let builder = Server::builder().on_request::<Shutdown, _, _>(|(), client| async move {
+            let params = ConfigurationParams { items: Vec::new() };
+            let values = client.request::<WorkspaceConfiguration>(params).await?;
+            assert_eq!(values, vec![json!(true)]);
+            Ok(())
+        });
+        let (mut client, server) = start(builder);
+        request(&mut client, 1, "initialize").await;
+
+        let shutdown = RpcMessage::Request(RequestMessage::new(2, "shutdown"));
+        client.send(&shutdown).await.unwrap();
+        let request = match client.recv().await.unwrap() {
+            Some(RpcMessage::Request(request)) => request,
+            other => panic!("Expected request to the client, got {:?}", other),
+        };
+        assert_eq!(request.method, "workspace/configuration");
+        let response = ResponseMessage::success(request.id, json!([true]));
+        client.send(&RpcMessage::Response(response)).await.unwrap();
+
+        match client.recv().await.unwrap() {
+            Some(RpcMessage::Response(response)) => {
+                assert_eq!(response.id, Some(Id::Number(2)));
+                assert!(response.error.is_none());
+            }
+            other => panic!("Expected shutdown response, got {:?}", other),
+        }
+        notify(&mut client, "exit").await;
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_request_to_client_is_cancelled() {
+        let builder = Server::builder()
+            .with_request_timeout(Duration::from_millis(50))
+            .on_request::<Shutdown, _, _>(|(), client| async move {
+                let params = ConfigurationParams { items: Vec::new() };
+                let error = client
+                    .request::<WorkspaceConfiguration>(params)
+                    .await
+                    .unwrap_err();
+                assert!(matches!(error, LspError::Timeout { .. }));
+                Ok(())
+            });
+        let (mut client, _server) = start(builder);
+        request(&mut client, 1, "initialize").await;
+
+        let shutdown = RpcMessage::Request(RequestMessage::new(2, "shutdown"));
+        client.send(&shutdown).await.unwrap();
+        let request = match client.recv().await.unwrap() {
+            Some(RpcMessage::Request(request)) => request,
+            other => panic!("Expected request to the client, got {:?}", other),
+        };
+        match client.recv().await.unwrap() {
+            Some(RpcMessage::Notification(notification)) => {
+                assert_eq!(notification.method, Cancel::METHOD);
+                assert_eq!(notification.params, Some(json!({ "id": request.id })));
+            }
+            other => panic!("Expected cancel notification, got {:?}", other),
+        }
+        match client.recv().await.unwrap() {
+            Some(RpcMessage::Response(response)) => assert_eq!(response.id, Some(Id::Number(2))),
+            other => panic!("Expected shutdown response, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_wait_for_earlier_notifications() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let changed = Arc::clone(&events);
+        let shut_down = Arc::clone(&events);
+        let builder = Server::builder()
+            .on_notification::<DidChangeTextDocument, _, _>(move |_, _| {
+                let changed = Arc::clone(&changed);
+                async move {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    changed.lock().unwrap().push("didChange");
+                    Ok(())
+                }
+            })
+            .on_request::<Shutdown, _, _>(move |(), _| {
+                shut_down.lock().unwrap().push("shutdown");
+                async { Ok(()) }
+            });
+        let (mut client, _server) = start(builder);
+        request(&mut client, 1, "initialize").await;
+        notify(&mut client, "initialized").await;
+
+        let params = json!({
+            "textDocument": {"uri": "file:///main.rs", "version": 2},
+            "contentChanges": [{"text": "fn main() {}"}]
+        });
+        let change = NotificationMessage::with_params(DidChangeTextDocument::METHOD, params);
+        client
+            .send(&RpcMessage::Notification(change))
+            .await
+            .unwrap();
+        let shutdown = RpcMessage::Request(RequestMessage::new(2, "shutdown"));
+        client.send(&shutdown).await.unwrap();
+        match client.recv().await.unwrap() {
+            Some(RpcMessage::Response(response)) => assert!(response.error.is_none()),
+            other => panic!("Expected shutdown response, got {:?}", other),
+        }
+        assert_eq!(*events.lock().unwrap(), vec!["didChange", "shutdown"]);
+    }
+
+    #[tokio::test]
+    async fn test_aborted_handler_cancels_its_request_to_client() {
+        let builder = Server::builder().on_request::<Shutdown, _, _>(|(), client| async move {
+            let params = ConfigurationParams { items: Vec::new() };
+            client.request::<WorkspaceConfiguration>(params).await?;
+            Ok(())
+        });
+        let (mut client, _server) = start(builder);
+        request(&mut client, 1, "initialize").await;
+
+        let shutdown = RpcMessage::Request(RequestMessage::new(2, "shutdown"));
+        client.send(&shutdown).await.unwrap();
+        let request = match client.recv().await.unwrap() {
+            Some(RpcMessage::Request(request)) => request,
+            other => panic!("Expected request to the client, got {:?}", other),
+        };
+        let cancel = NotificationMessage::with_params(Cancel::METHOD, json!({"id": 2}));
+        client
+            .send(&RpcMessage::Notification(cancel))
+            .await
+            .unwrap();
+
+        // The cancelled response and the handler's cancellation may come in
+        // either order
+        let mut cancelled = None;
+        let mut cancelled_response = None;
+        for _ in 0..2 {
+            match client.recv().await.unwrap() {
+                Some(RpcMessage::Notification(notification)) => {
+                    assert_eq!(notification.method, Cancel::METHOD);
+                    cancelled = notification.params;
+                }
+                Some(RpcMessage::Response(response)) => cancelled_response = response.error,
+                other => panic!("Expected cancellation, got {:?}", other),
+            }
+        }
+        assert_eq!(cancelled, Some(json!({ "id": request.id })));
+        assert_eq!(
+            cancelled_response.unwrap().code,
+            error_codes::REQUEST_CANCELLED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_request_is_aborted() {
+        let builder = Server::builder().on_request::<Shutdown, _, _>(|(), _| async move {
+            futures::future::pending::<()>().await;
+            Ok(())
+        });
+        let (mut client, _server) = start(builder);
+        request(&mut client, 1, "initialize").await;
+
+        let shutdown = RpcMessage::Request(RequestMessage::new(2, "shutdown"));
+        client.send(&shutdown).await.unwrap();
+        let cancel = NotificationMessage::with_params(Cancel::METHOD, json!({"id": 2}));
+        client
+            .send(&RpcMessage::Notification(cancel))
+            .await
+            .unwrap();
+
+        match client.recv().await.unwrap() {
+            Some(RpcMessage::Response(response)) => {
+                assert_eq!(response.id, Some(Id::Number(2)));
+                assert_eq!(response.error.unwrap().code, error_codes::REQUEST_CANCELLED);
+            }
+            other => panic!("Expected cancelled response, got {:?}", other),
+        }
+    }
+}
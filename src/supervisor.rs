@@ -0,0 +1,479 @@
+//! Automatic restarts for crashed language servers.
+//!
+//! A [`Supervisor`] owns a language server process and watches it for crashes.
+//! When the server exits or closes its connection unexpectedly, the supervisor
+//! starts a new one, replays `initialize`/`initialized`, and reopens every
+//! document that was open in the crashed client's
+//! [`DocumentStore`](crate::client::DocumentStore) with its last known content.
+//! Request handlers registered through [`Supervisor::on_request`] are
+//! registered again on every new client.
+
+use crate::client::{Client, ClientConfig, ServerProcess};
+use crate::error::{LspError, Result};
+use crate::types::{
+    DidChangeTextDocumentParams, InitializeParams, Request, TextDocumentIdentifier,
+    TextDocumentItem,
+};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+
+/// How long [`Supervisor::shutdown`] waits for the supervising task to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait for the exit status of a server that closed its output.
+const EXIT_STATUS_GRACE: Duration = Duration::from_millis(200);
+
+/// The number of restart events buffered for each subscriber.
+const EVENT_CAPACITY: usize = 16;
+
+/// When and how often a crashed server is restarted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestartPolicy {
+    /// Delay before the first restart after a crash.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between restarts.
+    pub max_backoff: Duration,
+    /// The maximum number of restarts within `restart_window` before the
+    /// supervisor gives up.
+    pub max_restarts: usize,
+    /// The window in which restarts are counted towards `max_restarts`.
+    pub restart_window: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_restarts: 5,
+            restart_window: Duration::from_secs(180),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Set the initial and maximum delay between restarts.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Give up after `max_restarts` restarts within `window`.
+    pub fn with_crash_limit(mut self, max_restarts: usize, window: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.restart_window = window;
+        self
+    }
+
+    /// The delay before a restart, given how many restarts preceded it
+    /// within the restart window. The delay doubles with each restart.
+    pub fn backoff(&self, recent_restarts: usize) -> Duration {
+        let factor = 1u32.checked_shl(recent_restarts as u32).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Configuration for a [`Supervisor`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SupervisorConfig {
+    /// Configuration for each client connected to the server.
+    pub client: ClientConfig,
+    /// The restart policy applied when the server crashes.
+    pub restart: RestartPolicy,
+}
+
+impl SupervisorConfig {
+    /// Set the configuration used for each client.
+    pub fn with_client_config(mut self, client: ClientConfig) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Set the restart policy.
+    pub fn with_restart_policy(mut self, restart: RestartPolicy) -> Self {
+        self.restart = restart;
+        self
+    }
+}
+
+/// Events emitted by a [`Supervisor`] as it restarts the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestartEvent {
+    /// The server exited or closed its connection unexpectedly.
+    Crashed {
+        /// The exit status of the server, if it had exited.
+        exit_status: Option<ExitStatus>,
+    },
+    /// A new server was started, initialized and had its documents reopened.
+    ///
+    /// The new server is connected to a new [`Client`]. Only handlers
+    /// registered through [`Supervisor::on_request`] are carried over to it:
+    /// handlers registered on the old client are lost, and the
+    /// [`Subscription`](crate::client::Subscription) streams of the old
+    /// client end, so subscribe again on [`Supervisor::client`].
+    Restarted {
+        /// The number of restarts within the current restart window.
+        attempt: usize,
+    },
+    /// Starting a new server failed.
+    RestartFailed {
+        /// The number of restarts within the current restart window.
+        attempt: usize,
+        /// Why the restart failed.
+        reason: String,
+    },
+    /// The server crashed too often and will not be restarted again.
+    GaveUp,
+}
+
+type CommandFactory = Box<dyn Fn() -> Command + Send + Sync>;
+
+/// Registers a request handler on a client.
+type Registration = Arc<dyn Fn(&Client) + Send + Sync>;
+
+/// State shared between the supervisor and its supervising task.
+struct Inner {
+    /// Builds the command used to start the server.
+    command: CommandFactory,
+    /// Parameters replayed with `initialize` on every start.
+    init_params: InitializeParams,
+    /// Supervisor configuration.
+    config: SupervisorConfig,
+    /// The client connected to the currently running server.
+    client: std::sync::RwLock<Arc<Client>>,
+    /// Held while documents are changed or reopened on a new server, so no
+    /// change is lost between reopening the documents and switching clients.
+    documents: Mutex<()>,
+    /// Request handlers registered on every new client.
+    registrations: std::sync::Mutex<Vec<Registration>>,
+    /// Sender for restart events.
+    events: broadcast::Sender<RestartEvent>,
+    /// Set once a shutdown was requested, so the exit is not treated as a crash.
+    stopping: AtomicBool,
+}
+
+impl Inner {
+    fn client(&self) -> Arc<Client> {
+        Arc::clone(&self.client.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn registrations(&self) -> std::sync::MutexGuard<'_, Vec<Registration>> {
+        self.registrations.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn emit(&self, event: RestartEvent) {
+        // Nobody listening is fine
+        let _ = self.events.send(event);
+    }
+
+    /// Start a new server and replay the open documents to it.
+    async fn restart(&self) -> Result<ServerProcess> {
+        let registrations = self.registrations().clone();
+        let (client, process) = launch(
+            &self.command,
+            &self.init_params,
+            &self.config,
+            &registrations,
+        )
+        .await?;
+
+        // Hold the documents while replaying, so that changes made meanwhile
+        // are sent to the new server rather than the crashed one.
+        let _documents = self.documents.lock().await;
+        for document in self.client().documents().snapshot().await {
+            client.documents().open(document).await?;
+        }
+        // Handlers registered since the server was started are registered
+        // again, while no more can be added to the old client
+        let registrations = self.registrations();
+        for register in registrations.iter() {
+            register(&client);
+        }
+        *self.client.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(client);
+        Ok(process)
+    }
+}
+
+/// Start a server, register the request handlers and run the initialization
+/// handshake.
+async fn launch(
+    command: &CommandFactory,
+    init_params: &InitializeParams,
+    config: &SupervisorConfig,
+    registrations: &[Registration],
+) -> Result<(Client, ServerProcess)> {
+    let (client, process) = Client::spawn_command(command(), config.client.clone())?;
+    for register in registrations {
+        register(&client);
+    }
+    client.initialize(init_params.clone()).await?;
+    client.initialized().await?;
+    Ok((client, process))
+}
+
+/// Keeps a language server running, restarting it when it crashes.
+///
+/// The documents open in the current client's
+/// [`DocumentStore`](crate::client::DocumentStore) are reopened after a
+/// restart. Prefer [`Supervisor::did_open`], [`Supervisor::did_change`] and
+/// [`Supervisor::did_close`] over using the store directly, as they wait
+/// while documents are being reopened on a new server instead of changing
+/// the crashed client's documents. Dropping the supervisor kills the server.
+pub struct Supervisor {
+    inner: Arc<Inner>,
+    task: std::sync::Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Supervisor {
+    /// Start a server with the default configuration.
+    ///
+    /// `command` is called to build the server command on every (re)start.
+    pub async fn start<F>(command: F, init_params: InitializeParams) -> Result<Self>
+    where
+        F: Fn() -> Command + Send + Sync + 'static,
+    {
+        Self::start_with_config(command, init_params, SupervisorConfig::default()).await
+    }
+
+    /// Start a server with the given configuration.
+    pub async fn start_with_config<F>(
+        command: F,
+        init_params: InitializeParams,
+        config: SupervisorConfig,
+    ) -> Result<Self>
+    where
+        F: Fn() -> Command + Send + Sync + 'static,
+    {
+        let command: CommandFactory = Box::new(command);
+        let (client, process) = launch(&command, &init_params, &config, &[]).await?;
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
+        let inner = Arc::new(Inner {
+            command,
+            init_params,
+            config,
+            client: std::sync::RwLock::new(Arc::new(client)),
+            documents: Mutex::new(()),
+            registrations: std::sync::Mutex::new(Vec::new()),
+            events,
+            stopping: AtomicBool::new(false),
+        });
+        let task = tokio::spawn(supervise(Arc::clone(&inner), process));
+        Ok(Self {
+            inner,
+            task: std::sync::Mutex::new(Some(task)),
+        })
+    }
+
+    /// Get the client connected to the currently running server.
+    ///
+    /// The returned client is replaced after a restart, so callers should
+    /// not hold on to it across crashes.
+    pub fn client(&self) -> Arc<Client> {
+        self.inner.client()
+    }
+
+    /// Get the supervisor configuration.
+    pub fn config(&self) -> &SupervisorConfig {
+        &self.inner.config
+    }
+
+    /// Register a handler answering requests of type `R` sent by the server,
+    /// on the current client and every client started after a restart.
+    ///
+    /// See [`Client::on_request`]. Handlers registered directly on
+    /// [`Supervisor::client`] are not carried over after a restart.
+    pub fn on_request<R, F, Fut>(&self, handler: F)
+    where
+        R: Request,
+        F: Fn(R::Params) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R::Result>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        let register: Registration = Arc::new(move |client: &Client| {
+            let handler = Arc::clone(&handler);
+            client.on_request::<R, _, _>(move |params| handler(params));
+        });
+        // Registering on the current client while holding the registrations
+        // means a restart either sees the new registration or has already
+        // switched to the new client
+        let mut registrations = self.inner.registrations();
+        register(&self.client());
+        registrations.push(register);
+    }
+
+    /// Subscribe to restart events.
+    pub fn subscribe_restarts(&self) -> broadcast::Receiver<RestartEvent> {
+        self.inner.events.subscribe()
+    }
+
+    /// Open a document on the server and track it for restarts.
+    ///
+    /// Fails like [`DocumentStore::open`](crate::client::DocumentStore::open)
+    /// if the document is already open. If the server is down, the document
+    /// is still tracked and opened on the restarted server.
+    pub async fn did_open(&self, text_document: TextDocumentItem) -> Result<()> {
+        let _documents = self.inner.documents.lock().await;
+        let client = self.client();
+        match client.documents().open(text_document.clone()).await {
+            Ok(()) => Ok(()),
+            Err(LspError::Document(e)) => Err(e.into()),
+            Err(e) => {
+                log::warn!(
+                    "Failed to open {}, opening it after a restart: {}",
+                    text_document.uri,
+                    e
+                );
+                client.documents().track(text_document).await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Send changes of a document to the server and apply them to the
+    /// tracked copy.
+    pub async fn did_change(&self, params: DidChangeTextDocumentParams) -> Result<()> {
+        let _documents = self.inner.documents.lock().await;
+        self.client()
+            .documents()
+            .change(params.text_document, params.content_changes)
+            .await
+    }
+
+    /// Close a document on the server and stop tracking it.
+    pub async fn did_close(&self, text_document: TextDocumentIdentifier) -> Result<()> {
+        let _documents = self.inner.documents.lock().await;
+        self.client().documents().close(&text_document.uri).await
+    }
+
+    /// Get the tracked content of an open document.
+    pub async fn document(&self, uri: &str) -> Option<TextDocumentItem> {
+        self.client().documents().get(uri).await
+    }
+
+    /// Shut down the server without restarting it.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.inner.stopping.store(true, Ordering::SeqCst);
+        let result = self.client().shutdown().await;
+
+        let task = self.task.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(mut task) = task {
+            if tokio::time::timeout(SHUTDOWN_TIMEOUT, &mut task)
+                .await
+                .is_err()
+            {
+                task.abort();
+            }
+        }
+        result
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.inner.stopping.store(true, Ordering::SeqCst);
+        // Aborting the task drops the server process, which kills it
+        if let Some(task) = self.task.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            task.abort();
+        }
+    }
+}
+
+/// Watch the server and restart it whenever it crashes.
+async fn supervise(inner: Arc<Inner>, mut process: ServerProcess) {
+    let policy = inner.config.restart.clone();
+    let mut restarts: VecDeque<Instant> = VecDeque::new();
+
+    loop {
+        let client = inner.client();
+        let exit_status = tokio::select! {
+            status = process.wait() => status.ok(),
+            // A server closing its output is usually about to exit
+            _ = client.closed() => {
+                match tokio::time::timeout(EXIT_STATUS_GRACE, process.wait()).await {
+                    Ok(status) => status.ok(),
+                    // Make sure the old server is gone before a new one is
+                    // started on the same workspace
+                    Err(_) => {
+                        log::warn!("Language server closed its output but did not exit");
+                        process.kill().await.ok()
+                    }
+                }
+            }
+        };
+        drop(client);
+        if inner.stopping.load(Ordering::SeqCst) {
+            return;
+        }
+        log::warn!("Language server crashed (exit status: {:?})", exit_status);
+        inner.emit(RestartEvent::Crashed { exit_status });
+
+        loop {
+            let now = Instant::now();
+            while restarts
+                .front()
+                .is_some_and(|restart| now.duration_since(*restart) > policy.restart_window)
+            {
+                restarts.pop_front();
+            }
+            if restarts.len() >= policy.max_restarts {
+                log::error!(
+                    "Language server crashed {} times within {:?}, giving up",
+                    restarts.len(),
+                    policy.restart_window
+                );
+                inner.emit(RestartEvent::GaveUp);
+                return;
+            }
+
+            tokio::time::sleep(policy.backoff(restarts.len())).await;
+            restarts.push_back(Instant::now());
+            let attempt = restarts.len();
+            if inner.stopping.load(Ordering::SeqCst) {
+                return;
+            }
+
+            match inner.restart().await {
+                Ok(new_process) => {
+                    log::info!("Language server restarted (attempt {})", attempt);
+                    process = new_process;
+                    inner.emit(RestartEvent::Restarted { attempt });
+                    break;
+                }
+                Err(e) => {
+                    log::error!("Failed to restart language server: {}", e);
+                    inner.emit(RestartEvent::RestartFailed {
+                        attempt,
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let policy = RestartPolicy::default()
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1));
+
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(800));
+        assert_eq!(policy.backoff(4), Duration::from_secs(1));
+        assert_eq!(policy.backoff(100), Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,492 @@
+//! A scriptable mock language server for integration tests, available with
+//! the `testing` feature.
+//!
+//! A [`MockServer`] is declared with a [`MockServerBuilder`] and connected
+//! to a [`Client`] through an in-memory duplex stream speaking the base
+//! protocol. It answers `initialize`, `shutdown` and `exit` like a real
+//! server, replies to other requests as scripted, and keeps every message it
+//! receives for assertions.
+//!
+//! ```
+//! use serde_json::json;
+//! use tokio_lsp::testing::{MockServer, Reply};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> tokio_lsp::Result<()> {
+//! let (client, server) = MockServer::builder()
+//!     .on_request_matching(
+//!         "textDocument/hover",
+//!         json!({"position": {"line": 1, "character": 4}}),
+//!         Reply::result(json!({"contents": "fn main()"})),
+//!     )
+//!     .start();
+//!
+//! client.initialize_default("test-client", None, None).await?;
+//! let params = json!({
+//!     "textDocument": {"uri": "file:///main.rs"},
+//!     "position": {"line": 1, "character": 4},
+//! });
+//! let hover = client.send_request("textDocument/hover", Some(params)).await?;
+//! assert_eq!(hover.result, Some(json!({"contents": "fn main()"})));
+//!
+//! server.assert_received("textDocument/hover");
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::{Client, ClientConfig};
+use crate::error::{LspError, ResponseError, Result};
+use crate::transport::{MessageReceiver, MessageSender, TransportReader, TransportWriter};
+use crate::types::notification::Exit;
+use crate::types::request::{Initialize, Shutdown};
+use crate::types::{
+    InitializeResult, Notification, NotificationMessage, Request, RequestMessage, ResponseMessage,
+    RpcMessage, ServerCapabilities,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio::sync::{watch, Mutex};
+
+/// The buffer size of the duplex stream between the client and the server.
+const DUPLEX_BUFFER_SIZE: usize = 64 * 1024;
+
+/// The scripted answer to a request.
+#[derive(Debug, Clone)]
+pub struct Reply {
+    outcome: std::result::Result<serde_json::Value, ResponseError>,
+    delay: Option<Duration>,
+    notifications: Vec<NotificationMessage>,
+}
+
+impl Reply {
+    /// Reply with a result.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the result cannot be serialized.
+    pub fn result(result: impl Serialize) -> Self {
+        let result = serde_json::to_value(result).expect("reply result must serialize");
+        Self {
+            outcome: Ok(result),
+            delay: None,
+            notifications: Vec::new(),
+        }
+    }
+
+    /// Reply with an error.
+    pub fn error(error: ResponseError) -> Self {
+        Self {
+            outcome: Err(error),
+            delay: None,
+            notifications: Vec::new(),
+        }
+    }
+
+    /// Wait before replying. Other messages are handled in the meantime.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Send a notification to the client right after the reply.
+    pub fn with_notification(mut self, notification: NotificationMessage) -> Self {
+        self.notifications.push(notification);
+        self
+    }
+}
+
+/// A scripted reply for requests of one method.
+struct Rule {
+    method: String,
+    /// Parameters a request must contain for the rule to apply.
+    params: Option<serde_json::Value>,
+    reply: Reply,
+}
+
+/// Declares how a [`MockServer`] behaves.
+pub struct MockServerBuilder {
+    initialize_result: InitializeResult,
+    rules: Vec<Rule>,
+    reactions: Vec<(String, NotificationMessage)>,
+}
+
+impl Default for MockServerBuilder {
+    fn default() -> Self {
+        Self {
+            initialize_result: InitializeResult {
+                capabilities: ServerCapabilities {
+                    text_document_sync: None,
+                    completion_provider: None,
+                    hover_provider: None,
+                },
+                server_info: None,
+            },
+            rules: Vec::new(),
+            reactions: Vec::new(),
+        }
+    }
+}
+
+impl MockServerBuilder {
+    /// Create a builder for a server without capabilities or scripted replies.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reply to `initialize` with these capabilities.
+    pub fn with_capabilities(mut self, capabilities: ServerCapabilities) -> Self {
+        self.initialize_result.capabilities = capabilities;
+        self
+    }
+
+    /// Reply to `initialize` with this result.
+    pub fn with_initialize_result(mut self, result: InitializeResult) -> Self {
+        self.initialize_result = result;
+        self
+    }
+
+    /// Reply to every request of the given method.
+    ///
+    /// Replies are tried in the order they were declared, so replies for
+    /// specific parameters should be declared before a catch-all. A reply for
+    /// `initialize` or `shutdown` replaces the default one.
+    pub fn on_request(mut self, method: impl Into<String>, reply: Reply) -> Self {
+        self.rules.push(Rule {
+            method: method.into(),
+            params: None,
+            reply,
+        });
+        self
+    }
+
+    /// Reply to requests of the given method whose parameters contain
+    /// `params`: every field of `params` must be present with the same value,
+    /// while other fields of the request are ignored.
+    pub fn on_request_matching(
+        mut self,
+        method: impl Into<String>,
+        params: serde_json::Value,
+        reply: Reply,
+    ) -> Self {
+        self.rules.push(Rule {
+            method: method.into(),
+            params: Some(params),
+            reply,
+        });
+        self
+    }
+
+    /// Send a notification to the client whenever it sends a notification of
+    /// the given method, such as diagnostics after `textDocument/didOpen`.
+    pub fn on_notification(
+        mut self,
+        method: impl Into<String>,
+        notification: NotificationMessage,
+    ) -> Self {
+        self.reactions.push((method.into(), notification));
+        self
+    }
+
+    /// Start the server and connect a client with the default configuration.
+    pub fn start(self) -> (Client, MockServer) {
+        self.start_with_config(ClientConfig::default())
+    }
+
+    /// Start the server and connect a client with the given configuration.
+    pub fn start_with_config(self, config: ClientConfig) -> (Client, MockServer) {
+        let (client_stream, server_stream) = tokio::io::duplex(DUPLEX_BUFFER_SIZE);
+        let (reader, writer) = tokio::io::split(client_stream);
+        let client = Client::with_config(reader, writer, config);
+
+        let (reader, writer) = tokio::io::split(server_stream);
+        let shared = Arc::new(ServerShared {
+            writer: Mutex::new(Some(TransportWriter::new(writer))),
+            received: std::sync::Mutex::new(Vec::new()),
+            received_count: watch::Sender::new(0),
+        });
+        tokio::spawn(serve(
+            TransportReader::new(reader),
+            Arc::clone(&shared),
+            self,
+        ));
+        (client, MockServer { shared })
+    }
+
+    /// Find the reply to a request.
+    fn reply(&self, request: &RequestMessage) -> Reply {
+        let params = request.params.as_ref().unwrap_or(&serde_json::Value::Null);
+        let rule = self.rules.iter().find(|rule| {
+            rule.method == request.method
+                && rule
+                    .params
+                    .as_ref()
+                    .is_none_or(|pattern| contains(params, pattern))
+        });
+        match rule {
+            Some(rule) => rule.reply.clone(),
+            None if request.method == Initialize::METHOD => Reply::result(&self.initialize_result),
+            None if request.method == Shutdown::METHOD => Reply::result(()),
+            None => Reply::error(ResponseError::method_not_found(format!(
+                "No reply scripted for {}",
+                request.method
+            ))),
+        }
+    }
+}
+
+/// Check whether `value` has every field of `pattern` with the same value.
+fn contains(value: &serde_json::Value, pattern: &serde_json::Value) -> bool {
+    match (value, pattern) {
+        (serde_json::Value::Object(value), serde_json::Value::Object(pattern)) => pattern
+            .iter()
+            .all(|(key, pattern)| value.get(key).is_some_and(|value| contains(value, pattern))),
+        _ => value == pattern,
+    }
+}
+
+/// State shared between a [`MockServer`] and the task serving the client.
+struct ServerShared {
+    /// The sending half of the connection, taken when it is closed.
+    writer: Mutex<Option<TransportWriter<WriteHalf<DuplexStream>>>>,
+    /// Every message received from the client.
+    received: std::sync::Mutex<Vec<RpcMessage>>,
+    /// The number of messages received, to wait for new ones.
+    received_count: watch::Sender<usize>,
+}
+
+impl ServerShared {
+    async fn send(&self, message: &RpcMessage) -> Result<()> {
+        match self.writer.lock().await.as_mut() {
+            Some(writer) => writer.send(message).await,
+            None => Err(LspError::Connection(
+                "The mock server closed the connection".to_string(),
+            )),
+        }
+    }
+
+    async fn reply(&self, request: RequestMessage, reply: Reply) -> Result<()> {
+        if let Some(delay) = reply.delay {
+            tokio::time::sleep(delay).await;
+        }
+        let response = match reply.outcome {
+            Ok(result) => ResponseMessage::success(request.id, result),
+            Err(error) => ResponseMessage::error(Some(request.id), error),
+        };
+        self.send(&RpcMessage::Response(response)).await?;
+        for notification in reply.notifications {
+            self.send(&RpcMessage::Notification(notification)).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&self) {
+        if let Some(writer) = self.writer.lock().await.take() {
+            let _ = writer.into_inner().shutdown().await;
+        }
+    }
+
+    fn lock_received(&self) -> std::sync::MutexGuard<'_, Vec<RpcMessage>> {
+        self.received.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Handle the client's messages until it closes the connection or exits.
+async fn serve(
+    mut reader: TransportReader<ReadHalf<DuplexStream>>,
+    shared: Arc<ServerShared>,
+    script: MockServerBuilder,
+) {
+    loop {
+        let message = match reader.recv().await {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e @ (LspError::Transport(_) | LspError::Json(_))) => {
+                log::warn!("Mock server rejected a message: {}", e);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Mock server failed to read a message: {}", e);
+                break;
+            }
+        };
+        shared.lock_received().push(message.clone());
+        shared.received_count.send_modify(|count| *count += 1);
+
+        let result = match message {
+            RpcMessage::Request(request) => {
+                let reply = script.reply(&request);
+                if reply.delay.is_some() {
+                    let shared = Arc::clone(&shared);
+                    tokio::spawn(async move { shared.reply(request, reply).await });
+                    Ok(())
+                } else {
+                    shared.reply(request, reply).await
+                }
+            }
+            RpcMessage::Notification(notification) if notification.method == Exit::METHOD => {
+                break;
+            }
+            RpcMessage::Notification(notification) => {
+                let mut result = Ok(());
+                for (method, reaction) in &script.reactions {
+                    if *method == notification.method {
+                        result = shared
+                            .send(&RpcMessage::Notification(reaction.clone()))
+                            .await;
+                    }
+                }
+                result
+            }
+            RpcMessage::Response(_) => Ok(()),
+        };
+        if let Err(e) = result {
+            log::warn!("Mock server failed to send a message: {}", e);
+        }
+    }
+    shared.close().await;
+}
+
+/// A scripted language server connected to a [`Client`].
+///
+/// Create one with [`MockServer::builder`]. The server runs until the client
+/// sends `exit`, closes the connection, or [`MockServer::close`] is called.
+pub struct MockServer {
+    shared: Arc<ServerShared>,
+}
+
+impl MockServer {
+    /// Create a builder declaring the server's behaviour.
+    pub fn builder() -> MockServerBuilder {
+        MockServerBuilder::new()
+    }
+
+    /// Send a message to the client.
+    pub async fn send(&self, message: &RpcMessage) -> Result<()> {
+        self.shared.send(message).await
+    }
+
+    /// Send a typed notification to the client.
+    pub async fn notify<N: Notification>(&self, params: N::Params) -> Result<()> {
+        let notification =
+            NotificationMessage::with_params(N::METHOD, serde_json::to_value(params)?);
+        self.send(&RpcMessage::Notification(notification)).await
+    }
+
+    /// Send a typed request to the client. Its response is available through
+    /// [`MockServer::received`].
+    pub async fn request<Q: Request>(
+        &self,
+        id: impl Into<crate::types::Id>,
+        params: Q::Params,
+    ) -> Result<()> {
+        let request = RequestMessage::with_params(id, Q::METHOD, serde_json::to_value(params)?);
+        self.send(&RpcMessage::Request(request)).await
+    }
+
+    /// Close the connection, as if the server had crashed.
+    pub async fn close(&self) {
+        self.shared.close().await;
+    }
+
+    /// Get every message received from the client so far, in order.
+    pub fn received(&self) -> Vec<RpcMessage> {
+        self.shared.lock_received().clone()
+    }
+
+    /// Get the requests and notifications of the given method received so
+    /// far, in order.
+    pub fn received_with_method(&self, method: &str) -> Vec<RpcMessage> {
+        self.shared
+            .lock_received()
+            .iter()
+            .filter(|message| message.method() == Some(method))
+            .cloned()
+            .collect()
+    }
+
+    /// Wait until a request or notification of the given method has been
+    /// received, returning the first one.
+    pub async fn wait_for(&self, method: &str, timeout: Duration) -> Result<RpcMessage> {
+        let mut changes = self.shared.received_count.subscribe();
+        let wait = async {
+            loop {
+                if let Some(message) = self.received_with_method(method).into_iter().next() {
+                    return message;
+                }
+                // The sender lives as long as the server
+                let _ = changes.changed().await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.map_err(|_| {
+            LspError::Other(format!(
+                "No {} message received within {:?}",
+                method, timeout
+            ))
+        })
+    }
+
+    /// Assert that a request or notification of the given method has been
+    /// received, returning the first one.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the methods received so far if there is no such message.
+    #[track_caller]
+    pub fn assert_received(&self, method: &str) -> RpcMessage {
+        let received = self.received();
+        match received
+            .iter()
+            .find(|message| message.method() == Some(method))
+        {
+            Some(message) => message.clone(),
+            None => {
+                let methods: Vec<_> = received.iter().filter_map(RpcMessage::method).collect();
+                panic!("Expected a {} message, received {:?}", method, methods);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_contains_matches_nested_fields() {
+        let params = json!({
+            "textDocument": {"uri": "file:///main.rs"},
+            "position": {"line": 1, "character": 4},
+        });
+        assert!(contains(&params, &json!({})));
+        assert!(contains(&params, &json!({"position": {"line": 1}})));
+        assert!(!contains(&params, &json!({"position": {"line": 2}})));
+        assert!(!contains(&params, &json!({"context": null})));
+        assert!(!contains(&json!(null), &json!({"position": {}})));
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let builder = MockServer::builder()
+            .on_request_matching("test", json!({"n": 1}), Reply::result("one"))
+            .on_request("test", Reply::result("any"))
+            .on_request(
+                "shutdown",
+                Reply::error(ResponseError::internal_error("no")),
+            );
+        let reply = |method, params| {
+            builder
+                .reply(&RequestMessage::with_params(1, method, params))
+                .outcome
+        };
+
+        assert_eq!(reply("test", json!({"n": 1})).unwrap(), json!("one"));
+        assert_eq!(reply("test", json!({"n": 2})).unwrap(), json!("any"));
+        assert!(reply("shutdown", json!(null)).is_err());
+        assert!(reply("initialize", json!({})).unwrap()["capabilities"].is_object());
+        let error = reply("unknown", json!(null)).unwrap_err();
+        assert_eq!(error.code, ResponseError::method_not_found("").code);
+    }
+}
@@ -1,16 +1,131 @@
 //! Transport layer for the Language Server Protocol.
 //!
 //! This module implements the base protocol as defined by the LSP specification,
-//! including header parsing, content handling, and message framing.
+//! including header parsing, content handling, and message framing. The
+//! [`MessageTransport`] trait abstracts over the framing, with [`Transport`]
+//! as its base protocol implementation.
 
-use crate::error::{LspError, Result};
+use crate::error::{Result, TransportError};
 use crate::types::RpcMessage;
+use bytes::BytesMut;
 use std::collections::HashMap;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::Decoder;
+
+pub mod channel;
+pub mod charset;
+pub mod codec;
+pub mod message_transport;
+pub mod replay;
+pub mod trace;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use channel::{pair, ChannelTransport};
+pub use charset::Charset;
+pub use codec::LspCodec;
+pub use message_transport::{MessageReceiver, MessageSender, MessageTransport};
+pub use replay::{Recording, Replay, ReplayConfig};
+pub use trace::{Direction, TraceFormat, Traced, Tracer};
 
 /// The default content type for LSP messages.
 pub const DEFAULT_CONTENT_TYPE: &str = "application/vscode-jsonrpc; charset=utf-8";
 
+/// How much spare room the reader keeps in its buffer for each read.
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// The default limit for the length of a header line in bytes.
+pub const DEFAULT_MAX_HEADER_LINE_LENGTH: usize = 8 * 1024;
+
+/// The default limit for the number of header fields of a message.
+pub const DEFAULT_MAX_HEADER_COUNT: usize = 32;
+
+/// The default limit for the content length of a message in bytes.
+pub const DEFAULT_MAX_CONTENT_LENGTH: usize = 64 * 1024 * 1024;
+
+/// How strictly the headers of incoming messages are parsed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Follow the base protocol: header lines end with `\r\n` and fields are
+    /// separated by `": "`.
+    #[default]
+    Strict,
+    /// Tolerate servers that do not quite follow the base protocol.
+    ///
+    /// Header lines may end with a bare `\n`, fields may omit the space after
+    /// the colon, and output that is not part of a header is logged and
+    /// skipped until a valid `Content-Length` appears.
+    Lenient,
+}
+
+/// Options for reading and writing messages.
+///
+/// Messages exceeding a limit are rejected with a [`TransportError`] before
+/// the offending data is buffered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportConfig {
+    /// How strictly headers are parsed.
+    pub parse_mode: ParseMode,
+    /// The charset outgoing RPC messages are encoded in. Incoming messages
+    /// are decoded according to their own `Content-Type`.
+    pub charset: Charset,
+    /// The maximum length of a header line in bytes, without its line ending.
+    pub max_header_line_length: usize,
+    /// The maximum number of header fields in a message.
+    pub max_header_count: usize,
+    /// The maximum Content-Length of a message in bytes.
+    pub max_content_length: usize,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            parse_mode: ParseMode::Strict,
+            charset: Charset::Utf8,
+            max_header_line_length: DEFAULT_MAX_HEADER_LINE_LENGTH,
+            max_header_count: DEFAULT_MAX_HEADER_COUNT,
+            max_content_length: DEFAULT_MAX_CONTENT_LENGTH,
+        }
+    }
+}
+
+impl TransportConfig {
+    /// A configuration with the default limits that parses headers leniently.
+    pub fn lenient() -> Self {
+        Self::default().with_parse_mode(ParseMode::Lenient)
+    }
+
+    /// Set how strictly headers are parsed.
+    pub fn with_parse_mode(mut self, parse_mode: ParseMode) -> Self {
+        self.parse_mode = parse_mode;
+        self
+    }
+
+    /// Set the charset outgoing RPC messages are encoded in.
+    pub fn with_charset(mut self, charset: Charset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    /// Set the maximum length of a header line.
+    pub fn with_max_header_line_length(mut self, length: usize) -> Self {
+        self.max_header_line_length = length;
+        self
+    }
+
+    /// Set the maximum number of header fields in a message.
+    pub fn with_max_header_count(mut self, count: usize) -> Self {
+        self.max_header_count = count;
+        self
+    }
+
+    /// Set the maximum Content-Length of a message.
+    pub fn with_max_content_length(mut self, length: usize) -> Self {
+        self.max_content_length = length;
+        self
+    }
+}
+
 /// Header fields for LSP messages.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MessageHeaders {
@@ -49,6 +164,8 @@ impl MessageHeaders {
 
     /// Get the character encoding from the content type.
     /// Returns "utf-8" by default, and also accepts "utf8" for backwards compatibility.
+    ///
+    /// See [`MessageHeaders::charset`] for the decoded form.
     pub fn get_encoding(&self) -> &str {
         if self.content_type.contains("charset=") {
             if let Some(charset_part) = self.content_type.split("charset=").nth(1) {
@@ -62,6 +179,12 @@ impl MessageHeaders {
         }
         "utf-8"
     }
+
+    /// Get the charset declared by the content type, or `None` if it is not
+    /// supported.
+    pub fn charset(&self) -> Option<Charset> {
+        Charset::from_name(self.get_encoding())
+    }
 }
 
 /// A complete LSP message with headers and content.
@@ -83,6 +206,20 @@ impl Message {
         }
     }
 
+    /// Create a new message whose content is sent in the given charset.
+    pub fn with_charset(content: impl Into<String>, charset: Charset) -> Self {
+        if charset == Charset::Utf8 {
+            return Self::new(content);
+        }
+        let content = content.into();
+        let content_type = format!("application/vscode-jsonrpc; charset={}", charset);
+
+        Self {
+            headers: MessageHeaders::with_content_type(charset.encoded_len(&content), content_type),
+            content,
+        }
+    }
+
     /// Create a message from an RPC message by serializing it to JSON.
     pub fn from_rpc_message(rpc_message: &RpcMessage) -> Result<Self> {
         let content = serde_json::to_string(rpc_message)?;
@@ -95,6 +232,9 @@ impl Message {
     }
 
     /// Serialize this message to bytes for transmission.
+    ///
+    /// The content is encoded in the charset declared by its headers, or in
+    /// UTF-8 if that charset is not supported.
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut result = Vec::new();
 
@@ -119,132 +259,166 @@ impl Message {
         result.extend_from_slice(b"\r\n");
 
         // Add content
-        result.extend_from_slice(self.content.as_bytes());
+        match self.headers.charset().unwrap_or_default() {
+            Charset::Utf8 => result.extend_from_slice(self.content.as_bytes()),
+            charset => result.extend_from_slice(&charset.encode(&self.content)),
+        }
 
         result
     }
 }
 
 /// Transport for reading and writing LSP messages.
+///
+/// A `Transport` bundles a [`TransportReader`] and a [`TransportWriter`]. Use
+/// [`Transport::split`] to obtain independent halves that can be driven from
+/// separate tasks, so that waiting for an incoming message never blocks an
+/// outgoing one.
 pub struct Transport<R, W> {
-    reader: R,
-    writer: W,
+    reader: TransportReader<R>,
+    writer: TransportWriter<W>,
 }
 
 impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Transport<R, W> {
     /// Create a new transport with the given reader and writer.
     pub fn new(reader: R, writer: W) -> Self {
-        Self { reader, writer }
+        Self::with_config(reader, writer, TransportConfig::default())
+    }
+
+    /// Create a new transport with the given reader, writer and configuration.
+    pub fn with_config(reader: R, writer: W, config: TransportConfig) -> Self {
+        Self {
+            writer: TransportWriter::with_charset(writer, config.charset),
+            reader: TransportReader::with_config(reader, config),
+        }
+    }
+
+    /// Split the transport into its reading and writing halves.
+    pub fn split(self) -> (TransportReader<R>, TransportWriter<W>) {
+        (self.reader, self.writer)
     }
 
     /// Read a complete message from the transport.
     pub async fn read_message(&mut self) -> Result<Message> {
-        let headers = self.read_headers().await?;
-        let content = self.read_content(&headers).await?;
-
-        Ok(Message { headers, content })
+        self.reader.read_message().await
     }
 
     /// Write a message to the transport.
     pub async fn write_message(&mut self, message: &Message) -> Result<()> {
-        let bytes = message.to_bytes();
-        self.writer.write_all(&bytes).await?;
-        self.writer.flush().await?;
-        Ok(())
+        self.writer.write_message(message).await
     }
 
     /// Write an RPC message to the transport.
     pub async fn write_rpc_message(&mut self, rpc_message: &RpcMessage) -> Result<()> {
-        let message = Message::from_rpc_message(rpc_message)?;
-        self.write_message(&message).await
+        self.writer.write_rpc_message(rpc_message).await
     }
+}
 
-    /// Read message headers from the transport.
-    async fn read_headers(&mut self) -> Result<MessageHeaders> {
-        let mut headers = HashMap::new();
-        let mut content_length = None;
-        let mut content_type = DEFAULT_CONTENT_TYPE.to_string();
-
-        loop {
-            let line = self.read_line().await?;
+/// The reading half of a [`Transport`].
+///
+/// Reads from the underlying stream go through an internal buffer, and the
+/// messages are framed by an [`LspCodec`].
+pub struct TransportReader<R> {
+    reader: R,
+    buffer: BytesMut,
+    codec: LspCodec,
+}
 
-            // Empty line indicates end of headers
-            if line.is_empty() {
-                break;
-            }
+impl<R: AsyncRead + Unpin> TransportReader<R> {
+    /// Create a new transport reader over the given stream.
+    pub fn new(reader: R) -> Self {
+        Self::with_config(reader, TransportConfig::default())
+    }
 
-            // Parse header field
-            if let Some((name, value)) = parse_header_field(&line)? {
-                match name.to_lowercase().as_str() {
-                    "content-length" => {
-                        content_length = Some(value.parse::<usize>().map_err(|_| {
-                            LspError::Transport(format!("Invalid Content-Length: {}", value))
-                        })?);
-                    }
-                    "content-type" => {
-                        content_type = value;
-                    }
-                    _ => {
-                        headers.insert(name, value);
-                    }
-                }
-            }
+    /// Create a new transport reader over the given stream with the given
+    /// configuration.
+    pub fn with_config(reader: R, config: TransportConfig) -> Self {
+        Self {
+            reader,
+            buffer: BytesMut::with_capacity(READ_BUFFER_SIZE),
+            codec: LspCodec::with_config(config),
         }
+    }
 
-        let content_length = content_length
-            .ok_or_else(|| LspError::Transport("Missing Content-Length header".to_string()))?;
+    /// Consume the transport reader, returning the underlying stream.
+    ///
+    /// Any data already buffered but not yet read as a message is lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
 
-        Ok(MessageHeaders {
-            content_length,
-            content_type,
-            additional: headers,
-        })
-    }
-
-    /// Read message content based on the headers.
-    async fn read_content(&mut self, headers: &MessageHeaders) -> Result<String> {
-        let mut buffer = vec![0; headers.content_length];
-        self.reader.read_exact(&mut buffer).await?;
-
-        // Validate encoding
-        let encoding = headers.get_encoding();
-        if encoding != "utf-8" {
-            return Err(LspError::Transport(format!(
-                "Unsupported encoding: {}",
-                encoding
-            )));
+    /// Read a complete message from the transport.
+    ///
+    /// If the next message is rejected with [`LspError::Transport`](crate::LspError::Transport), the
+    /// reader skips it, so the following call reads the message after it.
+    pub async fn read_message(&mut self) -> Result<Message> {
+        match self.next_message().await? {
+            Some(message) => Ok(message),
+            None => Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()),
         }
-
-        // Convert to string
-        String::from_utf8(buffer)
-            .map_err(|e| LspError::Transport(format!("Invalid UTF-8 content: {}", e)))
     }
 
-    /// Read a single line (ending with \r\n) from the transport.
-    async fn read_line(&mut self) -> Result<String> {
-        let mut line = Vec::new();
-        let mut prev_byte = 0u8;
-
+    /// Read the next message, or `None` if the stream ended between messages.
+    async fn next_message(&mut self) -> Result<Option<Message>> {
         loop {
-            let mut byte = [0u8; 1];
-            self.reader.read_exact(&mut byte).await?;
-            let byte = byte[0];
-
-            if byte == b'\n' && prev_byte == b'\r' {
-                // Remove the \r\n
-                line.pop();
-                break;
+            if let Some(message) = self.codec.decode(&mut self.buffer)? {
+                return Ok(Some(message));
+            }
+            self.buffer.reserve(READ_BUFFER_SIZE);
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return self.codec.decode_eof(&mut self.buffer);
             }
-
-            line.push(byte);
-            prev_byte = byte;
         }
+    }
+}
+
+/// The writing half of a [`Transport`].
+pub struct TransportWriter<W> {
+    writer: W,
+    charset: Charset,
+}
+
+impl<W: AsyncWrite + Unpin> TransportWriter<W> {
+    /// Create a new transport writer over the given stream.
+    pub fn new(writer: W) -> Self {
+        Self::with_charset(writer, Charset::Utf8)
+    }
+
+    /// Create a new transport writer that encodes RPC messages in the given
+    /// charset.
+    pub fn with_charset(writer: W, charset: Charset) -> Self {
+        Self { writer, charset }
+    }
+
+    /// Consume the transport writer, returning the underlying stream.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Write a message to the transport.
+    pub async fn write_message(&mut self, message: &Message) -> Result<()> {
+        let bytes = message.to_bytes();
+        self.writer.write_all(&bytes).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
 
-        String::from_utf8(line)
-            .map_err(|e| LspError::Transport(format!("Invalid UTF-8 in header: {}", e)))
+    /// Write an RPC message to the transport.
+    pub async fn write_rpc_message(&mut self, rpc_message: &RpcMessage) -> Result<()> {
+        let content = serde_json::to_string(rpc_message)?;
+        self.write_message(&Message::with_charset(content, self.charset))
+            .await
     }
 }
 
+/// Decode message content according to the encoding in its headers.
+fn decode_content(headers: &MessageHeaders, content: Vec<u8>) -> Result<String> {
+    let charset = headers.charset().ok_or_else(|| {
+        TransportError::Malformed(format!("Unsupported encoding: {}", headers.get_encoding()))
+    })?;
+    charset.decode(content)
+}
+
 /// Parse a header field line into name and value.
 fn parse_header_field(line: &str) -> Result<Option<(String, String)>> {
     if line.is_empty() {
@@ -254,16 +428,26 @@ fn parse_header_field(line: &str) -> Result<Option<(String, String)>> {
     if let Some((name, value)) = line.split_once(": ") {
         Ok(Some((name.trim().to_string(), value.trim().to_string())))
     } else {
-        Err(LspError::Transport(format!(
-            "Invalid header field: {}",
-            line
-        )))
+        Err(TransportError::Malformed(format!("Invalid header field: {}", line)).into())
     }
 }
 
+/// Parse a header field line leniently, allowing any whitespace around the
+/// colon. Returns `None` if the line does not look like a header field.
+fn parse_lenient_header_field(line: &str) -> Option<(String, String)> {
+    let (name, value) = line.split_once(':')?;
+    let name = name.trim();
+    let is_token = !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-');
+    is_token.then(|| (name.to_string(), value.trim().to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::error::LspError;
     use crate::types::jsonrpc::RequestMessage;
     use std::io::Cursor;
 
@@ -349,7 +533,7 @@ mod tests {
         transport.write_rpc_message(&rpc_message).await.unwrap();
 
         // Get the written bytes
-        let written_bytes = transport.writer.into_inner();
+        let written_bytes = transport.writer.into_inner().into_inner();
 
         // Create new transport to read it back
         let reader = Cursor::new(written_bytes);
@@ -361,6 +545,113 @@ mod tests {
         assert_eq!(parsed.method(), Some("initialize"));
     }
 
+    #[tokio::test]
+    async fn test_transport_split_halves() {
+        let request = RpcMessage::Request(RequestMessage::new(7, "test/split"));
+        let bytes = Message::from_rpc_message(&request).unwrap().to_bytes();
+
+        let transport = Transport::new(Cursor::new(bytes), Cursor::new(Vec::new()));
+        let (mut reader, mut writer) = transport.split();
+
+        writer.write_rpc_message(&request).await.unwrap();
+        let read_message = reader.read_message().await.unwrap();
+
+        assert_eq!(
+            read_message.parse_rpc_message().unwrap().method(),
+            Some("test/split")
+        );
+        let written = String::from_utf8(writer.into_inner().into_inner()).unwrap();
+        assert!(written.ends_with(&read_message.content));
+    }
+
+    /// A reader that counts how often it is polled for data.
+    struct CountingReader {
+        inner: Cursor<Vec<u8>>,
+        reads: usize,
+    }
+
+    impl AsyncRead for CountingReader {
+        fn poll_read(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            self.reads += 1;
+            std::pin::Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_headers_read_through_buffer() {
+        let mut message = Message::new(r#"{"jsonrpc":"2.0","method":"test"}"#);
+        message.headers = message.headers.add_header("X-Padding", "p".repeat(200));
+        let mut reader = TransportReader::new(CountingReader {
+            inner: Cursor::new(message.to_bytes()),
+            reads: 0,
+        });
+
+        let read = reader.read_message().await.unwrap();
+        assert_eq!(read.content, message.content);
+        assert!(reader.into_inner().reads <= 2);
+    }
+
+    #[tokio::test]
+    async fn test_framing_across_partial_reads() {
+        let first = Message::new(r#"{"jsonrpc":"2.0","method":"first"}"#).to_bytes();
+        let second = Message::new(r#"{"jsonrpc":"2.0","method":"second"}"#).to_bytes();
+        let stream = [first, second].concat();
+
+        // Split the stream in the middle of a header, at the header/body
+        // boundary and in the middle of a body
+        let mut builder = tokio_test::io::Builder::new();
+        for chunk in [&stream[..7], &stream[7..20], &stream[20..45], &stream[45..]] {
+            builder.read(chunk);
+        }
+        let mut reader = TransportReader::new(builder.build());
+
+        let first = reader.read_message().await.unwrap();
+        let second = reader.read_message().await.unwrap();
+        assert_eq!(first.parse_rpc_message().unwrap().method(), Some("first"));
+        assert_eq!(second.parse_rpc_message().unwrap().method(), Some("second"));
+    }
+
+    #[tokio::test]
+    async fn test_line_ending_without_carriage_return() {
+        // A bare \n does not end a header line, and EOF after it is an error
+        let mut reader = TransportReader::new(Cursor::new(b"Content-Length: 2\n".to_vec()));
+        assert!(matches!(
+            reader.read_message().await,
+            Err(LspError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read_in_other_charset() {
+        let request = RpcMessage::Request(RequestMessage::new(1, "test/ünïcode"));
+        let mut writer = TransportWriter::with_charset(Vec::new(), Charset::Utf16Le);
+        writer.write_rpc_message(&request).await.unwrap();
+        let written = writer.into_inner();
+        assert!(String::from_utf8_lossy(&written).contains("charset=utf-16le"));
+
+        let mut reader = TransportReader::new(Cursor::new(written));
+        let message = reader.read_message().await.unwrap();
+        assert_eq!(message.headers.charset(), Some(Charset::Utf16Le));
+        assert_eq!(
+            message.parse_rpc_message().unwrap().method(),
+            Some("test/ünïcode")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_charset() {
+        let input = b"Content-Length: 2\r\nContent-Type: text/plain; charset=koi8-r\r\n\r\n{}";
+        let mut reader = TransportReader::new(Cursor::new(input.to_vec()));
+        assert!(matches!(
+            reader.read_message().await,
+            Err(LspError::Transport(TransportError::Malformed(e))) if e.contains("koi8-r")
+        ));
+    }
+
     #[test]
     fn test_header_parsing() {
         assert_eq!(
@@ -377,4 +668,18 @@ mod tests {
 
         assert!(parse_header_field("InvalidHeader").is_err());
     }
+
+    #[test]
+    fn test_lenient_header_parsing() {
+        assert_eq!(
+            parse_lenient_header_field("Content-Length:123"),
+            Some(("Content-Length".to_string(), "123".to_string()))
+        );
+        assert_eq!(
+            parse_lenient_header_field("Content-Type : text/plain"),
+            Some(("Content-Type".to_string(), "text/plain".to_string()))
+        );
+        assert_eq!(parse_lenient_header_field("server starting"), None);
+        assert_eq!(parse_lenient_header_field("[info] ready: yes"), None);
+    }
 }
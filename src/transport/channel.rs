@@ -0,0 +1,154 @@
+//! In-memory transport passing messages over channels.
+//!
+//! [`pair`] connects two endpoints without serialization or framing, so a
+//! client can talk to a server running in the same process, such as a test
+//! double or an embedded language server.
+
+use super::{MessageReceiver, MessageSender, MessageTransport};
+use crate::error::{LspError, Result};
+use crate::types::RpcMessage;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::sync::mpsc;
+
+/// Create two connected endpoints. Messages sent on one are received by the
+/// other, in order.
+///
+/// ```
+/// use tokio_lsp::transport::{self, MessageReceiver, MessageSender};
+/// use tokio_lsp::types::{NotificationMessage, RpcMessage};
+///
+/// # #[tokio::main]
+/// # async fn main() -> tokio_lsp::Result<()> {
+/// let (mut client, mut server) = transport::pair();
+///
+/// let notification = RpcMessage::Notification(NotificationMessage::new("initialized"));
+/// client.send(&notification).await?;
+///
+/// let received = server.recv().await?.unwrap();
+/// assert_eq!(received.method(), Some("initialized"));
+/// # Ok(())
+/// # }
+/// ```
+pub fn pair() -> (ChannelTransport, ChannelTransport) {
+    let (a_sender, b_receiver) = mpsc::unbounded_channel();
+    let (b_sender, a_receiver) = mpsc::unbounded_channel();
+    (
+        ChannelTransport::new(a_sender, a_receiver),
+        ChannelTransport::new(b_sender, b_receiver),
+    )
+}
+
+/// One endpoint of an in-memory transport created by [`pair`].
+///
+/// Dropping an endpoint, or both of its halves, closes the connection: the
+/// other endpoint receives `None` once it has received every message sent
+/// before.
+pub struct ChannelTransport {
+    sender: ChannelSender,
+    receiver: ChannelReceiver,
+}
+
+impl ChannelTransport {
+    fn new(
+        sender: mpsc::UnboundedSender<RpcMessage>,
+        receiver: mpsc::UnboundedReceiver<RpcMessage>,
+    ) -> Self {
+        Self {
+            sender: ChannelSender { sender },
+            receiver: ChannelReceiver { receiver },
+        }
+    }
+}
+
+impl MessageSender for ChannelTransport {
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, Result<()>> {
+        self.sender.send(message)
+    }
+}
+
+impl MessageReceiver for ChannelTransport {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<RpcMessage>>> {
+        self.receiver.recv()
+    }
+}
+
+impl MessageTransport for ChannelTransport {
+    type Sender = ChannelSender;
+    type Receiver = ChannelReceiver;
+
+    fn split(self) -> (Self::Receiver, Self::Sender) {
+        (self.receiver, self.sender)
+    }
+}
+
+/// The sending half of a [`ChannelTransport`].
+///
+/// The sender can be cloned to send from several tasks.
+#[derive(Clone)]
+pub struct ChannelSender {
+    sender: mpsc::UnboundedSender<RpcMessage>,
+}
+
+impl MessageSender for ChannelSender {
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, Result<()>> {
+        let result = self
+            .sender
+            .send(message.clone())
+            .map_err(|_| LspError::Connection("The peer closed the connection".to_string()));
+        futures::future::ready(result).boxed()
+    }
+}
+
+/// The receiving half of a [`ChannelTransport`].
+pub struct ChannelReceiver {
+    receiver: mpsc::UnboundedReceiver<RpcMessage>,
+}
+
+impl MessageReceiver for ChannelReceiver {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<RpcMessage>>> {
+        async move { Ok(self.receiver.recv().await) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{NotificationMessage, RequestMessage};
+
+    #[tokio::test]
+    async fn test_pair_delivers_in_order_in_both_directions() {
+        let (mut a, mut b) = pair();
+        for method in ["first", "second"] {
+            let request = RpcMessage::Request(RequestMessage::new(1, method));
+            a.send(&request).await.unwrap();
+        }
+        let notification = RpcMessage::Notification(NotificationMessage::new("back"));
+        b.send(&notification).await.unwrap();
+
+        assert_eq!(b.recv().await.unwrap().unwrap().method(), Some("first"));
+        assert_eq!(b.recv().await.unwrap().unwrap().method(), Some("second"));
+        assert_eq!(a.recv().await.unwrap().unwrap().method(), Some("back"));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_endpoint_closes_connection() {
+        let (mut a, b) = pair();
+        let (receiver, sender) = b.split();
+        let mut second_sender = sender.clone();
+        drop(sender);
+
+        // The connection stays open while a clone of the sender is alive
+        let notification = RpcMessage::Notification(NotificationMessage::new("exit"));
+        second_sender.send(&notification).await.unwrap();
+        drop(second_sender);
+        assert!(a.recv().await.unwrap().is_some());
+        assert!(a.recv().await.unwrap().is_none());
+
+        drop(receiver);
+        assert!(matches!(
+            a.send(&notification).await,
+            Err(LspError::Connection(_))
+        ));
+    }
+}
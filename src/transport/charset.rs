@@ -0,0 +1,203 @@
+//! Character encodings for message content.
+//!
+//! The base protocol only requires UTF-8, but the `Content-Type` header may
+//! declare another charset. The encodings supported here are simple enough to
+//! be implemented without an external dependency.
+
+use crate::error::{Result, TransportError};
+use std::fmt;
+
+/// The characters 0x80..=0x9F of windows-1252. Undefined positions map to
+/// the C1 control character of the same value, as in the WHATWG encoding
+/// standard.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// Byte written for characters the target charset cannot represent.
+const REPLACEMENT_BYTE: u8 = b'?';
+
+/// A character encoding for message content.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Charset {
+    /// UTF-8, the charset required by the base protocol.
+    #[default]
+    Utf8,
+    /// UTF-16, little endian.
+    Utf16Le,
+    /// UTF-16, big endian.
+    Utf16Be,
+    /// ISO-8859-1.
+    Latin1,
+    /// windows-1252, a superset of the printable characters of ISO-8859-1.
+    Windows1252,
+}
+
+impl Charset {
+    /// Look up a charset by the name used in a `Content-Type` header.
+    ///
+    /// Names are matched case-insensitively. Returns `None` for charsets that
+    /// are not supported.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().trim_matches('"').to_ascii_lowercase();
+        match name.as_str() {
+            "utf-8" | "utf8" => Some(Charset::Utf8),
+            // Without a byte order mark, UTF-16 is big endian
+            "utf-16le" => Some(Charset::Utf16Le),
+            "utf-16be" | "utf-16" => Some(Charset::Utf16Be),
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => {
+                Some(Charset::Latin1)
+            }
+            "windows-1252" | "cp1252" => Some(Charset::Windows1252),
+            _ => None,
+        }
+    }
+
+    /// The name of the charset, as written in a `Content-Type` header.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Charset::Utf8 => "utf-8",
+            Charset::Utf16Le => "utf-16le",
+            Charset::Utf16Be => "utf-16be",
+            Charset::Latin1 => "iso-8859-1",
+            Charset::Windows1252 => "windows-1252",
+        }
+    }
+
+    /// Decode `bytes` into a string.
+    ///
+    /// A leading byte order mark is removed from UTF-8 and UTF-16 content.
+    pub fn decode(&self, bytes: Vec<u8>) -> Result<String> {
+        match self {
+            Charset::Utf8 => {
+                let mut content = String::from_utf8(bytes).map_err(|e| {
+                    TransportError::Malformed(format!("Invalid UTF-8 content: {}", e))
+                })?;
+                if content.starts_with('\u{FEFF}') {
+                    content.drain(..'\u{FEFF}'.len_utf8());
+                }
+                Ok(content)
+            }
+            Charset::Utf16Le => decode_utf16(&bytes, u16::from_le_bytes),
+            Charset::Utf16Be => decode_utf16(&bytes, u16::from_be_bytes),
+            Charset::Latin1 => Ok(bytes.into_iter().map(char::from).collect()),
+            Charset::Windows1252 => Ok(bytes
+                .into_iter()
+                .map(|byte| match byte {
+                    0x80..=0x9F => WINDOWS_1252_HIGH[usize::from(byte - 0x80)],
+                    _ => char::from(byte),
+                })
+                .collect()),
+        }
+    }
+
+    /// Encode `content` in this charset.
+    ///
+    /// Characters the charset cannot represent are written as `?`.
+    pub fn encode(&self, content: &str) -> Vec<u8> {
+        match self {
+            Charset::Utf8 => content.as_bytes().to_vec(),
+            Charset::Utf16Le => content.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Charset::Utf16Be => content.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Charset::Latin1 => content
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(REPLACEMENT_BYTE))
+                .collect(),
+            Charset::Windows1252 => content.chars().map(encode_windows_1252).collect(),
+        }
+    }
+
+    /// The length of `content` in bytes once encoded in this charset.
+    pub fn encoded_len(&self, content: &str) -> usize {
+        match self {
+            Charset::Utf8 => content.len(),
+            Charset::Utf16Le | Charset::Utf16Be => content.encode_utf16().count() * 2,
+            Charset::Latin1 | Charset::Windows1252 => content.chars().count(),
+        }
+    }
+}
+
+impl fmt::Display for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Result<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(TransportError::Malformed(
+            "Invalid UTF-16 content: odd number of bytes".to_string(),
+        )
+        .into());
+    }
+    let mut units = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]))
+        .peekable();
+    // Remove the byte order mark
+    units.next_if_eq(&0xFEFF);
+    char::decode_utf16(units)
+        .collect::<std::result::Result<String, _>>()
+        .map_err(|e| TransportError::Malformed(format!("Invalid UTF-16 content: {}", e)).into())
+}
+
+fn encode_windows_1252(c: char) -> u8 {
+    if let Some(position) = WINDOWS_1252_HIGH.iter().position(|&high| high == c) {
+        return 0x80 + position as u8;
+    }
+    match u8::try_from(c) {
+        Ok(byte) if !(0x80..=0x9F).contains(&byte) => byte,
+        _ => REPLACEMENT_BYTE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charset_names() {
+        assert_eq!(Charset::from_name("UTF-8"), Some(Charset::Utf8));
+        assert_eq!(Charset::from_name("utf8"), Some(Charset::Utf8));
+        assert_eq!(Charset::from_name("\"utf-16le\""), Some(Charset::Utf16Le));
+        assert_eq!(Charset::from_name("utf-16"), Some(Charset::Utf16Be));
+        assert_eq!(Charset::from_name("ISO-8859-1"), Some(Charset::Latin1));
+        assert_eq!(Charset::from_name("cp1252"), Some(Charset::Windows1252));
+        assert_eq!(Charset::from_name("shift_jis"), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let content = r#"{"text":"café – naïve"}"#;
+        for charset in [
+            Charset::Utf8,
+            Charset::Utf16Le,
+            Charset::Utf16Be,
+            Charset::Windows1252,
+        ] {
+            let encoded = charset.encode(content);
+            assert_eq!(encoded.len(), charset.encoded_len(content), "{}", charset);
+            assert_eq!(charset.decode(encoded).unwrap(), content, "{}", charset);
+        }
+    }
+
+    #[test]
+    fn test_single_byte_charsets() {
+        // 0x80 is the euro sign in windows-1252, but a control character in latin-1
+        assert_eq!(Charset::Windows1252.decode(vec![0x80, 0xE9]).unwrap(), "€é");
+        assert_eq!(Charset::Latin1.decode(vec![0x80, 0xE9]).unwrap(), "\u{80}é");
+        assert_eq!(Charset::Latin1.encode("€é"), vec![b'?', 0xE9]);
+    }
+
+    #[test]
+    fn test_utf16_byte_order_mark_and_errors() {
+        let bytes = vec![0xFF, 0xFE, b'{', 0, b'}', 0];
+        assert_eq!(Charset::Utf16Le.decode(bytes).unwrap(), "{}");
+        assert!(Charset::Utf16Le.decode(vec![b'{', 0, b'}']).is_err());
+        // An unpaired surrogate
+        assert!(Charset::Utf16Be.decode(vec![0xD8, 0x00]).is_err());
+    }
+}
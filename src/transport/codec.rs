@@ -0,0 +1,567 @@
+//! A [`tokio_util::codec`] implementation of the base protocol framing.
+//!
+//! [`LspCodec`] turns a byte stream into [`Message`]s and [`RpcMessage`]s into
+//! framed bytes, so the framing can be used with `Framed`, `FramedRead` and
+//! `FramedWrite` over any `AsyncRead`/`AsyncWrite`.
+
+use super::{
+    decode_content, parse_header_field, parse_lenient_header_field, Message, MessageHeaders,
+    ParseMode, TransportConfig, DEFAULT_CONTENT_TYPE,
+};
+use crate::error::{LspError, Result, TransportError};
+use crate::types::RpcMessage;
+use bytes::{Buf, BufMut, BytesMut};
+use std::collections::HashMap;
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Codec for LSP base protocol messages.
+///
+/// Headers are parsed according to the [`ParseMode`] of the codec's
+/// [`TransportConfig`], and messages are subject to its limits. A rejected message is reported once, and
+/// the decoder skips the rest of it, so decoding can continue with the next
+/// message. `FramedRead` returns `None` after an error; polling it again
+/// resumes reading.
+///
+/// ```
+/// use futures::{SinkExt, StreamExt};
+/// use tokio_lsp::transport::LspCodec;
+/// use tokio_lsp::types::{NotificationMessage, RpcMessage};
+/// use tokio_util::codec::{FramedRead, FramedWrite};
+///
+/// # #[tokio::main]
+/// # async fn main() -> tokio_lsp::Result<()> {
+/// let (client, server) = tokio::io::duplex(1024);
+/// let mut sink = FramedWrite::new(client, LspCodec::new());
+/// let mut stream = FramedRead::new(server, LspCodec::new());
+///
+/// let notification = RpcMessage::Notification(NotificationMessage::new("initialized"));
+/// sink.send(&notification).await?;
+///
+/// let message = stream.next().await.unwrap()?;
+/// assert_eq!(message.parse_rpc_message()?.method(), Some("initialized"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct LspCodec {
+    config: TransportConfig,
+    state: DecodeState,
+}
+
+#[derive(Debug)]
+enum DecodeState {
+    /// Reading header lines.
+    Headers(HeaderFields),
+    /// Discarding the rest of a header line that is too long.
+    SkipLine(HeaderFields),
+    /// Waiting for the content of a message with complete headers.
+    Content(MessageHeaders),
+    /// Discarding the remaining content of a rejected message.
+    SkipContent(usize),
+}
+
+impl Default for DecodeState {
+    fn default() -> Self {
+        DecodeState::Headers(HeaderFields::default())
+    }
+}
+
+/// Header fields parsed so far.
+#[derive(Debug, Default)]
+struct HeaderFields {
+    content_length: Option<usize>,
+    content_type: Option<String>,
+    additional: HashMap<String, String>,
+    /// The number of header lines read.
+    count: usize,
+    /// Set once the message has been rejected.
+    rejected: bool,
+}
+
+impl HeaderFields {
+    /// Mark the message as rejected, returning `error` unless it already was.
+    fn reject(&mut self, error: TransportError) -> Result<()> {
+        if self.rejected {
+            return Ok(());
+        }
+        self.rejected = true;
+        Err(error.into())
+    }
+
+    fn insert(&mut self, name: String, value: String) -> Result<()> {
+        match name.to_lowercase().as_str() {
+            "content-length" => match value.parse::<usize>() {
+                Ok(length) => self.content_length = Some(length),
+                Err(_) => {
+                    return self.reject(TransportError::Malformed(format!(
+                        "Invalid Content-Length: {}",
+                        value
+                    )));
+                }
+            },
+            // Only the length of a rejected message is of interest
+            _ if self.rejected => {}
+            "content-type" => {
+                self.content_type = Some(value);
+            }
+            _ => {
+                self.additional.insert(name, value);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<MessageHeaders> {
+        let content_length = self.content_length.ok_or_else(|| {
+            TransportError::Malformed("Missing Content-Length header".to_string())
+        })?;
+
+        Ok(MessageHeaders {
+            content_length,
+            content_type: self
+                .content_type
+                .unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string()),
+            additional: self.additional,
+        })
+    }
+}
+
+impl LspCodec {
+    /// Create a new codec with the default limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new codec with the given configuration.
+    pub fn with_config(config: TransportConfig) -> Self {
+        Self {
+            config,
+            state: DecodeState::default(),
+        }
+    }
+
+    /// Get the configuration of this codec.
+    pub fn config(&self) -> &TransportConfig {
+        &self.config
+    }
+
+    /// Handle a complete header line, without its line ending.
+    fn header_line(&mut self, line: &[u8]) -> Result<()> {
+        let lenient = self.config.parse_mode == ParseMode::Lenient;
+        let DecodeState::Headers(fields) = &mut self.state else {
+            unreachable!("decoder is reading headers");
+        };
+
+        // Empty line indicates end of headers
+        if line.is_empty() {
+            if lenient && fields.content_length.is_none() && !fields.rejected {
+                // Whatever came before was not the header of a message
+                if fields.count > 0 {
+                    log::warn!(
+                        "Skipping {} header fields without a Content-Length",
+                        fields.count
+                    );
+                }
+                *fields = HeaderFields::default();
+                return Ok(());
+            }
+            let fields = std::mem::take(fields);
+            if fields.rejected {
+                self.state = DecodeState::SkipContent(fields.content_length.unwrap_or(0));
+                return Ok(());
+            }
+            let headers = fields.finish()?;
+            if headers.content_length > self.config.max_content_length {
+                self.state = DecodeState::SkipContent(headers.content_length);
+                return Err(TransportError::ContentTooLarge {
+                    length: headers.content_length,
+                    limit: self.config.max_content_length,
+                }
+                .into());
+            }
+            self.state = DecodeState::Content(headers);
+            return Ok(());
+        }
+
+        if lenient {
+            return self.lenient_header_line(line);
+        }
+
+        fields.count += 1;
+        let counted = if fields.count > self.config.max_header_count {
+            fields.reject(TransportError::TooManyHeaders {
+                limit: self.config.max_header_count,
+            })
+        } else {
+            Ok(())
+        };
+        // The fields of a rejected message are still parsed for its length
+        let parsed = match std::str::from_utf8(line) {
+            Ok(line) => match parse_header_field(line) {
+                Ok(Some((name, value))) => fields.insert(name, value),
+                Ok(None) => Ok(()),
+                Err(LspError::Transport(error)) => fields.reject(error),
+                Err(e) => Err(e),
+            },
+            Err(e) => fields.reject(TransportError::Malformed(format!(
+                "Invalid UTF-8 in header: {}",
+                e
+            ))),
+        };
+        counted.and(parsed)
+    }
+
+    /// Handle a non-empty header line in lenient mode, skipping anything that
+    /// is not a header field.
+    fn lenient_header_line(&mut self, line: &[u8]) -> Result<()> {
+        let DecodeState::Headers(fields) = &mut self.state else {
+            unreachable!("decoder is reading headers");
+        };
+        let line = String::from_utf8_lossy(line);
+
+        // Stray output may run into the next header without a line ending
+        let mut field = line.as_ref();
+        if let Some(start) = line.to_ascii_lowercase().find("content-length:") {
+            if start > 0 {
+                log::warn!("Skipping non-header output: {:?}", &line[..start]);
+                field = &line[start..];
+            }
+        }
+
+        let Some((name, value)) = parse_lenient_header_field(field) else {
+            log::warn!("Skipping non-header output: {:?}", field);
+            return Ok(());
+        };
+        if name.eq_ignore_ascii_case("content-length") && value.parse::<usize>().is_err() {
+            log::warn!("Skipping invalid Content-Length: {:?}", value);
+            return Ok(());
+        }
+
+        fields.count += 1;
+        let counted = if fields.count > self.config.max_header_count {
+            fields.reject(TransportError::TooManyHeaders {
+                limit: self.config.max_header_count,
+            })
+        } else {
+            Ok(())
+        };
+        counted.and(fields.insert(name, value))
+    }
+}
+
+impl Decoder for LspCodec {
+    type Item = Message;
+    type Error = LspError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>> {
+        loop {
+            match &mut self.state {
+                DecodeState::Headers(fields) => {
+                    // A line may hold the limit plus its \r\n
+                    let limit = self.config.max_header_line_length + 2;
+                    let window = &src[..src.len().min(limit)];
+                    match find_line(self.config.parse_mode, window) {
+                        Some((length, end)) => {
+                            let line = src.split_to(end);
+                            self.header_line(&line[..length])?;
+                        }
+                        None if src.len() >= limit => {
+                            let mut fields = std::mem::take(fields);
+                            let result = if self.config.parse_mode == ParseMode::Lenient
+                                && fields.count == 0
+                            {
+                                // Not part of a message yet, so just noise
+                                log::warn!(
+                                    "Skipping non-header output: {:?}...",
+                                    String::from_utf8_lossy(&src[..limit])
+                                );
+                                Ok(())
+                            } else {
+                                fields.reject(TransportError::HeaderLineTooLong {
+                                    limit: self.config.max_header_line_length,
+                                })
+                            };
+                            self.state = DecodeState::SkipLine(fields);
+                            result?;
+                        }
+                        None => return Ok(None),
+                    }
+                }
+                DecodeState::SkipLine(fields) => match find_line(self.config.parse_mode, src) {
+                    Some((_, end)) => {
+                        src.advance(end);
+                        self.state = DecodeState::Headers(std::mem::take(fields));
+                    }
+                    None => {
+                        // Keep a trailing \r, it may start the line ending
+                        let keep = usize::from(src.ends_with(b"\r"));
+                        src.advance(src.len() - keep);
+                        return Ok(None);
+                    }
+                },
+                DecodeState::Content(headers) => {
+                    if src.len() < headers.content_length {
+                        src.reserve(headers.content_length - src.len());
+                        return Ok(None);
+                    }
+                    let content = src.split_to(headers.content_length);
+                    let headers = match std::mem::take(&mut self.state) {
+                        DecodeState::Content(headers) => headers,
+                        _ => unreachable!("decoder is reading content"),
+                    };
+                    let content = decode_content(&headers, content.to_vec())?;
+                    return Ok(Some(Message { headers, content }));
+                }
+                DecodeState::SkipContent(remaining) => {
+                    let skipped = (*remaining).min(src.len());
+                    src.advance(skipped);
+                    *remaining -= skipped;
+                    if *remaining > 0 {
+                        return Ok(None);
+                    }
+                    self.state = DecodeState::default();
+                }
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Message>> {
+        if let Some(message) = self.decode(src)? {
+            return Ok(Some(message));
+        }
+        match &self.state {
+            DecodeState::Headers(fields) if fields.count == 0 && src.is_empty() => Ok(None),
+            _ => {
+                self.state = DecodeState::default();
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "stream ended within a message",
+                )
+                .into())
+            }
+        }
+    }
+}
+
+impl Encoder<&Message> for LspCodec {
+    type Error = LspError;
+
+    fn encode(&mut self, message: &Message, dst: &mut BytesMut) -> Result<()> {
+        dst.put_slice(&message.to_bytes());
+        Ok(())
+    }
+}
+
+impl Encoder<&RpcMessage> for LspCodec {
+    type Error = LspError;
+
+    fn encode(&mut self, rpc_message: &RpcMessage, dst: &mut BytesMut) -> Result<()> {
+        let content = serde_json::to_string(rpc_message)?;
+        let message = Message::with_charset(content, self.config.charset);
+        self.encode(&message, dst)
+    }
+}
+
+/// Find the next line in `bytes`, returning the length of the line and the
+/// length including its line ending.
+fn find_line(parse_mode: ParseMode, bytes: &[u8]) -> Option<(usize, usize)> {
+    match parse_mode {
+        ParseMode::Strict => find_line_end(bytes).map(|end| (end, end + 2)),
+        ParseMode::Lenient => {
+            let end = bytes.iter().position(|&byte| byte == b'\n')?;
+            let length = if bytes[..end].ends_with(b"\r") {
+                end - 1
+            } else {
+                end
+            };
+            Some((length, end + 1))
+        }
+    }
+}
+
+/// Find the position of the first \r\n in `bytes`.
+fn find_line_end(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(2).position(|window| window == b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::RequestMessage;
+
+    #[test]
+    fn test_decode_waits_for_complete_frame() {
+        let request = RpcMessage::Request(RequestMessage::new(1, "test/method"));
+        let mut encoded = BytesMut::new();
+        LspCodec::new().encode(&request, &mut encoded).unwrap();
+
+        let mut codec = LspCodec::new();
+        let mut src = BytesMut::new();
+        for byte in &encoded[..encoded.len() - 1] {
+            src.put_u8(*byte);
+            assert!(codec.decode(&mut src).unwrap().is_none());
+        }
+        src.put_u8(encoded[encoded.len() - 1]);
+
+        let message = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(
+            message.parse_rpc_message().unwrap().method(),
+            Some("test/method")
+        );
+        assert!(src.is_empty());
+    }
+
+    fn decode_all(codec: &mut LspCodec, input: &str) -> Vec<Result<Message>> {
+        let mut src = BytesMut::from(input.as_bytes());
+        let mut results = Vec::new();
+        loop {
+            match codec.decode(&mut src) {
+                Ok(None) => return results,
+                Ok(Some(message)) => results.push(Ok(message)),
+                Err(e) => results.push(Err(e)),
+            }
+        }
+    }
+
+    fn transport_error(result: &Result<Message>) -> Option<&TransportError> {
+        match result {
+            Err(LspError::Transport(error)) => Some(error),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_decode_skips_malformed_frame() {
+        let mut codec = LspCodec::new();
+        let results = decode_all(
+            &mut codec,
+            "Bogus\r\nContent-Length: 5\r\n\r\nnopes\
+             Content-Length: 2\r\n\r\n{}",
+        );
+
+        assert_eq!(results.len(), 2);
+        assert!(matches!(
+            transport_error(&results[0]),
+            Some(TransportError::Malformed(_))
+        ));
+        assert_eq!(results[1].as_ref().unwrap().content, "{}");
+    }
+
+    #[test]
+    fn test_content_length_limit() {
+        let config = TransportConfig::default().with_max_content_length(4);
+        let mut codec = LspCodec::with_config(config);
+        let results = decode_all(
+            &mut codec,
+            "Content-Length: 5\r\n\r\n{\"a\"}Content-Length: 2\r\n\r\n{}",
+        );
+
+        assert_eq!(
+            transport_error(&results[0]),
+            Some(&TransportError::ContentTooLarge {
+                length: 5,
+                limit: 4
+            })
+        );
+        assert_eq!(results[1].as_ref().unwrap().content, "{}");
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_header_line_limit_without_line_ending() {
+        let config = TransportConfig::default().with_max_header_line_length(24);
+        let mut codec = LspCodec::with_config(config);
+        let mut src = BytesMut::from("X".repeat(64).as_bytes());
+
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(LspError::Transport(TransportError::HeaderLineTooLong {
+                limit: 24
+            }))
+        ));
+        // The line is discarded while it continues, and is not reported again
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(src.is_empty());
+        src.extend_from_slice(&[b'X'; 64]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+        assert!(src.is_empty());
+
+        src.extend_from_slice(b"\r\nContent-Length: 3\r\n\r\n123Content-Length: 2\r\n\r\n{}");
+        let message = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(message.content, "{}");
+    }
+
+    #[test]
+    fn test_header_count_limit() {
+        let config = TransportConfig::default().with_max_header_count(2);
+        let mut codec = LspCodec::with_config(config);
+        let results = decode_all(
+            &mut codec,
+            "A: 1\r\nB: 2\r\nContent-Length: 3\r\nC: 3\r\n\r\n123\
+             Content-Length: 2\r\nA: 1\r\n\r\n{}",
+        );
+
+        assert_eq!(
+            transport_error(&results[0]),
+            Some(&TransportError::TooManyHeaders { limit: 2 })
+        );
+        assert_eq!(results[1].as_ref().unwrap().content, "{}");
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    fn test_lenient_mode_skips_noise() {
+        let mut codec = LspCodec::with_config(TransportConfig::lenient());
+        let results = decode_all(
+            &mut codec,
+            "server v1.2 starting\n\
+             warning: no config: using defaults\n\
+             \n\
+             Content-Length:2\n\n{}\
+             loaded 3 crates Content-Length: 4\r\n\r\n[{}]",
+        );
+
+        let contents: Vec<_> = results
+            .into_iter()
+            .map(|result| result.unwrap().content)
+            .collect();
+        assert_eq!(contents, vec!["{}", "[{}]"]);
+    }
+
+    #[test]
+    fn test_lenient_mode_skips_long_noise() {
+        let config = TransportConfig::lenient().with_max_header_line_length(16);
+        let mut codec = LspCodec::with_config(config);
+        let input = format!("{}\nContent-Length: 2\n\n{{}}", "x".repeat(64));
+        let results = decode_all(&mut codec, &input);
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].as_ref().unwrap().content, "{}");
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_lenient_headers() {
+        let mut codec = LspCodec::new();
+        let results = decode_all(&mut codec, "Content-Length:2\r\n\r\n{}");
+        assert!(matches!(
+            transport_error(&results[0]),
+            Some(TransportError::Malformed(_))
+        ));
+
+        // A bare \n does not end a header line
+        let mut src = BytesMut::from("Content-Length: 2\n\n{}".as_bytes());
+        assert!(codec.decode(&mut src).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_decode_eof_within_message() {
+        let mut codec = LspCodec::new();
+        assert!(codec.decode_eof(&mut BytesMut::new()).unwrap().is_none());
+
+        let mut src = BytesMut::from("Content-Length: 10\r\n\r\n{}".as_bytes());
+        assert!(matches!(
+            codec.decode_eof(&mut src),
+            Err(LspError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+}
@@ -0,0 +1,137 @@
+//! Transports exchanging whole JSON-RPC messages.
+//!
+//! A [`Client`](crate::Client) talks to its server through a
+//! [`MessageTransport`], so it is not tied to the base protocol's
+//! `Content-Length` framing. [`Transport`] implements the base protocol over
+//! any `AsyncRead`/`AsyncWrite` pair; other framings, such as newline-delimited
+//! JSON or WebSocket frames, plug in by implementing these traits.
+
+use super::{Transport, TransportReader, TransportWriter};
+use crate::error::Result;
+use crate::types::RpcMessage;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// The sending half of a [`MessageTransport`].
+pub trait MessageSender: Send + 'static {
+    /// Send a message to the peer.
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, Result<()>>;
+}
+
+/// The receiving half of a [`MessageTransport`].
+pub trait MessageReceiver: Send + 'static {
+    /// Receive the next message from the peer.
+    ///
+    /// Returns `Ok(None)` once the peer has closed the connection. An
+    /// [`LspError::Transport`](crate::LspError::Transport) or
+    /// [`LspError::Json`](crate::LspError::Json) error means a single message
+    /// was rejected and skipped, so receiving may continue. Any other error
+    /// ends the connection.
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<RpcMessage>>>;
+}
+
+/// A bidirectional transport for JSON-RPC messages.
+///
+/// The transport can be used as a whole, or split into halves that are driven
+/// from separate tasks, so that waiting for an incoming message never blocks
+/// an outgoing one.
+pub trait MessageTransport: MessageSender + MessageReceiver {
+    /// The sending half of the transport.
+    type Sender: MessageSender;
+    /// The receiving half of the transport.
+    type Receiver: MessageReceiver;
+
+    /// Split the transport into its receiving and sending halves.
+    fn split(self) -> (Self::Receiver, Self::Sender);
+}
+
+impl<W: AsyncWrite + Unpin + Send + 'static> MessageSender for TransportWriter<W> {
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, Result<()>> {
+        self.write_rpc_message(message).boxed()
+    }
+}
+
+impl<R: AsyncRead + Unpin + Send + 'static> MessageReceiver for TransportReader<R> {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<RpcMessage>>> {
+        async move {
+            match self.next_message().await? {
+                Some(message) => Ok(Some(message.parse_rpc_message()?)),
+                None => Ok(None),
+            }
+        }
+        .boxed()
+    }
+}
+
+impl<R, W> MessageSender for Transport<R, W>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, Result<()>> {
+        self.writer.send(message)
+    }
+}
+
+impl<R, W> MessageReceiver for Transport<R, W>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<RpcMessage>>> {
+        self.reader.recv()
+    }
+}
+
+impl<R, W> MessageTransport for Transport<R, W>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
+    type Sender = TransportWriter<W>;
+    type Receiver = TransportReader<R>;
+
+    fn split(self) -> (Self::Receiver, Self::Sender) {
+        Transport::split(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LspError;
+    use crate::transport::Message;
+    use crate::types::NotificationMessage;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn test_receiver_distinguishes_close_from_errors() {
+        let valid = Message::new(r#"{"jsonrpc":"2.0","method":"first"}"#).to_bytes();
+        let invalid = Message::new("not json").to_bytes();
+        let mut reader = TransportReader::new(Cursor::new([valid, invalid].concat()));
+
+        let first = reader.recv().await.unwrap().unwrap();
+        assert_eq!(first.method(), Some("first"));
+        assert!(matches!(reader.recv().await, Err(LspError::Json(_))));
+        assert!(reader.recv().await.unwrap().is_none());
+
+        // A stream ending within a message is an error, not a close
+        let mut reader = TransportReader::new(Cursor::new(b"Content-Length: 5\r\n\r\n{".to_vec()));
+        assert!(matches!(reader.recv().await, Err(LspError::Io(_))));
+    }
+
+    #[tokio::test]
+    async fn test_transport_sends_through_trait() {
+        let mut transport = Transport::new(Cursor::new(Vec::new()), Vec::new());
+        let message = RpcMessage::Notification(NotificationMessage::new("initialized"));
+        MessageSender::send(&mut transport, &message).await.unwrap();
+
+        let (_, writer) = MessageTransport::split(transport);
+        let mut reader = TransportReader::new(Cursor::new(writer.into_inner()));
+        assert_eq!(
+            reader.recv().await.unwrap().unwrap().method(),
+            Some("initialized")
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! Recording language server sessions and replaying them in tests.
+//!
+//! A session is recorded by a client-side [`Tracer`] writing JSONL at the
+//! `verbose` level, as created by [`Recording::recorder`]. Loaded back as a
+//! [`Recording`], it drives a scripted fake server: messages the server sent
+//! are sent again, and messages the client sent are expected again, in the
+//! same order.
+//!
+//! Request IDs need not match the recording. The fake maps the ID of each
+//! request the client sends to the recorded one, and sends the recorded
+//! responses with the client's IDs.
+
+use super::trace::{Direction, TraceFormat, Tracer};
+use super::{pair, ChannelTransport, MessageReceiver, MessageSender, MessageTransport};
+use crate::error::{LspError, Result};
+use crate::types::notification::Cancel;
+use crate::types::{Id, Notification, RpcMessage, TraceValue};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tokio::task::JoinHandle;
+
+/// The messages exchanged in a recorded session, from the client's side.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    messages: Vec<(Direction, RpcMessage)>,
+}
+
+impl Recording {
+    /// Create a recording from messages in the order they were exchanged.
+    /// Outbound messages were sent by the client, inbound ones by the server.
+    pub fn new(messages: Vec<(Direction, RpcMessage)>) -> Self {
+        Self { messages }
+    }
+
+    /// Create a tracer recording a session to a file, for use with
+    /// [`ClientConfig::with_tracer`](crate::ClientConfig::with_tracer).
+    pub fn recorder(path: impl AsRef<Path>) -> Result<Tracer> {
+        Ok(Tracer::to_file(path, TraceFormat::Jsonl)?.with_trace(TraceValue::Verbose))
+    }
+
+    /// Load a recording from a file written by a [`Recording::recorder`].
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse a recording from a JSONL trace written at the `verbose` level.
+    pub fn parse(trace: &str) -> Result<Self> {
+        let mut messages = Vec::new();
+        for (number, line) in trace.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                LspError::Other(format!("Invalid trace line {}: {}", number + 1, reason))
+            };
+            let mut entry: serde_json::Value = serde_json::from_str(line)?;
+            let direction = match entry["direction"].as_str() {
+                Some("outbound") => Direction::Outbound,
+                Some("inbound") => Direction::Inbound,
+                _ => return Err(invalid("unknown direction")),
+            };
+            let message = match entry.get_mut("message") {
+                Some(message) => serde_json::from_value(message.take())?,
+                None => return Err(invalid("no message, was it traced at the verbose level?")),
+            };
+            messages.push((direction, message));
+        }
+        Ok(Self { messages })
+    }
+
+    /// Get the recorded messages in the order they were exchanged.
+    pub fn messages(&self) -> &[(Direction, RpcMessage)] {
+        &self.messages
+    }
+
+    /// Start a fake server replaying the recording, returning the transport a
+    /// client connects to and a handle on the replay.
+    pub fn replay(self) -> (ChannelTransport, Replay) {
+        self.replay_with_config(ReplayConfig::default())
+    }
+
+    /// Start a fake server replaying the recording with the given options.
+    pub fn replay_with_config(self, config: ReplayConfig) -> (ChannelTransport, Replay) {
+        let (client, server) = pair();
+        let task = tokio::spawn(run(self.messages, server, config));
+        (client, Replay { task })
+    }
+}
+
+/// Options for replaying a [`Recording`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReplayConfig {
+    /// Methods whose parameters may differ from the recording, such as
+    /// `initialize`, whose parameters include the client's process ID.
+    pub ignored_params: HashSet<String>,
+}
+
+impl ReplayConfig {
+    /// Accept any parameters for requests and notifications of the given
+    /// method.
+    pub fn with_ignored_params(mut self, method: impl Into<String>) -> Self {
+        self.ignored_params.insert(method.into());
+        self
+    }
+}
+
+/// A running replay of a [`Recording`].
+///
+/// The fake server stops sending once the whole recording has been replayed,
+/// and reports any message the client sends after that as a difference from
+/// the recording. It stops early if the client sends a message the recording
+/// does not expect, or closes the connection before the recording ends.
+pub struct Replay {
+    task: JoinHandle<Result<()>>,
+}
+
+impl Replay {
+    /// Wait for the replay to end, returning an error describing the first
+    /// difference from the recording, if any.
+    ///
+    /// The client must close the connection, for example by being dropped,
+    /// for the replay to end.
+    pub async fn finish(self) -> Result<()> {
+        self.task
+            .await
+            .map_err(|e| LspError::Other(format!("Replay failed: {}", e)))?
+    }
+}
+
+async fn run(
+    messages: Vec<(Direction, RpcMessage)>,
+    server: ChannelTransport,
+    config: ReplayConfig,
+) -> Result<()> {
+    let (mut receiver, mut sender) = server.split();
+    // Maps recorded request IDs to those the client actually used
+    let mut ids = HashMap::new();
+    for (index, (direction, message)) in messages.into_iter().enumerate() {
+        match direction {
+            Direction::Inbound => {
+                sender.send(&remap_response(message, &ids)).await?;
+            }
+            Direction::Outbound => {
+                let actual = receiver.recv().await?.ok_or_else(|| {
+                    LspError::Other(format!(
+                        "Client closed the connection, expected message {} of the recording: {}",
+                        index,
+                        to_json(&message)
+                    ))
+                })?;
+                let expected = expect(message, &actual, &mut ids, &config);
+                if to_json(&expected) != to_json(&actual) {
+                    return Err(LspError::Other(format!(
+                        "Message {} does not match the recording: expected {}, got {}",
+                        index,
+                        to_json(&expected),
+                        to_json(&actual)
+                    )));
+                }
+            }
+        }
+    }
+    // Close the connection from the server's side, but keep listening until the
+    // client closes its side, as anything it still sends was not recorded
+    drop(sender);
+    match receiver.recv().await? {
+        Some(extra) => Err(LspError::Other(format!(
+            "Client sent a message after the end of the recording: {}",
+            to_json(&extra)
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Adjust a recorded client message to what the client is expected to send
+/// instead of `actual`, recording the mapping of request IDs.
+fn expect(
+    mut expected: RpcMessage,
+    actual: &RpcMessage,
+    ids: &mut HashMap<Id, Id>,
+    config: &ReplayConfig,
+) -> RpcMessage {
+    match (&mut expected, actual) {
+        (RpcMessage::Request(expected), RpcMessage::Request(actual))
+            if expected.method == actual.method =>
+        {
+            ids.insert(expected.id.clone(), actual.id.clone());
+            expected.id = actual.id.clone();
+            if config.ignored_params.contains(&expected.method) {
+                expected.params = actual.params.clone();
+            }
+        }
+        (RpcMessage::Notification(expected), RpcMessage::Notification(actual))
+            if expected.method == actual.method =>
+        {
+            if config.ignored_params.contains(&expected.method) {
+                expected.params = actual.params.clone();
+            } else if expected.method == Cancel::METHOD {
+                if let Some(params) = &mut expected.params {
+                    remap_id(&mut params["id"], ids);
+                }
+            }
+        }
+        _ => {}
+    }
+    expected
+}
+
+/// Give a recorded response to a client request the client's request ID.
+fn remap_response(mut message: RpcMessage, ids: &HashMap<Id, Id>) -> RpcMessage {
+    if let RpcMessage::Response(response) = &mut message {
+        if let Some(id) = response.id.as_ref().and_then(|id| ids.get(id)) {
+            response.id = Some(id.clone());
+        }
+    }
+    message
+}
+
+fn remap_id(value: &mut serde_json::Value, ids: &HashMap<Id, Id>) {
+    let actual = serde_json::from_value(value.clone())
+        .ok()
+        .and_then(|id: Id| ids.get(&id));
+    if let Some(actual) = actual {
+        *value = serde_json::json!(actual);
+    }
+}
+
+fn to_json(message: &RpcMessage) -> serde_json::Value {
+    serde_json::to_value(message).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{NotificationMessage, RequestMessage, ResponseMessage};
+    use serde_json::json;
+
+    fn recording() -> Recording {
+        Recording::new(vec![
+            (
+                Direction::Outbound,
+                RpcMessage::Request(RequestMessage::new(41, "shutdown")),
+            ),
+            (
+                Direction::Inbound,
+                RpcMessage::Response(ResponseMessage::success(41, json!(null))),
+            ),
+            (
+                Direction::Outbound,
+                RpcMessage::Notification(NotificationMessage::new("exit")),
+            ),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_replay_remaps_request_ids() {
+        let (mut client, replay) = recording().replay();
+
+        client
+            .send(&RpcMessage::Request(RequestMessage::new(1, "shutdown")))
+            .await
+            .unwrap();
+        match client.recv().await.unwrap() {
+            Some(RpcMessage::Response(response)) => assert_eq!(response.id, Some(Id::Number(1))),
+            other => panic!("Expected response, got {:?}", other),
+        }
+        client
+            .send(&RpcMessage::Notification(NotificationMessage::new("exit")))
+            .await
+            .unwrap();
+
+        // The fake server closes the connection at the end of the recording
+        assert!(client.recv().await.unwrap().is_none());
+        drop(client);
+        replay.finish().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_replay_reports_messages_after_the_recording() {
+        let (mut client, replay) = Recording::new(vec![(
+            Direction::Outbound,
+            RpcMessage::Notification(NotificationMessage::new("exit")),
+        )])
+        .replay();
+        client
+            .send(&RpcMessage::Notification(NotificationMessage::new("exit")))
+            .await
+            .unwrap();
+        client
+            .send(&RpcMessage::Request(RequestMessage::new(2, "shutdown")))
+            .await
+            .unwrap();
+
+        let error = replay.finish().await.unwrap_err().to_string();
+        assert!(error.contains("after the end of the recording"));
+        assert!(error.contains(r#""method":"shutdown""#));
+    }
+
+    #[tokio::test]
+    async fn test_replay_reports_unexpected_messages() {
+        let (mut client, replay) = recording().replay();
+        client
+            .send(&RpcMessage::Request(RequestMessage::new(1, "initialize")))
+            .await
+            .unwrap();
+
+        let error = replay.finish().await.unwrap_err().to_string();
+        assert!(error.contains("Message 0 does not match the recording"));
+        assert!(error.contains(r#""method":"initialize""#));
+    }
+
+    #[tokio::test]
+    async fn test_replay_reports_early_close() {
+        let (client, replay) = recording().replay();
+        drop(client);
+
+        let error = replay.finish().await.unwrap_err().to_string();
+        assert!(error.contains("Client closed the connection"));
+    }
+
+    #[test]
+    fn test_parse_requires_verbose_trace() {
+        let trace = concat!(
+            r#"{"direction":"outbound","message":{"jsonrpc":"2.0","method":"exit"}}"#,
+            "\n\n",
+            r#"{"direction":"inbound","type":"notification","method":"exit"}"#,
+        );
+        let error = Recording::parse(trace).unwrap_err().to_string();
+        assert!(error.contains("Invalid trace line 3"));
+
+        let recording = Recording::parse(trace.lines().next().unwrap()).unwrap();
+        assert_eq!(recording.messages().len(), 1);
+        assert_eq!(recording.messages()[0].0, Direction::Outbound);
+    }
+}
@@ -0,0 +1,578 @@
+//! Wire-level tracing of the messages passing through a transport.
+//!
+//! A [`Tracer`] records every message sent or received through a [`Traced`]
+//! transport, together with its timestamp, direction, method, ID and, for
+//! responses, the latency of the request it answers. The trace is written
+//! either in the format of VS Code's trace output, which the LSP Inspector
+//! reads, or as one JSON object per line.
+//!
+//! How much is recorded follows the [`TraceValue`] of the tracer, which can
+//! be changed at any time: `off` records nothing, `messages` records one line
+//! per message, and `verbose` adds the parameters or result of each message.
+
+use super::{MessageReceiver, MessageSender, MessageTransport};
+use crate::error::Result;
+use crate::types::notification::Cancel;
+use crate::types::{CancelParams, Id, Notification, RpcMessage, TraceValue};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The number of requests awaiting a response a [`Tracer`] keeps track of.
+/// Beyond this, the oldest are forgotten and their responses traced without
+/// latency.
+const MAX_PENDING_REQUESTS: usize = 1024;
+
+/// The format a [`Tracer`] writes its trace in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// The text format of VS Code's trace output, as read by the LSP
+    /// Inspector. Times of day are given in UTC.
+    #[default]
+    Inspector,
+    /// One JSON object per line, with the timestamp in milliseconds since the
+    /// Unix epoch.
+    Jsonl,
+}
+
+/// The direction a traced message travelled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The message was sent to the peer.
+    Outbound,
+    /// The message was received from the peer.
+    Inbound,
+}
+
+impl Direction {
+    fn opposite(self) -> Self {
+        match self {
+            Direction::Outbound => Direction::Inbound,
+            Direction::Inbound => Direction::Outbound,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::Outbound => "outbound",
+            Direction::Inbound => "inbound",
+        }
+    }
+}
+
+/// Records the messages passing through [`Traced`] transports.
+///
+/// Clones share the same output and trace level, so one tracer can be kept to
+/// change the level while its clones are in use by a transport.
+///
+/// The output is not flushed after each message. It is flushed by
+/// [`Tracer::flush`], when a [`Client`](crate::Client) loses its connection,
+/// and when the last clone is dropped.
+#[derive(Clone)]
+pub struct Tracer {
+    format: TraceFormat,
+    state: Arc<Mutex<TracerState>>,
+}
+
+struct TracerState {
+    trace: TraceValue,
+    output: Box<dyn Write + Send>,
+    /// Requests awaiting a response, keyed by the direction they were sent
+    /// in, with their method and the time they were seen.
+    pending: HashMap<(Direction, Id), (String, Instant)>,
+}
+
+impl Tracer {
+    /// Create a tracer writing to the given output, tracing at the
+    /// `messages` level.
+    pub fn new(output: impl Write + Send + 'static, format: TraceFormat) -> Self {
+        Self {
+            format,
+            state: Arc::new(Mutex::new(TracerState {
+                trace: TraceValue::Messages,
+                output: Box::new(output),
+                pending: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Create a tracer writing to a file through a buffer, replacing any
+    /// existing content.
+    pub fn to_file(path: impl AsRef<Path>, format: TraceFormat) -> Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self::new(BufWriter::new(file), format))
+    }
+
+    /// Set the initial trace level.
+    pub fn with_trace(self, trace: TraceValue) -> Self {
+        self.set_trace(trace);
+        self
+    }
+
+    /// Get the format the trace is written in.
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// Get the current trace level.
+    pub fn trace(&self) -> TraceValue {
+        self.lock().trace
+    }
+
+    /// Change the trace level. Turning tracing off forgets the requests
+    /// awaiting a response, so their responses are traced without latency if
+    /// tracing is turned on again.
+    pub fn set_trace(&self, trace: TraceValue) {
+        let mut state = self.lock();
+        state.trace = trace;
+        if trace == TraceValue::Off {
+            state.pending.clear();
+        }
+    }
+
+    /// Write out any trace buffered by the output.
+    pub fn flush(&self) -> Result<()> {
+        Ok(self.lock().output.flush()?)
+    }
+
+    /// Record a message travelling in the given direction.
+    pub fn record(&self, direction: Direction, message: &RpcMessage) {
+        let mut state = self.lock();
+        if state.trace == TraceValue::Off {
+            return;
+        }
+        let now = Instant::now();
+        let request = match message {
+            RpcMessage::Request(request) => {
+                state.track(direction, request.id.clone(), request.method.clone(), now);
+                None
+            }
+            RpcMessage::Response(response) => response
+                .id
+                .as_ref()
+                .and_then(|id| state.pending.remove(&(direction.opposite(), id.clone())))
+                .map(|(method, sent)| (method, now.duration_since(sent))),
+            RpcMessage::Notification(notification) => {
+                // A cancelled request may never be answered
+                if notification.method == Cancel::METHOD {
+                    let params = notification.params.clone().unwrap_or_default();
+                    if let Ok(CancelParams { id }) = serde_json::from_value(params) {
+                        state.pending.remove(&(direction, id));
+                    }
+                }
+                None
+            }
+        };
+
+        let entry = Entry {
+            timestamp: SystemTime::now(),
+            direction,
+            message,
+            request,
+            verbose: state.trace == TraceValue::Verbose,
+        };
+        let text = match self.format {
+            TraceFormat::Inspector => entry.to_inspector(),
+            TraceFormat::Jsonl => entry.to_jsonl(),
+        };
+        if let Err(e) = state.output.write_all(text.as_bytes()) {
+            log::warn!("Failed to write message trace: {}", e);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TracerState> {
+        // A panic while writing leaves the state usable
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl TracerState {
+    /// Track a request awaiting a response, forgetting the oldest one if
+    /// too many are awaiting responses already.
+    fn track(&mut self, direction: Direction, id: Id, method: String, now: Instant) {
+        if self.pending.len() >= MAX_PENDING_REQUESTS {
+            let oldest = self
+                .pending
+                .iter()
+                .min_by_key(|(_, (_, seen))| *seen)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.pending.remove(&oldest);
+            }
+        }
+        self.pending.insert((direction, id), (method, now));
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tracer")
+            .field("format", &self.format)
+            .field("trace", &self.trace())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Tracers are equal if they share the same output.
+impl PartialEq for Tracer {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl Eq for Tracer {}
+
+/// A traced message about to be written out.
+struct Entry<'a> {
+    timestamp: SystemTime,
+    direction: Direction,
+    message: &'a RpcMessage,
+    /// The method and latency of the request a response answers, if known.
+    request: Option<(String, Duration)>,
+    verbose: bool,
+}
+
+impl Entry<'_> {
+    fn to_inspector(&self) -> String {
+        let verb = match self.direction {
+            Direction::Outbound => "Sending",
+            Direction::Inbound => "Received",
+        };
+        let (line, data) = match self.message {
+            RpcMessage::Request(request) => (
+                format!("{} request '{} - ({})'.", verb, request.method, request.id),
+                params_data(request.params.as_ref()),
+            ),
+            RpcMessage::Notification(notification) => (
+                format!("{} notification '{}'.", verb, notification.method),
+                params_data(notification.params.as_ref()),
+            ),
+            RpcMessage::Response(response) => {
+                let id = response
+                    .id
+                    .as_ref()
+                    .map_or_else(|| "null".to_string(), Id::to_string);
+                let mut line = match (&self.request, self.direction) {
+                    (Some((method, latency)), Direction::Inbound) => format!(
+                        "Received response '{} - ({})' in {}ms.",
+                        method,
+                        id,
+                        latency.as_millis()
+                    ),
+                    (Some((method, latency)), Direction::Outbound) => format!(
+                        "Sending response '{} - ({})'. Processing request took {}ms",
+                        method,
+                        id,
+                        latency.as_millis()
+                    ),
+                    (None, _) => format!("{} response '{}'.", verb, id),
+                };
+                if let Some(error) = &response.error {
+                    line.push_str(&format!(
+                        " Request failed: {} ({}).",
+                        error.message, error.code
+                    ));
+                }
+                let data = match &response.result {
+                    Some(result) if !result.is_null() => format!("Result: {}", pretty(result)),
+                    _ => "No result returned.".to_string(),
+                };
+                (line, data)
+            }
+        };
+
+        if self.verbose {
+            format!(
+                "[Trace - {}] {}\n{}\n\n\n",
+                time_of_day(self.timestamp),
+                line,
+                data
+            )
+        } else {
+            format!("[Trace - {}] {}\n", time_of_day(self.timestamp), line)
+        }
+    }
+
+    fn to_jsonl(&self) -> String {
+        let (kind, method) = match self.message {
+            RpcMessage::Request(request) => ("request", Some(request.method.as_str())),
+            RpcMessage::Notification(notification) => {
+                ("notification", Some(notification.method.as_str()))
+            }
+            RpcMessage::Response(_) => (
+                "response",
+                self.request.as_ref().map(|(method, _)| method.as_str()),
+            ),
+        };
+        let timestamp = self
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        let mut entry = json!({
+            "timestamp": timestamp,
+            "direction": self.direction.as_str(),
+            "type": kind,
+            "method": method,
+            "id": self.message.id(),
+        });
+        if let Some((_, latency)) = &self.request {
+            entry["latencyMs"] = json!(latency.as_millis() as u64);
+        }
+        if self.verbose {
+            entry["message"] = json!(self.message);
+        }
+        format!("{}\n", entry)
+    }
+}
+
+fn params_data(params: Option<&serde_json::Value>) -> String {
+    match params {
+        Some(params) => format!("Params: {}", pretty(params)),
+        None => "No parameters provided.".to_string(),
+    }
+}
+
+fn pretty(value: &serde_json::Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+}
+
+/// Format the UTC time of day like `3:04:05 PM`.
+fn time_of_day(timestamp: SystemTime) -> String {
+    let seconds = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        % (24 * 60 * 60);
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    let period = if hours < 12 { "AM" } else { "PM" };
+    let hours = match hours % 12 {
+        0 => 12,
+        h => h,
+    };
+    format!("{}:{:02}:{:02} {}", hours, minutes, seconds, period)
+}
+
+/// A transport, or half of one, whose messages are recorded by a [`Tracer`].
+///
+/// Outgoing messages are recorded just before they are sent, so a response
+/// can never be recorded ahead of its request.
+pub struct Traced<T> {
+    inner: T,
+    tracer: Tracer,
+}
+
+impl<T> Traced<T> {
+    /// Trace the messages passing through the given transport.
+    pub fn new(inner: T, tracer: Tracer) -> Self {
+        Self { inner, tracer }
+    }
+
+    /// Get the tracer recording the messages.
+    pub fn tracer(&self) -> &Tracer {
+        &self.tracer
+    }
+
+    /// Consume the wrapper, returning the underlying transport.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: MessageSender> MessageSender for Traced<T> {
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, Result<()>> {
+        self.tracer.record(Direction::Outbound, message);
+        self.inner.send(message)
+    }
+}
+
+impl<T: MessageReceiver> MessageReceiver for Traced<T> {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<RpcMessage>>> {
+        async move {
+            let message = self.inner.recv().await?;
+            if let Some(message) = &message {
+                self.tracer.record(Direction::Inbound, message);
+            }
+            Ok(message)
+        }
+        .boxed()
+    }
+}
+
+impl<T: MessageTransport> MessageTransport for Traced<T> {
+    type Sender = Traced<T::Sender>;
+    type Receiver = Traced<T::Receiver>;
+
+    fn split(self) -> (Self::Receiver, Self::Sender) {
+        let (receiver, sender) = self.inner.split();
+        (
+            Traced::new(receiver, self.tracer.clone()),
+            Traced::new(sender, self.tracer),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ResponseError;
+    use crate::types::{NotificationMessage, RequestMessage, ResponseMessage};
+
+    /// An output that can be read while the tracer holds it.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn request() -> RpcMessage {
+        RpcMessage::Request(RequestMessage::with_params(
+            1,
+            "textDocument/hover",
+            json!({"position": {"line": 0, "character": 4}}),
+        ))
+    }
+
+    #[test]
+    fn test_inspector_format() {
+        let output = Output::default();
+        let tracer = Tracer::new(output.clone(), TraceFormat::Inspector);
+
+        tracer.record(Direction::Outbound, &request());
+        let response = ResponseMessage::success(1, json!({"contents": "fn main()"}));
+        tracer.record(Direction::Inbound, &RpcMessage::Response(response));
+
+        let lines: Vec<_> = output.text().lines().map(str::to_string).collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("[Trace - "));
+        assert!(lines[0].ends_with("] Sending request 'textDocument/hover - (1)'."));
+        assert!(lines[1].contains("] Received response 'textDocument/hover - (1)' in "));
+        assert!(lines[1].ends_with("ms."));
+    }
+
+    #[test]
+    fn test_verbose_inspector_format_includes_data() {
+        let output = Output::default();
+        let tracer =
+            Tracer::new(output.clone(), TraceFormat::Inspector).with_trace(TraceValue::Verbose);
+
+        tracer.record(
+            Direction::Inbound,
+            &RpcMessage::Notification(NotificationMessage::new("initialized")),
+        );
+        tracer.record(Direction::Inbound, &request());
+        let error = ResponseError::new(-32601, "Unknown method");
+        tracer.record(
+            Direction::Outbound,
+            &RpcMessage::Response(ResponseMessage::error(Some(1.into()), error)),
+        );
+
+        let text = output.text();
+        assert!(text.contains("Received notification 'initialized'.\nNo parameters provided.\n"));
+        assert!(text.contains("Received request 'textDocument/hover - (1)'.\nParams: {\n"));
+        assert!(
+            text.contains("Sending response 'textDocument/hover - (1)'. Processing request took ")
+        );
+        assert!(text.contains(" Request failed: Unknown method (-32601).\nNo result returned.\n"));
+    }
+
+    #[test]
+    fn test_jsonl_format() {
+        let output = Output::default();
+        let tracer = Tracer::new(output.clone(), TraceFormat::Jsonl);
+
+        tracer.record(Direction::Inbound, &request());
+        tracer.record(
+            Direction::Outbound,
+            &RpcMessage::Response(ResponseMessage::success(1, json!(null))),
+        );
+        // An unknown response has no method or latency
+        tracer.record(
+            Direction::Inbound,
+            &RpcMessage::Response(ResponseMessage::success(7, json!(null))),
+        );
+
+        let entries: Vec<serde_json::Value> = output
+            .text()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0]["direction"], "inbound");
+        assert_eq!(entries[0]["type"], "request");
+        assert_eq!(entries[0]["method"], "textDocument/hover");
+        assert_eq!(entries[0]["id"], 1);
+        assert!(entries[0]["timestamp"].as_u64().unwrap() > 0);
+        assert!(entries[0].get("message").is_none());
+        assert_eq!(entries[1]["direction"], "outbound");
+        assert_eq!(entries[1]["method"], "textDocument/hover");
+        assert!(entries[1]["latencyMs"].is_u64());
+        assert!(entries[2]["method"].is_null());
+        assert!(entries[2].get("latencyMs").is_none());
+    }
+
+    #[test]
+    fn test_trace_level_can_change_at_runtime() {
+        let output = Output::default();
+        let tracer = Tracer::new(output.clone(), TraceFormat::Jsonl).with_trace(TraceValue::Off);
+
+        tracer.record(Direction::Outbound, &request());
+        assert!(output.text().is_empty());
+
+        // The level is shared between clones
+        tracer.clone().set_trace(TraceValue::Verbose);
+        assert_eq!(tracer.trace(), TraceValue::Verbose);
+        tracer.record(Direction::Outbound, &request());
+        let entry: serde_json::Value = serde_json::from_str(output.text().trim()).unwrap();
+        assert_eq!(entry["message"]["params"]["position"]["character"], 4);
+    }
+
+    #[test]
+    fn test_unanswered_requests_are_forgotten() {
+        let output = Output::default();
+        let tracer = Tracer::new(output.clone(), TraceFormat::Jsonl);
+
+        tracer.record(Direction::Outbound, &request());
+        let cancel = NotificationMessage::with_params(Cancel::METHOD, json!({"id": 1}));
+        tracer.record(Direction::Outbound, &RpcMessage::Notification(cancel));
+        assert!(tracer.lock().pending.is_empty());
+
+        for id in 0..MAX_PENDING_REQUESTS as i64 + 10 {
+            let request = RequestMessage::new(id, "workspace/symbol");
+            tracer.record(Direction::Inbound, &RpcMessage::Request(request));
+        }
+        let state = tracer.lock();
+        assert_eq!(state.pending.len(), MAX_PENDING_REQUESTS);
+        let newest = Id::Number(MAX_PENDING_REQUESTS as i64 + 9);
+        assert!(state.pending.contains_key(&(Direction::Inbound, newest)));
+    }
+
+    #[test]
+    fn test_time_of_day() {
+        let at = |seconds| UNIX_EPOCH + Duration::from_secs(seconds);
+        assert_eq!(time_of_day(at(0)), "12:00:00 AM");
+        assert_eq!(time_of_day(at(13 * 3600 + 4 * 60 + 5)), "1:04:05 PM");
+        assert_eq!(time_of_day(at(86400 + 12 * 3600)), "12:00:00 PM");
+    }
+}
@@ -0,0 +1,177 @@
+//! WebSocket transport, available with the `websocket` feature.
+//!
+//! WebSocket bridges carry one JSON-RPC message per text frame, without the
+//! base protocol headers. [`WebSocketTransport`] implements
+//! [`MessageTransport`] for such a connection, so it plugs into
+//! [`Client::with_transport`](crate::Client::with_transport).
+
+use super::{MessageReceiver, MessageSender, MessageTransport};
+use crate::error::Result;
+use crate::types::RpcMessage;
+use futures::future::BoxFuture;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt};
+use std::io;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+pub use tokio_tungstenite::WebSocketStream;
+
+/// A transport sending one JSON-RPC message per WebSocket text frame.
+///
+/// Binary frames are accepted as well, and a close frame ends the connection.
+pub struct WebSocketTransport<S> {
+    stream: WebSocketStream<S>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> WebSocketTransport<S> {
+    /// Create a transport over an established WebSocket connection.
+    pub fn new(stream: WebSocketStream<S>) -> Self {
+        Self { stream }
+    }
+
+    /// Consume the transport, returning the underlying connection.
+    pub fn into_inner(self) -> WebSocketStream<S> {
+        self.stream
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> MessageSender for WebSocketTransport<S> {
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, Result<()>> {
+        send_frame(&mut self.stream, message).boxed()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> MessageReceiver for WebSocketTransport<S> {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<RpcMessage>>> {
+        receive_frame(&mut self.stream).boxed()
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> MessageTransport
+    for WebSocketTransport<S>
+{
+    type Sender = WebSocketSender<S>;
+    type Receiver = WebSocketReceiver<S>;
+
+    fn split(self) -> (Self::Receiver, Self::Sender) {
+        let (sink, stream) = self.stream.split();
+        (WebSocketReceiver { stream }, WebSocketSender { sink })
+    }
+}
+
+/// The sending half of a [`WebSocketTransport`].
+pub struct WebSocketSender<S> {
+    sink: SplitSink<WebSocketStream<S>, WsMessage>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> MessageSender for WebSocketSender<S> {
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, Result<()>> {
+        send_frame(&mut self.sink, message).boxed()
+    }
+}
+
+/// The receiving half of a [`WebSocketTransport`].
+pub struct WebSocketReceiver<S> {
+    stream: SplitStream<WebSocketStream<S>>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send + 'static> MessageReceiver for WebSocketReceiver<S> {
+    fn recv(&mut self) -> BoxFuture<'_, Result<Option<RpcMessage>>> {
+        receive_frame(&mut self.stream).boxed()
+    }
+}
+
+async fn send_frame<S>(sink: &mut S, message: &RpcMessage) -> Result<()>
+where
+    S: Sink<WsMessage, Error = WsError> + Unpin,
+{
+    let text = serde_json::to_string(message)?;
+    sink.send(WsMessage::Text(text)).await.map_err(io_error)?;
+    Ok(())
+}
+
+async fn receive_frame<S>(stream: &mut S) -> Result<Option<RpcMessage>>
+where
+    S: Stream<Item = std::result::Result<WsMessage, WsError>> + Unpin,
+{
+    loop {
+        match stream.next().await {
+            Some(Ok(WsMessage::Text(text))) => return Ok(Some(serde_json::from_str(&text)?)),
+            Some(Ok(WsMessage::Binary(data))) => return Ok(Some(serde_json::from_slice(&data)?)),
+            // Pings are answered by the connection itself
+            Some(Ok(WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_))) => {}
+            Some(Ok(WsMessage::Close(_))) | None => return Ok(None),
+            Some(Err(e)) => return Err(io_error(e).into()),
+        }
+    }
+}
+
+/// Convert a WebSocket error into an IO error, keeping the original IO error
+/// if there is one.
+pub(crate) fn io_error(error: WsError) -> io::Error {
+    match error {
+        WsError::Io(e) => e,
+        WsError::ConnectionClosed | WsError::AlreadyClosed => {
+            io::Error::new(io::ErrorKind::BrokenPipe, error)
+        }
+        e => io::Error::other(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LspError;
+    use crate::types::NotificationMessage;
+    use tokio_tungstenite::tungstenite::protocol::Role;
+
+    async fn connected() -> (
+        WebSocketTransport<tokio::io::DuplexStream>,
+        WebSocketStream<tokio::io::DuplexStream>,
+    ) {
+        let (a, b) = tokio::io::duplex(64 * 1024);
+        (
+            WebSocketTransport::new(WebSocketStream::from_raw_socket(a, Role::Client, None).await),
+            WebSocketStream::from_raw_socket(b, Role::Server, None).await,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_frames_are_received_as_messages() {
+        let (transport, mut peer) = connected().await;
+        let (mut receiver, _sender) = transport.split();
+
+        let message = r#"{"jsonrpc":"2.0","method":"initialized"}"#;
+        peer.send(WsMessage::Text(message.to_string()))
+            .await
+            .unwrap();
+        peer.send(WsMessage::Ping(vec![1])).await.unwrap();
+        peer.send(WsMessage::Text("not json".to_string()))
+            .await
+            .unwrap();
+        peer.send(WsMessage::Binary(message.as_bytes().to_vec()))
+            .await
+            .unwrap();
+        peer.close(None).await.unwrap();
+
+        let first = receiver.recv().await.unwrap().unwrap();
+        assert_eq!(first.method(), Some("initialized"));
+        assert!(matches!(receiver.recv().await, Err(LspError::Json(_))));
+        let binary = receiver.recv().await.unwrap().unwrap();
+        assert_eq!(binary.method(), Some("initialized"));
+        assert!(receiver.recv().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_sent_as_text_frames() {
+        let (mut transport, mut peer) = connected().await;
+
+        let message = RpcMessage::Notification(NotificationMessage::new("exit"));
+        MessageSender::send(&mut transport, &message).await.unwrap();
+
+        match peer.next().await.unwrap().unwrap() {
+            WsMessage::Text(text) => assert_eq!(text, r#"{"jsonrpc":"2.0","method":"exit"}"#),
+            other => panic!("Expected text frame, got {:?}", other),
+        }
+    }
+}
@@ -5,13 +5,23 @@
 
 use serde::{Deserialize, Serialize};
 
+pub mod document;
 pub mod initialization;
 pub mod jsonrpc;
 pub mod lsp;
+pub mod notification;
+pub mod request;
+pub mod window;
+pub mod workspace;
 
+pub use document::*;
 pub use initialization::*;
 pub use jsonrpc::*;
 pub use lsp::*;
+pub use notification::Notification;
+pub use request::Request;
+pub use window::*;
+pub use workspace::*;
 
 /// Type alias for request/notification IDs.
 /// Can be either a number or a string as per JSON-RPC spec.
@@ -0,0 +1,186 @@
+//! Text document synchronization types.
+//!
+//! These are the parameters of the `textDocument/didOpen`, `didChange` and
+//! `didClose` notifications, along with helpers for applying content changes
+//! to a client-side copy of a document.
+
+use crate::types::{
+    DocumentUri, Position, Range, TextDocumentIdentifier, VersionedTextDocumentIdentifier,
+};
+use serde::{Deserialize, Serialize};
+
+/// An item to transfer a text document from the client to the server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentItem {
+    /// The text document's URI.
+    pub uri: DocumentUri,
+    /// The text document's language identifier.
+    pub language_id: String,
+    /// The version number of this document (it will increase after each change, including undo/redo).
+    pub version: i32,
+    /// The content of the opened text document.
+    pub text: String,
+}
+
+impl TextDocumentItem {
+    /// Create a new text document item.
+    pub fn new(
+        uri: impl Into<DocumentUri>,
+        language_id: impl Into<String>,
+        version: i32,
+        text: impl Into<String>,
+    ) -> Self {
+        Self {
+            uri: uri.into(),
+            language_id: language_id.into(),
+            version,
+            text: text.into(),
+        }
+    }
+}
+
+/// Parameters of the `textDocument/didOpen` notification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidOpenTextDocumentParams {
+    /// The document that was opened.
+    pub text_document: TextDocumentItem,
+}
+
+/// Parameters of the `textDocument/didChange` notification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidChangeTextDocumentParams {
+    /// The document that did change. The version number points to the
+    /// version after all provided content changes have been applied.
+    pub text_document: VersionedTextDocumentIdentifier,
+    /// The actual content changes, applied in order.
+    pub content_changes: Vec<TextDocumentContentChangeEvent>,
+}
+
+/// Parameters of the `textDocument/didClose` notification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DidCloseTextDocumentParams {
+    /// The document that was closed.
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// An event describing a change to a text document.
+///
+/// If `range` is omitted the new text is considered to be the full content
+/// of the document.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentContentChangeEvent {
+    /// The range of the document that changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+    /// The optional length of the range that got replaced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range_length: Option<u32>,
+    /// The new text for the provided range, or the whole document.
+    pub text: String,
+}
+
+impl TextDocumentContentChangeEvent {
+    /// Create a change replacing the whole content of the document.
+    pub fn full(text: impl Into<String>) -> Self {
+        Self {
+            range: None,
+            range_length: None,
+            text: text.into(),
+        }
+    }
+
+    /// Create a change replacing the given range of the document.
+    pub fn incremental(range: Range, text: impl Into<String>) -> Self {
+        Self {
+            range: Some(range),
+            range_length: None,
+            text: text.into(),
+        }
+    }
+
+    /// Apply this change to the given document content.
+    pub fn apply(&self, document: &mut String) {
+        match self.range {
+            Some(range) => {
+                let start = offset_at(document, range.start);
+                let end = offset_at(document, range.end).max(start);
+                document.replace_range(start..end, &self.text);
+            }
+            None => document.clone_from(&self.text),
+        }
+    }
+}
+
+/// Convert a position to a byte offset into `text`.
+///
+/// Characters are counted in UTF-16 code units. Positions past the end of a
+/// line or of the document are clamped to it.
+pub fn offset_at(text: &str, position: Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(index) => line_start += index + 1,
+            None => return text.len(),
+        }
+    }
+
+    let line = &text[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let line = line.strip_suffix('\r').unwrap_or(line);
+
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + index;
+        }
+        units += c.len_utf16();
+    }
+    line_start + line.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_document_item_serialization() {
+        let item = TextDocumentItem::new("file:///test.rs", "rust", 1, "fn main() {}");
+        let json = serde_json::to_value(&item).unwrap();
+        assert_eq!(json["languageId"], "rust");
+
+        let deserialized: TextDocumentItem = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized, item);
+    }
+
+    #[test]
+    fn test_offset_at_counts_utf16_units() {
+        let text = "a😀b\r\nsecond";
+        assert_eq!(offset_at(text, Position::new(0, 0)), 0);
+        assert_eq!(offset_at(text, Position::new(0, 1)), 1);
+        assert_eq!(offset_at(text, Position::new(0, 3)), 5);
+        assert_eq!(offset_at(text, Position::new(0, 99)), 6);
+        assert_eq!(offset_at(text, Position::new(1, 2)), 10);
+        assert_eq!(offset_at(text, Position::new(5, 0)), text.len());
+    }
+
+    #[test]
+    fn test_apply_content_changes() {
+        let mut text = "hello world\nsecond line".to_string();
+
+        TextDocumentContentChangeEvent::incremental(Range::from_coords(0, 6, 0, 11), "there")
+            .apply(&mut text);
+        assert_eq!(text, "hello there\nsecond line");
+
+        TextDocumentContentChangeEvent::incremental(Range::from_coords(0, 11, 1, 0), " ")
+            .apply(&mut text);
+        assert_eq!(text, "hello there second line");
+
+        TextDocumentContentChangeEvent::full("replaced").apply(&mut text);
+        assert_eq!(text, "replaced");
+    }
+}
@@ -205,6 +205,13 @@ pub struct ProgressParams<T> {
     pub value: T,
 }
 
+/// Parameters for the window/workDoneProgress/create request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkDoneProgressCreateParams {
+    /// The token to be used to report progress.
+    pub token: crate::types::ProgressToken,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -154,6 +154,20 @@ pub struct Diagnostic {
     pub data: Option<serde_json::Value>,
 }
 
+/// Parameters of the `textDocument/publishDiagnostics` notification.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PublishDiagnosticsParams {
+    /// The URI for which diagnostic information is reported.
+    pub uri: DocumentUri,
+
+    /// The version number of the document the diagnostics are published for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+
+    /// An array of diagnostic information items.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
 /// The diagnostic's severity.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
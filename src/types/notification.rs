@@ -0,0 +1,158 @@
+//! Typed notification definitions.
+//!
+//! Each LSP notification modelled by this crate has a marker type implementing
+//! [`Notification`], tying its method name to its parameter type.
+
+use crate::types::{
+    CancelParams, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, InitializedParams, LogMessageParams, LogTraceParams, ProgressParams,
+    PublishDiagnosticsParams, SetTraceParams, ShowMessageParams,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// A notification as defined by the LSP specification.
+pub trait Notification {
+    /// The parameters sent with the notification.
+    type Params: Serialize + DeserializeOwned + Send + Sync + 'static;
+    /// The method name of the notification.
+    const METHOD: &'static str;
+}
+
+/// The `initialized` notification, sent after the client received the `initialize` result.
+#[derive(Debug)]
+pub enum Initialized {}
+
+impl Notification for Initialized {
+    type Params = InitializedParams;
+    const METHOD: &'static str = "initialized";
+}
+
+/// The `exit` notification, asking the server to exit its process.
+#[derive(Debug)]
+pub enum Exit {}
+
+impl Notification for Exit {
+    type Params = ();
+    const METHOD: &'static str = "exit";
+}
+
+/// The `$/cancelRequest` notification, used to cancel an in-flight request.
+#[derive(Debug)]
+pub enum Cancel {}
+
+impl Notification for Cancel {
+    type Params = CancelParams;
+    const METHOD: &'static str = "$/cancelRequest";
+}
+
+/// The `$/progress` notification, used to report progress in a generic fashion.
+#[derive(Debug)]
+pub enum Progress {}
+
+impl Notification for Progress {
+    type Params = ProgressParams<serde_json::Value>;
+    const METHOD: &'static str = "$/progress";
+}
+
+/// The `textDocument/didOpen` notification, sent when a document is opened.
+#[derive(Debug)]
+pub enum DidOpenTextDocument {}
+
+impl Notification for DidOpenTextDocument {
+    type Params = DidOpenTextDocumentParams;
+    const METHOD: &'static str = "textDocument/didOpen";
+}
+
+/// The `textDocument/didChange` notification, sent when a document's content changes.
+#[derive(Debug)]
+pub enum DidChangeTextDocument {}
+
+impl Notification for DidChangeTextDocument {
+    type Params = DidChangeTextDocumentParams;
+    const METHOD: &'static str = "textDocument/didChange";
+}
+
+/// The `textDocument/didClose` notification, sent when a document is closed.
+#[derive(Debug)]
+pub enum DidCloseTextDocument {}
+
+impl Notification for DidCloseTextDocument {
+    type Params = DidCloseTextDocumentParams;
+    const METHOD: &'static str = "textDocument/didClose";
+}
+
+/// The `textDocument/publishDiagnostics` notification, sent by the server to report diagnostics.
+#[derive(Debug)]
+pub enum PublishDiagnostics {}
+
+impl Notification for PublishDiagnostics {
+    type Params = PublishDiagnosticsParams;
+    const METHOD: &'static str = "textDocument/publishDiagnostics";
+}
+
+/// The `window/showMessage` notification, asking the client to show a message.
+#[derive(Debug)]
+pub enum ShowMessage {}
+
+impl Notification for ShowMessage {
+    type Params = ShowMessageParams;
+    const METHOD: &'static str = "window/showMessage";
+}
+
+/// The `window/logMessage` notification, asking the client to log a message.
+#[derive(Debug)]
+pub enum LogMessage {}
+
+impl Notification for LogMessage {
+    type Params = LogMessageParams;
+    const METHOD: &'static str = "window/logMessage";
+}
+
+/// The `$/logTrace` notification, used to log the trace of the server's execution.
+#[derive(Debug)]
+pub enum LogTrace {}
+
+impl Notification for LogTrace {
+    type Params = LogTraceParams;
+    const METHOD: &'static str = "$/logTrace";
+}
+
+/// The `$/setTrace` notification, used to change the server's trace setting.
+#[derive(Debug)]
+pub enum SetTrace {}
+
+impl Notification for SetTrace {
+    type Params = SetTraceParams;
+    const METHOD: &'static str = "$/setTrace";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification_methods() {
+        assert_eq!(Initialized::METHOD, "initialized");
+        assert_eq!(Exit::METHOD, "exit");
+        assert_eq!(Cancel::METHOD, "$/cancelRequest");
+        assert_eq!(Progress::METHOD, "$/progress");
+        assert_eq!(DidOpenTextDocument::METHOD, "textDocument/didOpen");
+        assert_eq!(DidChangeTextDocument::METHOD, "textDocument/didChange");
+        assert_eq!(DidCloseTextDocument::METHOD, "textDocument/didClose");
+        assert_eq!(
+            PublishDiagnostics::METHOD,
+            "textDocument/publishDiagnostics"
+        );
+        assert_eq!(ShowMessage::METHOD, "window/showMessage");
+        assert_eq!(LogMessage::METHOD, "window/logMessage");
+        assert_eq!(LogTrace::METHOD, "$/logTrace");
+        assert_eq!(SetTrace::METHOD, "$/setTrace");
+    }
+
+    #[test]
+    fn test_initialized_params_serialize_as_empty_object() {
+        let params = serde_json::to_value(InitializedParams {}).unwrap();
+        assert_eq!(params, serde_json::json!({}));
+    }
+}
@@ -0,0 +1,99 @@
+//! Typed request definitions.
+//!
+//! Each LSP request modelled by this crate has a marker type implementing
+//! [`Request`], tying its method name to its parameter and result types.
+
+use crate::types::{
+    ConfigurationParams, InitializeParams, InitializeResult, RegistrationParams,
+    UnregistrationParams, WorkDoneProgressCreateParams,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// A request as defined by the LSP specification.
+pub trait Request {
+    /// The parameters sent with the request.
+    type Params: Serialize + DeserializeOwned + Send + Sync + 'static;
+    /// The result returned by a successful response.
+    type Result: Serialize + DeserializeOwned + Send + Sync + 'static;
+    /// The method name of the request.
+    const METHOD: &'static str;
+}
+
+/// The `initialize` request, sent as the first request from the client to the server.
+#[derive(Debug)]
+pub enum Initialize {}
+
+impl Request for Initialize {
+    type Params = InitializeParams;
+    type Result = InitializeResult;
+    const METHOD: &'static str = "initialize";
+}
+
+/// The `shutdown` request, asking the server to shut down without exiting.
+#[derive(Debug)]
+pub enum Shutdown {}
+
+impl Request for Shutdown {
+    type Params = ();
+    type Result = ();
+    const METHOD: &'static str = "shutdown";
+}
+
+/// The `client/registerCapability` request, sent by the server to register a capability.
+#[derive(Debug)]
+pub enum RegisterCapability {}
+
+impl Request for RegisterCapability {
+    type Params = RegistrationParams;
+    type Result = ();
+    const METHOD: &'static str = "client/registerCapability";
+}
+
+/// The `client/unregisterCapability` request, sent by the server to unregister a capability.
+#[derive(Debug)]
+pub enum UnregisterCapability {}
+
+impl Request for UnregisterCapability {
+    type Params = UnregistrationParams;
+    type Result = ();
+    const METHOD: &'static str = "client/unregisterCapability";
+}
+
+/// The `workspace/configuration` request, fetching configuration settings from the client.
+#[derive(Debug)]
+pub enum WorkspaceConfiguration {}
+
+impl Request for WorkspaceConfiguration {
+    type Params = ConfigurationParams;
+    type Result = Vec<serde_json::Value>;
+    const METHOD: &'static str = "workspace/configuration";
+}
+
+/// The `window/workDoneProgress/create` request, asking the client to create a progress token.
+#[derive(Debug)]
+pub enum WorkDoneProgressCreate {}
+
+impl Request for WorkDoneProgressCreate {
+    type Params = WorkDoneProgressCreateParams;
+    type Result = ();
+    const METHOD: &'static str = "window/workDoneProgress/create";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_methods() {
+        assert_eq!(Initialize::METHOD, "initialize");
+        assert_eq!(Shutdown::METHOD, "shutdown");
+        assert_eq!(RegisterCapability::METHOD, "client/registerCapability");
+        assert_eq!(UnregisterCapability::METHOD, "client/unregisterCapability");
+        assert_eq!(WorkspaceConfiguration::METHOD, "workspace/configuration");
+        assert_eq!(
+            WorkDoneProgressCreate::METHOD,
+            "window/workDoneProgress/create"
+        );
+    }
+}
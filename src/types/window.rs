@@ -0,0 +1,106 @@
+//! Window and trace types.
+//!
+//! These are the parameters of notifications a server sends to show or log
+//! messages in the client.
+
+use crate::types::TraceValue;
+use serde::{Deserialize, Serialize};
+
+/// The type of a message shown or logged by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum MessageType {
+    /// An error message.
+    Error = 1,
+    /// A warning message.
+    Warning = 2,
+    /// An information message.
+    Info = 3,
+    /// A log message.
+    Log = 4,
+}
+
+impl Serialize for MessageType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = u8::deserialize(deserializer)?;
+        match value {
+            1 => Ok(MessageType::Error),
+            2 => Ok(MessageType::Warning),
+            3 => Ok(MessageType::Info),
+            4 => Ok(MessageType::Log),
+            _ => Err(serde::de::Error::custom(format!(
+                "Invalid message type: {}",
+                value
+            ))),
+        }
+    }
+}
+
+/// Parameters of the `window/showMessage` notification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShowMessageParams {
+    /// The message type.
+    #[serde(rename = "type")]
+    pub typ: MessageType,
+    /// The actual message.
+    pub message: String,
+}
+
+/// Parameters of the `window/logMessage` notification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogMessageParams {
+    /// The message type.
+    #[serde(rename = "type")]
+    pub typ: MessageType,
+    /// The actual message.
+    pub message: String,
+}
+
+/// Parameters of the `$/logTrace` notification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogTraceParams {
+    /// The message to be logged.
+    pub message: String,
+    /// Additional information that can be computed if the trace is set to `verbose`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbose: Option<String>,
+}
+
+/// Parameters of the `$/setTrace` notification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetTraceParams {
+    /// The new value that should be assigned to the trace setting.
+    pub value: TraceValue,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_log_message_params_wire_format() {
+        let params = LogMessageParams {
+            typ: MessageType::Warning,
+            message: "careful".to_string(),
+        };
+        let json = serde_json::to_value(&params).unwrap();
+        assert_eq!(json, json!({"type": 2, "message": "careful"}));
+
+        let deserialized: LogMessageParams = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized, params);
+        assert!(serde_json::from_value::<MessageType>(json!(5)).is_err());
+    }
+}
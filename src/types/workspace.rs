@@ -0,0 +1,107 @@
+//! Types for requests the server sends to the client.
+//!
+//! These cover dynamic capability registration and the
+//! `workspace/configuration` request.
+
+use crate::types::DocumentUri;
+use serde::{Deserialize, Serialize};
+
+/// General parameters to register for a capability.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Registration {
+    /// The ID used to register the request, which can be used to unregister it again.
+    pub id: String,
+    /// The method / capability to register for.
+    pub method: String,
+    /// Options necessary for the registration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub register_options: Option<serde_json::Value>,
+}
+
+/// Parameters of the `client/registerCapability` request.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistrationParams {
+    /// The capabilities to register.
+    pub registrations: Vec<Registration>,
+}
+
+/// General parameters to unregister a capability.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Unregistration {
+    /// The ID used to unregister the request or notification.
+    pub id: String,
+    /// The method / capability to unregister for.
+    pub method: String,
+}
+
+/// Parameters of the `client/unregisterCapability` request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnregistrationParams {
+    /// The capabilities to unregister. The misspelled name is part of the specification.
+    #[serde(rename = "unregisterations")]
+    pub unregistrations: Vec<Unregistration>,
+}
+
+/// A configuration section the server asks for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationItem {
+    /// The scope to get the configuration section for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope_uri: Option<DocumentUri>,
+    /// The configuration section asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+}
+
+/// Parameters of the `workspace/configuration` request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigurationParams {
+    /// The configuration sections asked for, answered in the same order.
+    pub items: Vec<ConfigurationItem>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_registration_params_wire_format() {
+        let params: RegistrationParams = serde_json::from_value(json!({
+            "registrations": [{
+                "id": "1",
+                "method": "workspace/didChangeWatchedFiles",
+                "registerOptions": {"watchers": []}
+            }]
+        }))
+        .unwrap();
+        assert_eq!(
+            params.registrations[0].register_options,
+            Some(json!({"watchers": []}))
+        );
+
+        let params = UnregistrationParams {
+            unregistrations: vec![Unregistration {
+                id: "1".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+            }],
+        };
+        let json = serde_json::to_value(&params).unwrap();
+        assert!(json["unregisterations"].is_array());
+    }
+
+    #[test]
+    fn test_configuration_item_wire_format() {
+        let item = ConfigurationItem {
+            scope_uri: Some("file:///project".to_string()),
+            section: Some("rust-analyzer".to_string()),
+        };
+        let json = serde_json::to_value(&item).unwrap();
+        assert_eq!(
+            json,
+            json!({"scopeUri": "file:///project", "section": "rust-analyzer"})
+        );
+    }
+}
@@ -3,11 +3,12 @@
 //! This module provides shared testing utilities, mock implementations,
 //! and test data for integration and unit tests.
 #![allow(dead_code)]
-use tokio_lsp::{prelude::*, Client};
 use std::io::{Cursor, Result as IoResult};
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf, ReadHalf, WriteHalf};
+use tokio_lsp::transport::Transport;
+use tokio_lsp::{prelude::*, Client, ClientConfig};
 
 /// Mock transport that can be configured with predefined responses
 pub struct MockTransport {
@@ -126,12 +127,74 @@ impl TestMessages {
 }
 
 /// Create a client with mock transport for testing
-pub fn create_test_client(read_data: Vec<u8>) -> Client<MockTransport, MockTransport> {
+pub fn create_test_client(read_data: Vec<u8>) -> Client {
     let reader = MockTransport::new(read_data);
     let writer = MockTransport::new(Vec::new());
     Client::new(reader, writer)
 }
 
+/// Server-side transport connected to an in-memory duplex stream
+pub type DuplexTransport = Transport<ReadHalf<DuplexStream>, WriteHalf<DuplexStream>>;
+
+/// Create a client connected to a server-side transport over an in-memory duplex stream
+pub fn create_duplex_client() -> (Client, DuplexTransport) {
+    create_duplex_client_with_config(ClientConfig::default())
+}
+
+/// Create a duplex-connected client with a custom configuration
+pub fn create_duplex_client_with_config(config: ClientConfig) -> (Client, DuplexTransport) {
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+    let (client_reader, client_writer) = tokio::io::split(client_stream);
+    let (server_reader, server_writer) = tokio::io::split(server_stream);
+    (
+        Client::with_config(client_reader, client_writer, config),
+        Transport::new(server_reader, server_writer),
+    )
+}
+
+/// Create a duplex-connected client that has completed the initialize request
+pub async fn create_initialized_duplex_client() -> (Client, DuplexTransport) {
+    create_initialized_duplex_client_with_config(ClientConfig::default()).await
+}
+
+/// Create an initialized duplex-connected client with a custom configuration
+pub async fn create_initialized_duplex_client_with_config(
+    config: ClientConfig,
+) -> (Client, DuplexTransport) {
+    let (client, mut server) = create_duplex_client_with_config(config);
+    let respond = async {
+        let request = match read_rpc_message(&mut server).await {
+            RpcMessage::Request(request) => request,
+            other => panic!("Expected initialize request, got {:?}", other),
+        };
+        let result = serde_json::to_value(test_initialize_result()).unwrap();
+        server
+            .write_rpc_message(&RpcMessage::Response(ResponseMessage::success(
+                request.id, result,
+            )))
+            .await
+            .unwrap();
+    };
+    let (result, _) = tokio::join!(client.initialize(test_init_params()), respond);
+    result.expect("Initialize should succeed");
+    (client, server)
+}
+
+/// Create an initialize result for a server without any capabilities
+pub fn test_initialize_result() -> InitializeResult {
+    InitializeResult {
+        capabilities: ServerCapabilities {
+            text_document_sync: None,
+            completion_provider: None,
+            hover_provider: None,
+        },
+        server_info: Some(ServerInfo {
+            name: "Test Server".to_string(),
+            version: None,
+        }),
+    }
+}
+
 /// Create test initialization parameters
 pub fn test_init_params() -> InitializeParams {
     InitializeParams {
@@ -178,3 +241,14 @@ pub fn test_diagnostic() -> Diagnostic {
         data: None,
     }
 }
+
+/// Read the next message from a server-side transport and parse it
+pub async fn read_rpc_message(server: &mut DuplexTransport) -> RpcMessage {
+    let message = tokio::time::timeout(std::time::Duration::from_secs(1), server.read_message())
+        .await
+        .expect("Server should receive a message")
+        .expect("Message should be readable");
+    message
+        .parse_rpc_message()
+        .expect("Message should be valid JSON-RPC")
+}
@@ -6,10 +6,17 @@
 mod common;
 
 use common::*;
-use tokio_lsp::{error::*, types::*, Client};
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use serde_json::json;
 use std::io::Cursor;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, DuplexStream};
 use tokio::time::{timeout, Duration};
+use tokio_lsp::client::{ConnectConfig, OverflowPolicy};
+use tokio_lsp::transport::{
+    MessageReceiver, MessageSender, MessageTransport, Recording, ReplayConfig, TraceFormat, Tracer,
+};
+use tokio_lsp::{error::*, types::*, Client, ClientConfig, ClientState, DispatchMode};
 
 #[tokio::test]
 async fn test_client_creation() {
@@ -29,7 +36,8 @@ async fn test_receive_message_request() {
     let reader = Cursor::new(request_data.into_bytes());
     let writer = Cursor::new(Vec::new());
 
-    let mut client = Client::new(reader, writer);
+    let config = ClientConfig::default().with_forward_unhandled_requests(true);
+    let mut client = Client::with_config(reader, writer, config);
 
     // Give the background task a moment to process the message
     tokio::time::sleep(Duration::from_millis(50)).await;
@@ -100,7 +108,8 @@ async fn test_receive_multiple_messages() {
     let reader = Cursor::new(multiple_data.into_bytes());
     let writer = Cursor::new(Vec::new());
 
-    let mut client = Client::new(reader, writer);
+    let config = ClientConfig::default().with_forward_unhandled_requests(true);
+    let mut client = Client::with_config(reader, writer, config);
 
     // Give the background task a moment to process messages
     tokio::time::sleep(Duration::from_millis(50)).await;
@@ -142,9 +151,12 @@ async fn test_receive_multiple_messages() {
         _ => panic!("Expected progress notification third"),
     }
 
-    // Should have no more messages
+    // The input is exhausted, so the connection is closed
     let result = timeout(Duration::from_millis(50), client.receive_message()).await;
-    assert!(result.is_err(), "Should timeout with no more messages");
+    assert!(
+        matches!(result, Ok(None)),
+        "Should end after the last message"
+    );
 }
 
 #[tokio::test]
@@ -233,9 +245,9 @@ async fn test_client_with_empty_data() {
 
     let mut client = Client::new(reader, writer);
 
-    // Attempting to receive from empty data should timeout
+    // Empty input closes the connection straight away
     let result = timeout(Duration::from_millis(50), client.receive_message()).await;
-    assert!(result.is_err(), "Should timeout with empty data");
+    assert!(matches!(result, Ok(None)), "Should end with empty data");
 }
 
 #[tokio::test]
@@ -340,3 +352,1254 @@ async fn test_large_message_handling() {
         }
     }
 }
+
+#[tokio::test]
+async fn test_send_does_not_wait_for_incoming_messages() {
+    let (client, mut server) = create_duplex_client();
+
+    // The server never writes anything, so the reader task stays blocked.
+    timeout(
+        Duration::from_millis(500),
+        client.send_notification("test/notification", Some(json!({"key": "value"}))),
+    )
+    .await
+    .expect("Sending should not wait for the server")
+    .expect("Notification should be written");
+
+    let message = timeout(Duration::from_millis(500), server.read_message())
+        .await
+        .expect("Server should receive the notification")
+        .unwrap();
+    assert_eq!(
+        message.parse_rpc_message().unwrap().method(),
+        Some("test/notification")
+    );
+}
+
+#[tokio::test]
+async fn test_request_response_round_trip() {
+    let (client, mut server) = create_initialized_duplex_client().await;
+
+    let server_task = tokio::spawn(async move {
+        let message = server.read_message().await.unwrap();
+        let request = match message.parse_rpc_message().unwrap() {
+            RpcMessage::Request(request) => request,
+            other => panic!("Expected request, got {:?}", other),
+        };
+        assert_eq!(request.method, "test/echo");
+        let response = ResponseMessage::success(request.id, request.params.unwrap());
+        server
+            .write_rpc_message(&RpcMessage::Response(response))
+            .await
+            .unwrap();
+    });
+
+    let response = timeout(
+        Duration::from_secs(1),
+        client.send_request("test/echo", Some(json!({"value": 42}))),
+    )
+    .await
+    .expect("Request should not timeout")
+    .unwrap();
+
+    assert_eq!(response.result, Some(json!({"value": 42})));
+    assert_eq!(client.pending_request_count().await, 0);
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_request_timeout_override() {
+    let (client, mut server) = create_initialized_duplex_client().await;
+
+    let result = client
+        .send_request_with_timeout("test/slow", None, Duration::from_millis(50))
+        .await;
+
+    let request_id = match result {
+        Err(LspError::Timeout {
+            method,
+            id,
+            elapsed,
+        }) => {
+            assert_eq!(method, "test/slow");
+            assert!(elapsed >= Duration::from_millis(50));
+            id
+        }
+        other => panic!("Expected timeout, got {:?}", other),
+    };
+    assert_eq!(client.pending_request_count().await, 0);
+
+    // The server sees the request followed by its cancellation
+    match read_rpc_message(&mut server).await {
+        RpcMessage::Request(request) => assert_eq!(request.id, request_id),
+        other => panic!("Expected request, got {:?}", other),
+    }
+    match read_rpc_message(&mut server).await {
+        RpcMessage::Notification(notification) => {
+            assert_eq!(notification.method, "$/cancelRequest");
+            let params: CancelParams =
+                serde_json::from_value(notification.params.unwrap()).unwrap();
+            assert_eq!(params.id, request_id);
+        }
+        other => panic!("Expected cancel notification, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_default_request_timeout_from_config() {
+    let config = ClientConfig::default().with_request_timeout(Duration::from_millis(30));
+    let (client, _server) = create_initialized_duplex_client_with_config(config).await;
+
+    let result = client.send_request("test/slow", None).await;
+    assert!(matches!(result, Err(LspError::Timeout { .. })));
+}
+
+/// Read the next message and assert that it cancels the given request
+async fn expect_cancel_request(server: &mut DuplexTransport, expected: &Id) {
+    match read_rpc_message(server).await {
+        RpcMessage::Notification(notification) => {
+            assert_eq!(notification.method, "$/cancelRequest");
+            let params: CancelParams =
+                serde_json::from_value(notification.params.unwrap()).unwrap();
+            assert_eq!(&params.id, expected);
+        }
+        other => panic!("Expected cancel notification, got {:?}", other),
+    }
+}
+
+fn assert_cancelled(result: tokio_lsp::Result<ResponseMessage>) {
+    match result {
+        Err(LspError::Protocol(error)) => {
+            assert_eq!(error.code, error_codes::REQUEST_CANCELLED);
+        }
+        other => panic!("Expected cancellation error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_dropping_request_handle_cancels_request() {
+    let (client, mut server) = create_initialized_duplex_client().await;
+
+    let handle = client.start_request("test/slow", None).await.unwrap();
+    let id = handle.id().clone();
+    drop(handle);
+
+    assert!(read_rpc_message(&mut server).await.is_request());
+    expect_cancel_request(&mut server, &id).await;
+    assert_eq!(client.pending_request_count().await, 0);
+}
+
+#[tokio::test]
+async fn test_request_canceller_fails_waiting_caller() {
+    let (client, mut server) = create_initialized_duplex_client().await;
+
+    let handle = client.start_request("test/slow", None).await.unwrap();
+    let id = handle.id().clone();
+    let canceller = handle.canceller();
+
+    let waiter = tokio::spawn(handle);
+    canceller.cancel();
+
+    assert_cancelled(waiter.await.unwrap());
+    assert!(read_rpc_message(&mut server).await.is_request());
+    expect_cancel_request(&mut server, &id).await;
+}
+
+#[tokio::test]
+async fn test_completed_request_is_not_cancelled() {
+    let (client, mut server) = create_initialized_duplex_client().await;
+
+    let handle = client.start_request("test/fast", None).await.unwrap();
+    let id = handle.id().clone();
+    server
+        .write_rpc_message(&RpcMessage::Response(ResponseMessage::success(
+            id,
+            json!(null),
+        )))
+        .await
+        .unwrap();
+    assert!(handle.await.is_ok());
+
+    client.send_notification("test/marker", None).await.unwrap();
+    assert!(read_rpc_message(&mut server).await.is_request());
+    assert_eq!(
+        read_rpc_message(&mut server).await.method(),
+        Some("test/marker")
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_all_requests_notifies_server() {
+    let (client, mut server) = create_initialized_duplex_client().await;
+
+    let first = client.start_request("test/one", None).await.unwrap();
+    let second = client.start_request("test/two", None).await.unwrap();
+    let ids = vec![first.id().clone(), second.id().clone()];
+    let waiters = vec![tokio::spawn(first), tokio::spawn(second)];
+
+    client.cancel_all_requests().await;
+    assert_eq!(client.pending_request_count().await, 0);
+    for waiter in waiters {
+        assert_cancelled(waiter.await.unwrap());
+    }
+
+    assert!(read_rpc_message(&mut server).await.is_request());
+    assert!(read_rpc_message(&mut server).await.is_request());
+    let mut cancelled = Vec::new();
+    for _ in 0..2 {
+        let notification = read_rpc_message(&mut server).await;
+        assert_eq!(notification.method(), Some("$/cancelRequest"));
+        if let RpcMessage::Notification(notification) = notification {
+            let params: CancelParams =
+                serde_json::from_value(notification.params.unwrap()).unwrap();
+            cancelled.push(params.id);
+        }
+    }
+    cancelled.sort_by_key(|id| id.to_string());
+    assert_eq!(cancelled, ids);
+}
+
+/// Answer the next request on the server side with the given response body
+async fn respond_to_next_request(
+    server: &mut DuplexTransport,
+    respond: impl FnOnce(RequestMessage) -> ResponseMessage,
+) {
+    let request = match read_rpc_message(server).await {
+        RpcMessage::Request(request) => request,
+        other => panic!("Expected request, got {:?}", other),
+    };
+    server
+        .write_rpc_message(&RpcMessage::Response(respond(request)))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_typed_request() {
+    use tokio_lsp::types::request::{Initialize, Request};
+
+    let (client, mut server) = create_duplex_client();
+
+    let server_task = tokio::spawn(async move {
+        respond_to_next_request(&mut server, |request| {
+            assert_eq!(request.method, Initialize::METHOD);
+            let params: InitializeParams = serde_json::from_value(request.params.unwrap()).unwrap();
+            assert_eq!(params.process_id, Some(12345));
+            let result = serde_json::to_value(test_initialize_result()).unwrap();
+            ResponseMessage::success(request.id, result)
+        })
+        .await;
+    });
+
+    let result = client
+        .request::<Initialize>(test_init_params())
+        .await
+        .unwrap();
+    assert_eq!(result, test_initialize_result());
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_typed_request_error_response() {
+    use tokio_lsp::types::request::Initialize;
+
+    let (client, mut server) = create_duplex_client();
+
+    let server_task = tokio::spawn(async move {
+        respond_to_next_request(&mut server, |request| {
+            ResponseMessage::error(
+                Some(request.id),
+                ResponseError::invalid_params("missing capabilities"),
+            )
+        })
+        .await;
+    });
+
+    match client.request::<Initialize>(test_init_params()).await {
+        Err(LspError::Protocol(error)) => {
+            assert_eq!(error.code, error_codes::INVALID_PARAMS);
+            assert_eq!(error.message, "missing capabilities");
+        }
+        other => panic!("Expected protocol error, got {:?}", other),
+    }
+    server_task.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_typed_notification() {
+    use tokio_lsp::types::notification::{Notification, Progress};
+
+    let (client, mut server) = create_duplex_client();
+
+    client
+        .notify::<Progress>(ProgressParams {
+            token: Id::from("indexing"),
+            value: json!({"kind": "end"}),
+        })
+        .await
+        .unwrap();
+
+    match read_rpc_message(&mut server).await {
+        RpcMessage::Notification(notification) => {
+            assert_eq!(notification.method, Progress::METHOD);
+            assert_eq!(
+                notification.params,
+                Some(json!({"token": "indexing", "value": {"kind": "end"}}))
+            );
+        }
+        other => panic!("Expected notification, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_requests_rejected_before_initialize() {
+    let (client, _server) = create_duplex_client();
+
+    match client.send_request("textDocument/hover", None).await {
+        Err(LspError::Protocol(error)) => {
+            assert_eq!(error.code, error_codes::SERVER_NOT_INITIALIZED);
+        }
+        other => panic!("Expected protocol error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_shutdown_and_exit() {
+    let (client, mut server) = create_initialized_duplex_client().await;
+    assert_eq!(client.state(), ClientState::Initialized);
+
+    let pending = client.start_request("test/slow", None).await.unwrap();
+    assert!(read_rpc_message(&mut server).await.is_request());
+
+    let server_task = tokio::spawn(async move {
+        respond_to_next_request(&mut server, |request| {
+            assert_eq!(request.method, "shutdown");
+            assert!(request.params.is_none());
+            ResponseMessage::success(request.id, json!(null))
+        })
+        .await;
+        assert_eq!(read_rpc_message(&mut server).await.method(), Some("exit"));
+        // The server closes the connection once it exits
+        drop(server);
+    });
+
+    timeout(Duration::from_secs(2), client.shutdown())
+        .await
+        .expect("Shutdown should complete")
+        .unwrap();
+    server_task.await.unwrap();
+
+    assert_eq!(client.state(), ClientState::Exited);
+    assert!(matches!(pending.await, Err(LspError::Connection(_))));
+    assert!(!client.has_pending_requests().await);
+
+    match client.send_request("textDocument/hover", None).await {
+        Err(LspError::Protocol(error)) => {
+            assert_eq!(error.code, error_codes::INVALID_REQUEST);
+        }
+        other => panic!("Expected protocol error, got {:?}", other),
+    }
+    assert!(client
+        .send_notification("textDocument/didOpen", None)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_pending_requests_fail_when_connection_closes() {
+    let (client, server) = create_initialized_duplex_client().await;
+
+    let pending = client.start_request("test/slow", None).await.unwrap();
+    drop(server);
+
+    let result = timeout(Duration::from_secs(1), pending)
+        .await
+        .expect("Pending request should fail");
+    assert!(matches!(result, Err(LspError::Connection(_))));
+
+    timeout(Duration::from_secs(1), client.closed())
+        .await
+        .expect("Client should notice the closed connection");
+    assert!(client.is_closed());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_spawned_server_reports_stderr_and_exit_status() {
+    let (_client, mut process) = Client::spawn(
+        "sh",
+        ["-c", "echo \"$GREETING\" >&2; exit 3"],
+        [("GREETING", "hello from the server")],
+        None,
+    )
+    .unwrap();
+    assert!(process.id().is_some());
+
+    let mut stderr = process.take_stderr().unwrap();
+    let line = timeout(Duration::from_secs(2), stderr.recv())
+        .await
+        .expect("Server should write to stderr");
+    assert_eq!(line.as_deref(), Some("hello from the server"));
+
+    let status = timeout(Duration::from_secs(2), process.wait())
+        .await
+        .expect("Server should exit")
+        .unwrap();
+    assert_eq!(status.code(), Some(3));
+    assert_eq!(process.exit_status(), Some(status));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_spawned_server_death_fails_pending_requests() {
+    let response = r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities":{}}}"#;
+    let script = format!(
+        "printf 'Content-Length: {}\\r\\n\\r\\n%s' '{}'; sleep 0.2; exit 1",
+        response.len(),
+        response
+    );
+    let (client, mut process) = Client::spawn(
+        "sh",
+        ["-c", script.as_str()],
+        Vec::<(&str, &str)>::new(),
+        None,
+    )
+    .unwrap();
+
+    client.initialize(test_init_params()).await.unwrap();
+    let pending = client.start_request("test/slow", None).await.unwrap();
+
+    let result = timeout(Duration::from_secs(2), pending)
+        .await
+        .expect("Pending request should fail");
+    assert!(matches!(result, Err(LspError::Connection(_))));
+    assert_eq!(process.wait().await.unwrap().code(), Some(1));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_kill_spawned_server() {
+    let (_client, mut process) = Client::spawn(
+        "sleep",
+        ["30"],
+        Vec::<(&str, &str)>::new(),
+        Some(std::env::temp_dir().as_path()),
+    )
+    .unwrap();
+
+    let status = timeout(Duration::from_secs(2), process.kill())
+        .await
+        .expect("Server should be killed")
+        .unwrap();
+    assert!(!status.success());
+}
+
+/// Send a request from the server and return the client's response
+async fn server_request(
+    server: &mut DuplexTransport,
+    id: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> ResponseMessage {
+    let request = RequestMessage::with_params(id, method, params);
+    server
+        .write_rpc_message(&RpcMessage::Request(request))
+        .await
+        .unwrap();
+    match read_rpc_message(server).await {
+        RpcMessage::Response(response) => {
+            assert_eq!(response.id, Some(Id::from(id)));
+            response
+        }
+        other => panic!("Expected response, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_request_handler_answers_server_request() {
+    let (client, mut server) = create_duplex_client();
+    client.on_request::<request::WorkspaceConfiguration, _, _>(|params| async move {
+        Ok(params
+            .items
+            .into_iter()
+            .map(|item| json!({ "section": item.section }))
+            .collect())
+    });
+
+    let response = server_request(
+        &mut server,
+        "config",
+        "workspace/configuration",
+        json!({"items": [{"section": "rust-analyzer"}]}),
+    )
+    .await;
+    assert_eq!(response.result, Some(json!([{"section": "rust-analyzer"}])));
+
+    // Removing the handler falls back to the automatic reply
+    assert!(client.remove_request_handler("workspace/configuration"));
+    let response = server_request(
+        &mut server,
+        "again",
+        "workspace/configuration",
+        json!({"items": []}),
+    )
+    .await;
+    assert_eq!(response.error.unwrap().code, error_codes::METHOD_NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_unhandled_server_request_gets_method_not_found() {
+    let (mut client, mut server) = create_duplex_client();
+
+    let response = server_request(
+        &mut server,
+        "1",
+        "client/registerCapability",
+        json!({"registrations": []}),
+    )
+    .await;
+    let error = response.error.expect("Response should be an error");
+    assert_eq!(error.code, error_codes::METHOD_NOT_FOUND);
+
+    // The request is not passed on to the caller
+    let message = timeout(Duration::from_millis(50), client.receive_message()).await;
+    assert!(message.is_err(), "Handled request should not be forwarded");
+}
+
+#[tokio::test]
+async fn test_request_handler_failures_become_error_responses() {
+    let (client, mut server) = create_duplex_client();
+    client.on_request::<request::RegisterCapability, _, _>(|params| async move {
+        match params.registrations.first().map(|r| r.method.as_str()) {
+            Some("modified") => Err(LspError::Protocol(ResponseError::content_modified("stale"))),
+            Some("panic") => panic!("handler bug"),
+            _ => Err(LspError::Other("registration failed".to_string())),
+        }
+    });
+
+    let registration = |method: &str| json!({"registrations": [{"id": "1", "method": method}]});
+    let error_code = |response: ResponseMessage| response.error.expect("Expected error").code;
+
+    let response = server_request(
+        &mut server,
+        "a",
+        "client/registerCapability",
+        registration("modified"),
+    )
+    .await;
+    assert_eq!(error_code(response), error_codes::CONTENT_MODIFIED);
+
+    let response = server_request(
+        &mut server,
+        "b",
+        "client/registerCapability",
+        registration("other"),
+    )
+    .await;
+    assert_eq!(error_code(response), error_codes::INTERNAL_ERROR);
+
+    let response = server_request(
+        &mut server,
+        "c",
+        "client/registerCapability",
+        registration("panic"),
+    )
+    .await;
+    assert_eq!(error_code(response), error_codes::INTERNAL_ERROR);
+
+    let response = server_request(
+        &mut server,
+        "d",
+        "client/registerCapability",
+        json!({"bad": true}),
+    )
+    .await;
+    assert_eq!(error_code(response), error_codes::INVALID_PARAMS);
+}
+
+async fn server_notify(server: &mut DuplexTransport, method: &str, params: serde_json::Value) {
+    let notification = NotificationMessage::with_params(method, params);
+    server
+        .write_rpc_message(&RpcMessage::Notification(notification))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_subscribe_to_typed_notifications() {
+    use futures::StreamExt;
+    use tokio_lsp::types::notification::{LogMessage, PublishDiagnostics};
+
+    let (mut client, mut server) = create_duplex_client();
+    let mut diagnostics = client.subscribe::<PublishDiagnostics>();
+    let mut more_diagnostics = client.subscribe::<PublishDiagnostics>();
+    let mut log_messages = client.subscribe::<LogMessage>();
+
+    let published = PublishDiagnosticsParams {
+        uri: "file:///test.rs".to_string(),
+        version: Some(3),
+        diagnostics: vec![test_diagnostic()],
+    };
+    server_notify(
+        &mut server,
+        "textDocument/publishDiagnostics",
+        serde_json::to_value(&published).unwrap(),
+    )
+    .await;
+    server_notify(
+        &mut server,
+        "window/logMessage",
+        json!({"type": 3, "message": "ready"}),
+    )
+    .await;
+    server_notify(
+        &mut server,
+        "$/progress",
+        json!({"token": "t", "value": {}}),
+    )
+    .await;
+
+    let wait = Duration::from_secs(1);
+    let received = timeout(wait, diagnostics.next()).await.unwrap();
+    assert_eq!(received, Some(published.clone()));
+    let received = timeout(wait, more_diagnostics.next()).await.unwrap();
+    assert_eq!(received, Some(published));
+    let log_message = timeout(wait, log_messages.next()).await.unwrap().unwrap();
+    assert_eq!(log_message.typ, MessageType::Info);
+    assert_eq!(log_message.message, "ready");
+
+    // Only the notification nobody subscribed to reaches the catch-all stream
+    match timeout(Duration::from_secs(1), client.receive_message()).await {
+        Ok(Some(RpcMessage::Notification(notification))) => {
+            assert_eq!(notification.method, "$/progress");
+        }
+        other => panic!("Expected progress notification, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_dropped_subscription_falls_back_to_catch_all() {
+    use futures::StreamExt;
+    use tokio_lsp::types::notification::LogMessage;
+
+    let (mut client, mut server) = create_duplex_client();
+    let subscription = client.subscribe::<LogMessage>();
+    let mut remaining = client.subscribe::<notification::ShowMessage>();
+    drop(subscription);
+
+    server_notify(
+        &mut server,
+        "window/logMessage",
+        json!({"type": 4, "message": "log"}),
+    )
+    .await;
+    match timeout(Duration::from_secs(1), client.receive_message()).await {
+        Ok(Some(RpcMessage::Notification(notification))) => {
+            assert_eq!(notification.method, "window/logMessage");
+        }
+        other => panic!("Expected log notification, got {:?}", other),
+    }
+
+    // Subscriptions end once the connection is closed
+    drop(server);
+    let end = timeout(Duration::from_secs(1), remaining.next()).await;
+    assert_eq!(end.expect("Subscription should end"), None);
+
+    // Subscribing afterwards gives a subscription that has already ended
+    client.closed().await;
+    let mut late = client.subscribe::<LogMessage>();
+    let end = timeout(Duration::from_secs(1), late.next()).await;
+    assert_eq!(end.expect("Late subscription should end"), None);
+}
+
+/// Wait until the client has dropped the given number of messages
+async fn wait_for_dropped(client: &Client, expected: u64) {
+    timeout(Duration::from_secs(1), async {
+        while client.dropped_messages() < expected {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("Client should drop messages");
+}
+
+async fn expect_log_message(client: &mut Client, expected: &str) {
+    match timeout(Duration::from_secs(1), client.receive_message()).await {
+        Ok(Some(RpcMessage::Notification(notification))) => {
+            assert_eq!(notification.params.unwrap()["message"], expected);
+        }
+        other => panic!("Expected log message {}, got {:?}", expected, other),
+    }
+}
+
+#[tokio::test]
+async fn test_default_config_keeps_every_message() {
+    let (mut client, mut server) = create_duplex_client();
+
+    for i in 0..2000 {
+        let message = format!("message {}", i);
+        server_notify(
+            &mut server,
+            "window/logMessage",
+            json!({"type": 4, "message": message}),
+        )
+        .await;
+    }
+    for i in 0..2000 {
+        expect_log_message(&mut client, &format!("message {}", i)).await;
+    }
+    assert_eq!(client.dropped_messages(), 0);
+}
+
+#[tokio::test]
+async fn test_full_queue_drops_oldest_messages() {
+    use futures::StreamExt;
+
+    let config = ClientConfig::default().with_message_capacity(2);
+    let (mut client, mut server) = create_duplex_client_with_config(config);
+    let mut progress = client.subscribe::<notification::Progress>();
+
+    for i in 0..5 {
+        let message = format!("message {}", i);
+        server_notify(
+            &mut server,
+            "window/logMessage",
+            json!({"type": 4, "message": message}),
+        )
+        .await;
+        server_notify(&mut server, "$/progress", json!({"token": i, "value": {}})).await;
+    }
+    wait_for_dropped(&client, 6).await;
+    assert_eq!(progress.dropped(), 3);
+
+    expect_log_message(&mut client, "message 3").await;
+    expect_log_message(&mut client, "message 4").await;
+    let latest = timeout(Duration::from_secs(1), progress.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(latest.token, Id::Number(3));
+}
+
+#[tokio::test]
+async fn test_coalesce_keeps_latest_diagnostics_per_uri() {
+    let config =
+        ClientConfig::default().with_overflow_policy(OverflowPolicy::coalesce_diagnostics());
+    let (mut client, mut server) = create_duplex_client_with_config(config);
+
+    for (uri, version) in [
+        ("file:///a.rs", 1),
+        ("file:///b.rs", 1),
+        ("file:///a.rs", 2),
+    ] {
+        let params = json!({"uri": uri, "version": version, "diagnostics": []});
+        server_notify(&mut server, "textDocument/publishDiagnostics", params).await;
+    }
+    wait_for_dropped(&client, 1).await;
+
+    for (uri, version) in [("file:///b.rs", 1), ("file:///a.rs", 2)] {
+        match timeout(Duration::from_secs(1), client.receive_message()).await {
+            Ok(Some(RpcMessage::Notification(notification))) => {
+                let params = notification.params.unwrap();
+                assert_eq!(params["uri"], uri);
+                assert_eq!(params["version"], version);
+            }
+            other => panic!("Expected diagnostics, got {:?}", other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_block_policy_stops_reading_until_consumer_catches_up() {
+    let config = ClientConfig::default()
+        .with_message_capacity(1)
+        .with_overflow_policy(OverflowPolicy::Block);
+    let (mut client, mut server) = create_initialized_duplex_client_with_config(config).await;
+
+    let mut pending = client.start_request("test/slow", None).await.unwrap();
+    let request = match read_rpc_message(&mut server).await {
+        RpcMessage::Request(request) => request,
+        other => panic!("Expected request, got {:?}", other),
+    };
+    for i in 0..2 {
+        let message = format!("message {}", i);
+        server_notify(
+            &mut server,
+            "window/logMessage",
+            json!({"type": 4, "message": message}),
+        )
+        .await;
+    }
+    server
+        .write_rpc_message(&RpcMessage::Response(ResponseMessage::success(
+            request.id,
+            json!("done"),
+        )))
+        .await
+        .unwrap();
+
+    // The reader waits for room, so the response is not processed yet
+    assert!(timeout(Duration::from_millis(100), &mut pending)
+        .await
+        .is_err());
+
+    expect_log_message(&mut client, "message 0").await;
+    expect_log_message(&mut client, "message 1").await;
+    let response = timeout(Duration::from_secs(1), pending)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response.result, Some(json!("done")));
+    assert_eq!(client.dropped_messages(), 0);
+}
+
+/// Answer the initialize request on a server-side transport
+async fn answer_initialize<R, W>(server: &mut tokio_lsp::transport::Transport<R, W>)
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+{
+    let message = server.read_message().await.unwrap();
+    let request = match message.parse_rpc_message().unwrap() {
+        RpcMessage::Request(request) => request,
+        other => panic!("Expected initialize request, got {:?}", other),
+    };
+    assert_eq!(request.method, "initialize");
+    let result = serde_json::to_value(test_initialize_result()).unwrap();
+    server
+        .write_rpc_message(&RpcMessage::Response(ResponseMessage::success(
+            request.id, result,
+        )))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_connect_tcp() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, writer) = stream.into_split();
+        answer_initialize(&mut tokio_lsp::transport::Transport::new(reader, writer)).await;
+    });
+
+    let client = Client::connect_tcp(addr).await.unwrap();
+    let result = client.initialize(test_init_params()).await.unwrap();
+    assert_eq!(result.server_info.unwrap().name, "Test Server");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_listen_tcp_for_server_to_connect_back() {
+    let listener = Client::listen_tcp("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    // The server is told the port and connects to the client
+    let server = tokio::spawn(async move {
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (reader, writer) = stream.into_split();
+        answer_initialize(&mut tokio_lsp::transport::Transport::new(reader, writer)).await;
+    });
+
+    let client = listener
+        .accept(&ConnectConfig::default(), ClientConfig::default())
+        .await
+        .unwrap();
+    client.initialize(test_init_params()).await.unwrap();
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_listen_tcp_accept_timeout() {
+    let listener = Client::listen_tcp("127.0.0.1:0").await.unwrap();
+    let connect = ConnectConfig::default().with_accept_timeout(Duration::from_millis(50));
+    match listener.accept(&connect, ClientConfig::default()).await {
+        Err(LspError::Connection(_)) => {}
+        Err(e) => panic!("Expected connection error, got {:?}", e),
+        Ok(_) => panic!("Expected connection error, got a client"),
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_connect_unix_retries_until_server_listens() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("server.sock");
+    let server_path = path.clone();
+    let server = tokio::spawn(async move {
+        // Start listening only after the client's first attempt failed
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let listener = tokio::net::UnixListener::bind(server_path).unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, writer) = stream.into_split();
+        answer_initialize(&mut tokio_lsp::transport::Transport::new(reader, writer)).await;
+    });
+
+    assert!(Client::connect_unix(&path).await.is_err());
+    let connect = ConnectConfig::default().with_retries(20, Duration::from_millis(25));
+    let client = Client::connect_unix_with_config(&path, connect, ClientConfig::default())
+        .await
+        .unwrap();
+    client.initialize(test_init_params()).await.unwrap();
+    server.await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_listen_unix_for_server_to_connect_back() {
+    let dir = tempfile::tempdir().unwrap();
+    let listener = Client::listen_unix(dir.path().join("client.sock")).unwrap();
+    let path = listener.path().to_path_buf();
+    let server_path = path.clone();
+    let server = tokio::spawn(async move {
+        let stream = tokio::net::UnixStream::connect(server_path).await.unwrap();
+        let (reader, writer) = stream.into_split();
+        answer_initialize(&mut tokio_lsp::transport::Transport::new(reader, writer)).await;
+    });
+
+    let client = listener
+        .accept(&ConnectConfig::default(), ClientConfig::default())
+        .await
+        .unwrap();
+    client.initialize(test_init_params()).await.unwrap();
+    server.await.unwrap();
+
+    // The socket file is removed with the listener
+    drop(listener);
+    assert!(!path.exists());
+}
+
+type LineReader = tokio::io::Lines<tokio::io::BufReader<tokio::io::ReadHalf<DuplexStream>>>;
+
+/// A transport framing each message as a line of JSON
+struct LineTransport {
+    receiver: LineReceiver,
+    sender: LineSender,
+}
+
+struct LineReceiver(LineReader);
+struct LineSender(tokio::io::WriteHalf<DuplexStream>);
+
+impl LineTransport {
+    fn new(stream: DuplexStream) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Self {
+            receiver: LineReceiver(tokio::io::BufReader::new(reader).lines()),
+            sender: LineSender(writer),
+        }
+    }
+}
+
+impl MessageSender for LineSender {
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, tokio_lsp::Result<()>> {
+        async move {
+            let mut line = serde_json::to_vec(message)?;
+            line.push(b'\n');
+            self.0.write_all(&line).await?;
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+impl MessageReceiver for LineReceiver {
+    fn recv(&mut self) -> BoxFuture<'_, tokio_lsp::Result<Option<RpcMessage>>> {
+        async move {
+            match self.0.next_line().await? {
+                Some(line) => Ok(Some(serde_json::from_str(&line)?)),
+                None => Ok(None),
+            }
+        }
+        .boxed()
+    }
+}
+
+impl MessageSender for LineTransport {
+    fn send<'a>(&'a mut self, message: &'a RpcMessage) -> BoxFuture<'a, tokio_lsp::Result<()>> {
+        self.sender.send(message)
+    }
+}
+
+impl MessageReceiver for LineTransport {
+    fn recv(&mut self) -> BoxFuture<'_, tokio_lsp::Result<Option<RpcMessage>>> {
+        self.receiver.recv()
+    }
+}
+
+impl MessageTransport for LineTransport {
+    type Sender = LineSender;
+    type Receiver = LineReceiver;
+
+    fn split(self) -> (LineReceiver, LineSender) {
+        (self.receiver, self.sender)
+    }
+}
+
+#[tokio::test]
+async fn test_client_with_custom_message_transport() {
+    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+    let client = Client::with_transport(LineTransport::new(client_stream), ClientConfig::default());
+    let mut server = LineTransport::new(server_stream);
+
+    let respond = async {
+        let request = match server.recv().await.unwrap() {
+            Some(RpcMessage::Request(request)) => request,
+            other => panic!("Expected initialize request, got {:?}", other),
+        };
+        let result = serde_json::to_value(test_initialize_result()).unwrap();
+        let response = RpcMessage::Response(ResponseMessage::success(request.id, result));
+        server.send(&response).await.unwrap();
+    };
+    let (result, _) = tokio::join!(client.initialize(test_init_params()), respond);
+    assert_eq!(result.unwrap().server_info.unwrap().name, "Test Server");
+
+    // An invalid line is skipped, and the end of the stream closes the client
+    let (_, LineSender(mut writer)) = server.split();
+    writer.write_all(b"not json\n").await.unwrap();
+    writer.shutdown().await.unwrap();
+    timeout(Duration::from_secs(1), client.closed())
+        .await
+        .expect("Client should notice the closed connection");
+}
+
+#[tokio::test]
+async fn test_client_over_in_memory_pair() {
+    let (client_end, mut server) = tokio_lsp::transport::pair();
+    let client = Client::with_transport(client_end, ClientConfig::default());
+    client.on_request::<request::WorkspaceConfiguration, _, _>(|params| async move {
+        Ok(params
+            .items
+            .iter()
+            .map(|_| json!({"enabled": true}))
+            .collect())
+    });
+
+    let respond = async {
+        let request = match server.recv().await.unwrap() {
+            Some(RpcMessage::Request(request)) => request,
+            other => panic!("Expected initialize request, got {:?}", other),
+        };
+        let result = serde_json::to_value(test_initialize_result()).unwrap();
+        let response = RpcMessage::Response(ResponseMessage::success(request.id, result));
+        server.send(&response).await.unwrap();
+    };
+    let (result, _) = tokio::join!(client.initialize(test_init_params()), respond);
+    result.unwrap();
+
+    // The server asks the client, which answers through its handler
+    let request = RequestMessage::with_params(
+        Id::String("config".to_string()),
+        "workspace/configuration",
+        json!({"items": [{"section": "test"}]}),
+    );
+    server.send(&RpcMessage::Request(request)).await.unwrap();
+    match timeout(Duration::from_secs(1), server.recv()).await {
+        Ok(Ok(Some(RpcMessage::Response(response)))) => {
+            assert_eq!(response.id, Some(Id::String("config".to_string())));
+            assert_eq!(response.result, Some(json!([{"enabled": true}])));
+        }
+        other => panic!("Expected configuration response, got {:?}", other),
+    }
+
+    drop(server);
+    timeout(Duration::from_secs(1), client.closed())
+        .await
+        .expect("Client should notice the closed connection");
+}
+
+#[tokio::test]
+async fn test_receive_message_ends_when_server_disconnects() {
+    let (client_end, mut server) = tokio_lsp::transport::pair();
+    let mut client = Client::with_transport(client_end, ClientConfig::default());
+
+    // Messages queued before the disconnect are still delivered
+    let notification = NotificationMessage::new("custom/notification");
+    server
+        .send(&RpcMessage::Notification(notification))
+        .await
+        .unwrap();
+    drop(server);
+
+    let message = timeout(Duration::from_secs(1), client.receive_message())
+        .await
+        .expect("Queued message should be delivered");
+    assert_eq!(message.unwrap().method(), Some("custom/notification"));
+    let message = timeout(Duration::from_secs(1), client.receive_message())
+        .await
+        .expect("receive_message should end after the server disconnects");
+    assert!(message.is_none());
+}
+
+/// A server request the client answers after the delay it names.
+enum Delayed {}
+
+impl Request for Delayed {
+    type Params = serde_json::Value;
+    type Result = serde_json::Value;
+    const METHOD: &'static str = "test/delayed";
+}
+
+#[tokio::test]
+async fn test_client_orders_server_requests_per_document() {
+    let (client_end, mut server) = tokio_lsp::transport::pair();
+    let config = ClientConfig::default().with_request_dispatch(DispatchMode::PerDocument);
+    let client = Client::with_transport(client_end, config);
+    client.on_request::<Delayed, _, _>(|params| async move {
+        let delay = params["delay"].as_u64().unwrap_or_default();
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+        Ok(params["delay"].clone())
+    });
+
+    let requests = [
+        ("file:///a.rs", 100),
+        ("file:///b.rs", 20),
+        ("file:///a.rs", 0),
+    ];
+    for (id, (uri, delay)) in requests.into_iter().enumerate() {
+        let params = json!({"textDocument": {"uri": uri}, "delay": delay});
+        let request = RequestMessage::with_params(id as i64, "test/delayed", params);
+        server.send(&RpcMessage::Request(request)).await.unwrap();
+    }
+
+    // Responses go out as handlers complete, but the second request about
+    // a.rs waits for the first
+    let mut answered = Vec::new();
+    for _ in 0..requests.len() {
+        match timeout(Duration::from_secs(1), server.recv()).await {
+            Ok(Ok(Some(RpcMessage::Response(response)))) => answered.push(response.id.unwrap()),
+            other => panic!("Expected response, got {:?}", other),
+        }
+    }
+    assert_eq!(answered, vec![Id::Number(1), Id::Number(0), Id::Number(2)]);
+}
+
+#[tokio::test]
+async fn test_client_traces_messages_to_file() {
+    let trace_file = tempfile::NamedTempFile::new().unwrap();
+    let tracer = Tracer::to_file(trace_file.path(), TraceFormat::Jsonl).unwrap();
+    let (client_end, mut server) = tokio_lsp::transport::pair();
+    let config = ClientConfig::default().with_tracer(tracer.clone());
+    let client = Client::with_transport(client_end, config);
+
+    let respond = async {
+        let request = match server.recv().await.unwrap() {
+            Some(RpcMessage::Request(request)) => request,
+            other => panic!("Expected initialize request, got {:?}", other),
+        };
+        let result = serde_json::to_value(test_initialize_result()).unwrap();
+        let response = RpcMessage::Response(ResponseMessage::success(request.id, result));
+        server.send(&response).await.unwrap();
+    };
+    let (result, _) = tokio::join!(client.initialize(test_init_params()), respond);
+    result.unwrap();
+    client.initialized().await.unwrap();
+    let initialized = server.recv().await.unwrap().unwrap();
+    assert_eq!(initialized.method(), Some("initialized"));
+
+    // Turning tracing off also tells the server, without tracing that
+    client.set_trace(TraceValue::Off).await.unwrap();
+    match server.recv().await.unwrap() {
+        Some(RpcMessage::Notification(notification)) => {
+            assert_eq!(notification.method, "$/setTrace");
+            assert_eq!(notification.params, Some(json!({"value": "off"})));
+        }
+        other => panic!("Expected $/setTrace, got {:?}", other),
+    }
+
+    // The trace is buffered until flushed
+    tracer.flush().unwrap();
+    let trace = std::fs::read_to_string(trace_file.path()).unwrap();
+    let entries: Vec<serde_json::Value> = trace
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let summary: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            (
+                entry["direction"].as_str().unwrap(),
+                entry["type"].as_str().unwrap(),
+                entry["method"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("outbound", "request", "initialize"),
+            ("inbound", "response", "initialize"),
+            ("outbound", "notification", "initialized"),
+        ]
+    );
+    assert_eq!(entries[0]["id"], entries[1]["id"]);
+    assert!(entries[1]["latencyMs"].is_u64());
+}
+
+#[tokio::test]
+async fn test_recorded_session_replays_against_new_client() {
+    let trace_file = tempfile::NamedTempFile::new().unwrap();
+
+    // Record a session against a scripted server
+    let tracer = Recording::recorder(trace_file.path()).unwrap();
+    let (client_end, mut server) = tokio_lsp::transport::pair();
+    let client = Client::with_transport(client_end, ClientConfig::default().with_tracer(tracer));
+    let serve = tokio::spawn(async move {
+        while let Some(message) = server.recv().await.unwrap() {
+            let request = match message {
+                RpcMessage::Request(request) => request,
+                message if message.method() == Some("exit") => break,
+                _ => continue,
+            };
+            let result = match request.method.as_str() {
+                "initialize" => serde_json::to_value(test_initialize_result()).unwrap(),
+                _ => json!(null),
+            };
+            let response = ResponseMessage::success(request.id, result);
+            server.send(&RpcMessage::Response(response)).await.unwrap();
+        }
+    });
+    client.initialize(test_init_params()).await.unwrap();
+    client.initialized().await.unwrap();
+    client.shutdown().await.unwrap();
+    drop(client);
+    serve.await.unwrap();
+
+    // Replay it against a new client with other initialize parameters. The
+    // recorded request IDs are moved away from those the new client uses, so
+    // the replay has to map between them.
+    let recording = Recording::from_file(trace_file.path()).unwrap();
+    assert_eq!(recording.messages().len(), 6);
+    let offset_id = |id: &mut Id| {
+        if let Id::Number(number) = id {
+            *number += 100;
+        }
+    };
+    let messages = recording
+        .messages()
+        .iter()
+        .cloned()
+        .map(|(direction, mut message)| {
+            match &mut message {
+                RpcMessage::Request(request) => offset_id(&mut request.id),
+                RpcMessage::Response(response) => response.id.iter_mut().for_each(offset_id),
+                RpcMessage::Notification(_) => {}
+            }
+            (direction, message)
+        })
+        .collect::<Vec<_>>();
+    assert!(matches!(
+        &messages[0].1,
+        RpcMessage::Request(request) if request.id == Id::Number(101)
+    ));
+    let recording = Recording::new(messages);
+    let config = ReplayConfig::default().with_ignored_params("initialize");
+    let (client_end, replay) = recording.replay_with_config(config);
+    let client = Client::with_transport(client_end, ClientConfig::default());
+    let mut params = test_init_params();
+    params.process_id = Some(54321);
+    client.initialize(params).await.unwrap();
+    client.initialized().await.unwrap();
+    client.shutdown().await.unwrap();
+    drop(client);
+    replay.finish().await.unwrap();
+}
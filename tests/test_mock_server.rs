@@ -0,0 +1,227 @@
+//! Integration tests for the scriptable mock language server
+//!
+//! These tests drive a real `Client` against `MockServer` over its in-memory
+//! duplex stream.
+#![cfg(feature = "testing")]
+
+mod common;
+
+use common::*;
+use futures::StreamExt;
+use serde_json::json;
+use tokio::time::{timeout, Duration};
+use tokio_lsp::error::ResponseError;
+use tokio_lsp::testing::{MockServer, Reply};
+use tokio_lsp::types::notification::PublishDiagnostics;
+use tokio_lsp::types::request::WorkspaceConfiguration;
+use tokio_lsp::types::*;
+use tokio_lsp::{ClientConfig, ClientState, LspError};
+
+#[tokio::test]
+async fn test_mock_server_replies_to_initialize_with_capabilities() {
+    let mut capabilities = test_initialize_result().capabilities;
+    capabilities.hover_provider = Some(HoverProviderCapability::Simple(true));
+    let (client, server) = MockServer::builder()
+        .with_capabilities(capabilities.clone())
+        .start();
+
+    let result = client.initialize(test_init_params()).await.unwrap();
+    assert_eq!(result.capabilities, capabilities);
+    client.initialized().await.unwrap();
+    assert_eq!(client.state(), ClientState::Initialized);
+
+    let message = server
+        .wait_for("initialized", Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert!(matches!(message, RpcMessage::Notification(_)));
+    let initialize = server.assert_received("initialize");
+    assert_eq!(
+        initialize.id(),
+        server.received()[0].id(),
+        "initialize is the first message"
+    );
+}
+
+#[tokio::test]
+async fn test_mock_server_scripted_replies() {
+    let (client, server) = MockServer::builder()
+        .on_request_matching(
+            "textDocument/hover",
+            json!({"position": {"line": 3}}),
+            Reply::result(json!({"contents": "line three"})),
+        )
+        .on_request(
+            "textDocument/hover",
+            Reply::error(ResponseError::content_modified("Document changed")),
+        )
+        .start();
+    client.initialize(test_init_params()).await.unwrap();
+
+    let hover = |line| {
+        let params = json!({
+            "textDocument": {"uri": "file:///test.rs"},
+            "position": {"line": line, "character": 0},
+        });
+        client.send_request("textDocument/hover", Some(params))
+    };
+    let response = hover(3).await.unwrap();
+    assert_eq!(response.result, Some(json!({"contents": "line three"})));
+    let response = hover(4).await.unwrap();
+    assert_eq!(response.error.unwrap().message, "Document changed");
+
+    // Requests without a scripted reply are not found
+    let response = client
+        .send_request("textDocument/definition", None)
+        .await
+        .unwrap();
+    assert_eq!(
+        response.error.unwrap().code,
+        ResponseError::method_not_found("").code
+    );
+    assert_eq!(server.received_with_method("textDocument/hover").len(), 2);
+}
+
+#[tokio::test]
+async fn test_mock_server_delayed_reply_times_out() {
+    let (client, _server) = MockServer::builder()
+        .on_request(
+            "workspace/symbol",
+            Reply::result(json!([])).with_delay(Duration::from_millis(200)),
+        )
+        .start_with_config(ClientConfig::default().with_request_timeout(Duration::from_millis(50)));
+    client.initialize(test_init_params()).await.unwrap();
+
+    let result = client.send_request("workspace/symbol", None).await;
+    assert!(matches!(result, Err(LspError::Timeout { .. })));
+
+    // Other requests are answered while the reply is delayed
+    let result = client
+        .send_request_with_timeout("workspace/symbol", None, Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert_eq!(result.result, Some(json!([])));
+}
+
+#[tokio::test]
+async fn test_mock_server_pushes_notifications() {
+    let diagnostics = json!({"uri": "file:///test.rs", "diagnostics": []});
+    let (client, server) = MockServer::builder()
+        .on_notification(
+            "textDocument/didOpen",
+            NotificationMessage::with_params("textDocument/publishDiagnostics", diagnostics),
+        )
+        .start();
+    let mut subscription = client.subscribe::<PublishDiagnostics>();
+    client.initialize(test_init_params()).await.unwrap();
+    client.initialized().await.unwrap();
+
+    let open = json!({"textDocument": {
+        "uri": "file:///test.rs", "languageId": "rust", "version": 1, "text": "",
+    }});
+    client
+        .send_notification("textDocument/didOpen", Some(open))
+        .await
+        .unwrap();
+    let params = timeout(Duration::from_secs(1), subscription.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(params.uri, "file:///test.rs");
+
+    // Notifications can also be pushed at any time
+    server
+        .notify::<PublishDiagnostics>(PublishDiagnosticsParams {
+            uri: "file:///other.rs".to_string(),
+            version: None,
+            diagnostics: Vec::new(),
+        })
+        .await
+        .unwrap();
+    let params = timeout(Duration::from_secs(1), subscription.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(params.uri, "file:///other.rs");
+}
+
+#[tokio::test]
+async fn test_mock_server_requests_reach_client_handlers() {
+    let (client, server) = MockServer::builder().start();
+    client.on_request::<WorkspaceConfiguration, _, _>(|params| async move {
+        Ok(params.items.iter().map(|_| json!("value")).collect())
+    });
+    client.initialize(test_init_params()).await.unwrap();
+
+    let params = ConfigurationParams {
+        items: vec![ConfigurationItem {
+            scope_uri: None,
+            section: Some("test".to_string()),
+        }],
+    };
+    server
+        .request::<WorkspaceConfiguration>("config", params)
+        .await
+        .unwrap();
+    let response = timeout(Duration::from_secs(1), async {
+        loop {
+            let response = server
+                .received()
+                .into_iter()
+                .find(|message| matches!(message, RpcMessage::Response(_)));
+            if let Some(RpcMessage::Response(response)) = response {
+                return response;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(response.id, Some(Id::String("config".to_string())));
+    assert_eq!(response.result, Some(json!(["value"])));
+}
+
+#[tokio::test]
+async fn test_mock_server_closes_on_exit() {
+    let (client, server) = MockServer::builder().start();
+    client.initialize(test_init_params()).await.unwrap();
+    client.initialized().await.unwrap();
+
+    timeout(Duration::from_secs(1), client.shutdown())
+        .await
+        .expect("Server should close the connection after exit")
+        .unwrap();
+    assert!(client.is_closed());
+    server.assert_received("shutdown");
+    server.assert_received("exit");
+    assert!(server
+        .notify::<PublishDiagnostics>(PublishDiagnosticsParams {
+            uri: "file:///test.rs".to_string(),
+            version: None,
+            diagnostics: Vec::new(),
+        })
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_mock_server_close_fails_pending_requests() {
+    let (client, server) = MockServer::builder()
+        .on_request(
+            "workspace/symbol",
+            Reply::result(json!([])).with_delay(Duration::from_secs(10)),
+        )
+        .start();
+    client.initialize(test_init_params()).await.unwrap();
+
+    let request = client.send_request("workspace/symbol", None);
+    let close = async {
+        server
+            .wait_for("workspace/symbol", Duration::from_secs(1))
+            .await
+            .unwrap();
+        server.close().await;
+    };
+    let (result, _) = tokio::join!(request, close);
+    assert!(matches!(result, Err(LspError::Connection(_))));
+}
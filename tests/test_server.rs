@@ -0,0 +1,129 @@
+//! Integration tests for the server role
+//!
+//! These tests drive a real `Client` against a `Server` over the in-memory
+//! transport pair.
+
+mod common;
+
+use common::*;
+use futures::StreamExt;
+use serde_json::json;
+use tokio::time::{timeout, Duration};
+use tokio_lsp::error::{error_codes, ResponseError};
+use tokio_lsp::transport::pair;
+use tokio_lsp::types::notification::{DidOpenTextDocument, PublishDiagnostics};
+use tokio_lsp::types::request::WorkspaceConfiguration;
+use tokio_lsp::types::*;
+use tokio_lsp::{Client, ClientConfig, LspError, Server};
+
+/// A request the test server answers, echoing its parameters.
+enum Echo {}
+
+impl Request for Echo {
+    type Params = serde_json::Value;
+    type Result = serde_json::Value;
+    const METHOD: &'static str = "test/echo";
+}
+
+#[tokio::test]
+async fn test_client_and_server_complete_lifecycle() {
+    let mut capabilities = test_initialize_result().capabilities;
+    capabilities.hover_provider = Some(HoverProviderCapability::Simple(true));
+    let server = Server::builder()
+        .with_capabilities(capabilities.clone())
+        .with_server_info("test-server", Some("1.0.0".to_string()))
+        .on_request::<Echo, _, _>(|params, _| async move {
+            match params {
+                serde_json::Value::Null => Err(LspError::Protocol(ResponseError::invalid_params(
+                    "Nothing to echo",
+                ))),
+                params => Ok(params),
+            }
+        })
+        .build();
+    let (client_end, server_end) = pair();
+    let serve = tokio::spawn(server.serve_transport(server_end));
+    let client = Client::with_transport(client_end, ClientConfig::default());
+
+    let result = client.initialize(test_init_params()).await.unwrap();
+    assert_eq!(result.capabilities, capabilities);
+    assert_eq!(result.server_info.unwrap().name, "test-server");
+    client.initialized().await.unwrap();
+
+    let echoed = client.request::<Echo>(json!({"ping": 1})).await.unwrap();
+    assert_eq!(echoed, json!({"ping": 1}));
+    let response = client.send_request("test/echo", None).await.unwrap();
+    assert_eq!(response.error.unwrap().code, error_codes::INVALID_PARAMS);
+    let response = client
+        .send_request("textDocument/definition", None)
+        .await
+        .unwrap();
+    assert_eq!(response.error.unwrap().code, error_codes::METHOD_NOT_FOUND);
+
+    client.shutdown().await.unwrap();
+    assert!(client.is_closed());
+    timeout(Duration::from_secs(1), serve)
+        .await
+        .expect("Server should stop after exit")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_server_handlers_talk_back_to_client() {
+    let server = Server::builder()
+        .on_notification::<DidOpenTextDocument, _, _>(|params, client| async move {
+            let config = ConfigurationParams {
+                items: vec![ConfigurationItem {
+                    scope_uri: Some(params.text_document.uri.clone()),
+                    section: Some("test".to_string()),
+                }],
+            };
+            let values = client.request::<WorkspaceConfiguration>(config).await?;
+            let diagnostics = if values == vec![json!({"lint": true})] {
+                vec![test_diagnostic()]
+            } else {
+                Vec::new()
+            };
+            client
+                .notify::<PublishDiagnostics>(PublishDiagnosticsParams {
+                    uri: params.text_document.uri,
+                    version: Some(params.text_document.version),
+                    diagnostics,
+                })
+                .await
+        })
+        .build();
+    let (client_end, server_end) = pair();
+    let serve = tokio::spawn(server.serve_transport(server_end));
+    let client = Client::with_transport(client_end, ClientConfig::default());
+    client.on_request::<WorkspaceConfiguration, _, _>(|params| async move {
+        Ok(params.items.iter().map(|_| json!({"lint": true})).collect())
+    });
+    let mut diagnostics = client.subscribe::<PublishDiagnostics>();
+
+    client.initialize(test_init_params()).await.unwrap();
+    client.initialized().await.unwrap();
+    client
+        .notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem {
+                uri: "file:///test.rs".to_string(),
+                language_id: "rust".to_string(),
+                version: 1,
+                text: "fn main() {}".to_string(),
+            },
+        })
+        .await
+        .unwrap();
+
+    let params = timeout(Duration::from_secs(1), diagnostics.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(params.uri, "file:///test.rs");
+    assert_eq!(params.version, Some(1));
+    assert_eq!(params.diagnostics, vec![test_diagnostic()]);
+
+    client.shutdown().await.unwrap();
+    serve.await.unwrap().unwrap();
+}
@@ -0,0 +1,353 @@
+//! Integration tests for the restart supervisor
+//!
+//! These tests run small shell scripts standing in for a language server:
+//! they answer `initialize` and then either crash or record their input.
+#![cfg(unix)]
+
+mod common;
+
+use common::*;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use tokio::sync::broadcast;
+use tokio::time::{timeout, Duration};
+use tokio_lsp::supervisor::{RestartEvent, RestartPolicy, Supervisor, SupervisorConfig};
+use tokio_lsp::{types::*, ClientState, LspError};
+
+/// A fake server that answers `initialize`, then runs `after` in `sh`.
+///
+/// `$run` holds how many times the server was started before, and `$dir`
+/// the directory the server may write its files to. The server does not
+/// answer `initialize` while `$dir/hold$run` exists.
+fn fake_server(dir: &Path, after: &str) -> impl Fn() -> Command + Send + Sync + 'static {
+    let dir = dir.to_path_buf();
+    let response = r#"{"jsonrpc":"2.0","id":1,"result":{"capabilities":{}}}"#;
+    let script = format!(
+        "run=$(cat \"$dir/runs\" 2>/dev/null || echo 0); echo $((run + 1)) > \"$dir/runs\"; \
+         while [ -e \"$dir/hold$run\" ]; do sleep 0.01; done; \
+         printf 'Content-Length: {}\\r\\n\\r\\n%s' '{}'; {}",
+        response.len(),
+        response,
+        after
+    );
+    move || {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(&script).env("dir", &dir);
+        command
+    }
+}
+
+fn fast_restarts(max_restarts: usize) -> SupervisorConfig {
+    SupervisorConfig::default().with_restart_policy(
+        RestartPolicy::default()
+            .with_backoff(Duration::from_millis(10), Duration::from_millis(50))
+            .with_crash_limit(max_restarts, Duration::from_secs(60)),
+    )
+}
+
+async fn next_event(events: &mut broadcast::Receiver<RestartEvent>) -> RestartEvent {
+    timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("Supervisor should emit an event")
+        .expect("Event channel should be open")
+}
+
+async fn wait_for_file_containing(path: PathBuf, needle: &str) -> String {
+    timeout(Duration::from_secs(5), async {
+        loop {
+            if let Ok(content) = std::fs::read_to_string(&path) {
+                if content.contains(needle) {
+                    return content;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("{} should contain {}", path.display(), needle))
+}
+
+#[tokio::test]
+async fn test_restart_replays_initialize_and_open_documents() {
+    let dir = tempfile::tempdir().unwrap();
+    // The first server crashes shortly after starting, the second one records its input
+    let command = fake_server(
+        dir.path(),
+        r#"if [ "$run" = 0 ]; then sleep 0.3; exit 1; fi; exec cat > "$dir/input$run""#,
+    );
+    let supervisor = Supervisor::start_with_config(command, test_init_params(), fast_restarts(3))
+        .await
+        .unwrap();
+    let mut events = supervisor.subscribe_restarts();
+
+    supervisor
+        .did_open(TextDocumentItem::new(
+            "file:///main.rs",
+            "rust",
+            1,
+            "fn main() {}",
+        ))
+        .await
+        .unwrap();
+    supervisor
+        .did_change(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new("file:///main.rs", 2),
+            content_changes: vec![TextDocumentContentChangeEvent::incremental(
+                Range::from_coords(0, 11, 0, 11),
+                " todo!() ",
+            )],
+        })
+        .await
+        .unwrap();
+    supervisor
+        .did_open(TextDocumentItem::new("file:///closed.rs", "rust", 1, ""))
+        .await
+        .unwrap();
+    supervisor
+        .did_close(TextDocumentIdentifier::new("file:///closed.rs"))
+        .await
+        .unwrap();
+
+    match next_event(&mut events).await {
+        RestartEvent::Crashed { exit_status } => {
+            assert_eq!(exit_status.and_then(|status| status.code()), Some(1));
+        }
+        other => panic!("Expected crash event, got {:?}", other),
+    }
+    assert_eq!(
+        next_event(&mut events).await,
+        RestartEvent::Restarted { attempt: 1 }
+    );
+
+    let input = wait_for_file_containing(dir.path().join("input1"), "textDocument/didOpen").await;
+    assert!(input.contains(r#""method":"initialized""#));
+    assert!(input.contains(r#""text":"fn main() { todo!() }""#));
+    assert!(input.contains(r#""version":2"#));
+    assert!(!input.contains("file:///closed.rs"));
+
+    let document = supervisor.document("file:///main.rs").await.unwrap();
+    assert_eq!(document.text, "fn main() { todo!() }");
+    assert_eq!(supervisor.client().state(), ClientState::Initialized);
+}
+
+#[tokio::test]
+async fn test_supervisor_gives_up_after_crash_loop() {
+    let dir = tempfile::tempdir().unwrap();
+    let command = fake_server(dir.path(), "sleep 0.1; exit 2");
+    let supervisor = Supervisor::start_with_config(command, test_init_params(), fast_restarts(2))
+        .await
+        .unwrap();
+    let mut events = supervisor.subscribe_restarts();
+
+    let mut restarts = 0;
+    loop {
+        match next_event(&mut events).await {
+            RestartEvent::Crashed { .. } => {}
+            RestartEvent::Restarted { attempt } | RestartEvent::RestartFailed { attempt, .. } => {
+                restarts += 1;
+                assert_eq!(attempt, restarts);
+            }
+            RestartEvent::GaveUp => break,
+        }
+    }
+    assert_eq!(restarts, 2);
+}
+
+#[tokio::test]
+async fn test_server_closing_output_is_killed_before_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    // The first server closes its output but keeps running
+    let command = fake_server(
+        dir.path(),
+        r#"echo $$ > "$dir/pid$run"; if [ "$run" = 0 ]; then exec sleep 30 >&-; fi; exec cat > /dev/null"#,
+    );
+    let supervisor = Supervisor::start_with_config(command, test_init_params(), fast_restarts(3))
+        .await
+        .unwrap();
+    let mut events = supervisor.subscribe_restarts();
+
+    match next_event(&mut events).await {
+        RestartEvent::Crashed { exit_status } => {
+            // Killed by a signal rather than exiting on its own
+            let exit_status = exit_status.expect("The old server should have been killed");
+            assert_eq!(exit_status.code(), None);
+        }
+        other => panic!("Expected crash event, got {:?}", other),
+    }
+    let pid = wait_for_file_containing(dir.path().join("pid0"), "").await;
+    let alive = std::process::Command::new("kill")
+        .args(["-0", pid.trim()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap()
+        .success();
+    assert!(!alive, "The old server should be killed before restarting");
+    assert_eq!(
+        next_event(&mut events).await,
+        RestartEvent::Restarted { attempt: 1 }
+    );
+}
+
+#[tokio::test]
+async fn test_documents_are_reopened_after_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    // The first server crashes once the test creates the crash marker, the
+    // second one records its input
+    let command = fake_server(
+        dir.path(),
+        r#"if [ "$run" = 0 ]; then
+             while [ ! -e "$dir/crash" ]; do sleep 0.01; done; exit 1
+           fi; exec cat > "$dir/input""#,
+    );
+    let supervisor = Supervisor::start_with_config(command, test_init_params(), fast_restarts(3))
+        .await
+        .unwrap();
+    let mut events = supervisor.subscribe_restarts();
+
+    supervisor
+        .did_open(TextDocumentItem::new(
+            "file:///main.rs",
+            "rust",
+            1,
+            "fn main() {}",
+        ))
+        .await
+        .unwrap();
+    // Documents opened through the client's store are reopened as well
+    let client = supervisor.client();
+    let documents = client.documents();
+    documents
+        .open(TextDocumentItem::new("file:///lib.rs", "rust", 1, ""))
+        .await
+        .unwrap();
+    documents
+        .update(
+            "file:///lib.rs",
+            vec![TextDocumentContentChangeEvent::full("pub mod main;")],
+        )
+        .await
+        .unwrap();
+    drop(client);
+
+    std::fs::write(dir.path().join("crash"), "").unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        RestartEvent::Crashed { .. }
+    ));
+    assert_eq!(
+        next_event(&mut events).await,
+        RestartEvent::Restarted { attempt: 1 }
+    );
+
+    let client = supervisor.client();
+    let mut uris = client.documents().uris().await;
+    uris.sort();
+    assert_eq!(uris, vec!["file:///lib.rs", "file:///main.rs"]);
+    let document = client.documents().get("file:///lib.rs").await.unwrap();
+    assert_eq!(
+        (document.version, document.text.as_str()),
+        (2, "pub mod main;")
+    );
+
+    // The reopened documents keep their versions on the new server
+    let version = client
+        .documents()
+        .update(
+            "file:///main.rs",
+            vec![TextDocumentContentChangeEvent::full("fn main() { run() }")],
+        )
+        .await
+        .unwrap();
+    assert_eq!(version, 2);
+    let input = wait_for_file_containing(dir.path().join("input"), "textDocument/didChange").await;
+    assert_eq!(input.matches("textDocument/didOpen").count(), 2);
+    assert!(input.contains(r#""text":"pub mod main;""#));
+}
+
+#[tokio::test]
+async fn test_documents_opened_while_down_are_opened_after_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    // The second server only starts answering once the test is done opening
+    std::fs::write(dir.path().join("hold1"), "").unwrap();
+    let command = fake_server(
+        dir.path(),
+        r#"if [ "$run" = 0 ]; then
+             while [ ! -e "$dir/crash" ]; do sleep 0.01; done; exit 1
+           fi; exec cat > "$dir/input""#,
+    );
+    let supervisor = Supervisor::start_with_config(command, test_init_params(), fast_restarts(3))
+        .await
+        .unwrap();
+    let mut events = supervisor.subscribe_restarts();
+
+    std::fs::write(dir.path().join("crash"), "").unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        RestartEvent::Crashed { .. }
+    ));
+    supervisor
+        .did_open(TextDocumentItem::new(
+            "file:///main.rs",
+            "rust",
+            1,
+            "fn main() {}",
+        ))
+        .await
+        .unwrap();
+    let error = supervisor
+        .did_open(TextDocumentItem::new("file:///main.rs", "rust", 1, ""))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, LspError::Document(_)));
+
+    std::fs::remove_file(dir.path().join("hold1")).unwrap();
+    assert_eq!(
+        next_event(&mut events).await,
+        RestartEvent::Restarted { attempt: 1 }
+    );
+    let input = wait_for_file_containing(dir.path().join("input"), "textDocument/didOpen").await;
+    assert!(input.contains(r#""text":"fn main() {}""#));
+    let document = supervisor.document("file:///main.rs").await.unwrap();
+    assert_eq!(document.text, "fn main() {}");
+}
+
+#[tokio::test]
+async fn test_request_handlers_are_registered_after_restart() {
+    use tokio_lsp::types::request::WorkspaceConfiguration;
+
+    let dir = tempfile::tempdir().unwrap();
+    let request = r#"{"jsonrpc":"2.0","id":"config","method":"workspace/configuration","params":{"items":[{"section":"rust"}]}}"#;
+    // The first server crashes once the test creates the crash marker, the
+    // second one asks for the configuration and records the answer
+    let after = format!(
+        r#"if [ "$run" = 0 ]; then
+             while [ ! -e "$dir/crash" ]; do sleep 0.01; done; exit 1
+           fi; printf 'Content-Length: {}\r\n\r\n%s' '{}'; exec cat > "$dir/input""#,
+        request.len(),
+        request
+    );
+    let command = fake_server(dir.path(), &after);
+    let supervisor = Supervisor::start_with_config(command, test_init_params(), fast_restarts(3))
+        .await
+        .unwrap();
+    let mut events = supervisor.subscribe_restarts();
+    supervisor.on_request::<WorkspaceConfiguration, _, _>(|params| async move {
+        Ok(params
+            .items
+            .iter()
+            .map(|_| serde_json::json!({"checkOnSave": true}))
+            .collect())
+    });
+
+    std::fs::write(dir.path().join("crash"), "").unwrap();
+    assert!(matches!(
+        next_event(&mut events).await,
+        RestartEvent::Crashed { .. }
+    ));
+    assert_eq!(
+        next_event(&mut events).await,
+        RestartEvent::Restarted { attempt: 1 }
+    );
+    let input = wait_for_file_containing(dir.path().join("input"), r#""id":"config""#).await;
+    assert!(input.contains(r#""result":[{"checkOnSave":true}]"#));
+}
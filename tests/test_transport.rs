@@ -7,9 +7,11 @@ mod common;
 
 use common::*;
 use core::panic;
-use tokio_lsp::{transport::*, types::*, Client};
+use futures::{SinkExt, StreamExt};
 use std::io::{Cursor, ErrorKind};
 use tokio::time::{timeout, Duration};
+use tokio_lsp::{transport::*, types::*, Client, ClientConfig};
+use tokio_util::codec::Framed;
 
 #[tokio::test]
 async fn test_message_parsing_from_stream() {
@@ -89,9 +91,7 @@ async fn test_message_writing() {
 
     let mut transport = Transport::new(reader, writer);
 
-    let test_rpc = RpcMessage::Notification(
-        NotificationMessage::new("test/method"),
-    );
+    let test_rpc = RpcMessage::Notification(NotificationMessage::new("test/method"));
 
     let _result = transport.write_rpc_message(&test_rpc).await;
 
@@ -304,3 +304,84 @@ async fn test_transport_with_different_async_types() {
     // but we can verify the trait bounds work
     let _: Box<dyn tokio::io::AsyncRead + Unpin + Send> = Box::new(cursor);
 }
+
+#[tokio::test]
+async fn test_codec_interoperates_with_transport() {
+    let (client_stream, server_stream) = tokio::io::duplex(4096);
+    let (server_read, server_write) = tokio::io::split(server_stream);
+    let mut server = Transport::new(server_read, server_write);
+    let mut framed = Framed::new(client_stream, LspCodec::new());
+
+    let request = RpcMessage::Request(RequestMessage::new(1, "test/codec"));
+    framed.send(&request).await.unwrap();
+    let received = server.read_message().await.unwrap();
+    assert_eq!(
+        received.parse_rpc_message().unwrap().method(),
+        Some("test/codec")
+    );
+
+    let response = RpcMessage::Response(ResponseMessage::success(1, serde_json::json!(null)));
+    server.write_rpc_message(&response).await.unwrap();
+    let received = framed.next().await.unwrap().unwrap();
+    assert!(received.parse_rpc_message().unwrap().is_response());
+}
+
+#[tokio::test]
+async fn test_client_skips_oversized_message() {
+    let large = serde_json::json!({
+        "jsonrpc": "2.0",
+        "method": "test/large",
+        "params": {"data": "x".repeat(1024)}
+    })
+    .to_string();
+    let small = r#"{"jsonrpc":"2.0","method":"test/small"}"#;
+    let input = [
+        tokio_lsp::transport::Message::new(large).to_bytes(),
+        tokio_lsp::transport::Message::new(small).to_bytes(),
+    ]
+    .concat();
+
+    let config = ClientConfig::default()
+        .with_transport_config(TransportConfig::default().with_max_content_length(256));
+    let mut client = Client::with_config(Cursor::new(input), Cursor::new(Vec::new()), config);
+
+    let message = timeout(Duration::from_secs(1), client.receive_message())
+        .await
+        .expect("Client should skip the oversized message")
+        .unwrap();
+    assert_eq!(message.method(), Some("test/small"));
+}
+
+#[tokio::test]
+async fn test_lenient_transport_reads_non_conforming_output() {
+    let output = "Listening on stdio\n\
+                  Content-Length:24\n\n{\"jsonrpc\":\"2.0\",\"id\":1}\
+                  Content-Length: 24\r\n\r\n{\"jsonrpc\":\"2.0\",\"id\":2}";
+    let mut transport = Transport::with_config(
+        Cursor::new(output.as_bytes()),
+        Cursor::new(Vec::new()),
+        TransportConfig::lenient(),
+    );
+
+    let first = transport.read_message().await.unwrap();
+    assert_eq!(first.content, "{\"jsonrpc\":\"2.0\",\"id\":1}");
+    let second = transport.read_message().await.unwrap();
+    assert_eq!(second.content, "{\"jsonrpc\":\"2.0\",\"id\":2}");
+}
+
+#[tokio::test]
+async fn test_latin1_content() {
+    // {"jsonrpc":"2.0","method":"café"} in ISO-8859-1
+    let mut content = br#"{"jsonrpc":"2.0","method":"caf"#.to_vec();
+    content.extend_from_slice(&[0xE9, b'"', b'}']);
+    let mut input = format!(
+        "Content-Length: {}\r\nContent-Type: application/vscode-jsonrpc; charset=iso-8859-1\r\n\r\n",
+        content.len()
+    )
+    .into_bytes();
+    input.extend_from_slice(&content);
+
+    let mut transport = Transport::new(Cursor::new(input), Cursor::new(Vec::new()));
+    let message = transport.read_message().await.unwrap();
+    assert_eq!(message.parse_rpc_message().unwrap().method(), Some("café"));
+}
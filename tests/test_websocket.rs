@@ -0,0 +1,102 @@
+//! Integration tests for the WebSocket transport
+//!
+//! The language server is played by an in-process WebSocket peer that
+//! exchanges one JSON-RPC message per text frame.
+#![cfg(feature = "websocket")]
+
+mod common;
+
+use common::*;
+use futures::{SinkExt, StreamExt};
+use serde_json::json;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration};
+use tokio_lsp::client::ConnectConfig;
+use tokio_lsp::types::*;
+use tokio_lsp::{Client, ClientConfig};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_tungstenite::WebSocketStream;
+
+/// Accept a WebSocket connection on a local port, returning the URL to connect to
+async fn websocket_peer() -> (String, tokio::task::JoinHandle<WebSocketStream<TcpStream>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ws://{}/lsp", listener.local_addr().unwrap());
+    let peer = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        tokio_tungstenite::accept_async(stream).await.unwrap()
+    });
+    (url, peer)
+}
+
+/// Receive the next text frame and parse it as a JSON-RPC message
+async fn next_frame(peer: &mut WebSocketStream<TcpStream>) -> RpcMessage {
+    match timeout(Duration::from_secs(1), peer.next()).await {
+        Ok(Some(Ok(WsMessage::Text(text)))) => {
+            assert!(!text.starts_with("Content-Length"));
+            serde_json::from_str(&text).unwrap()
+        }
+        other => panic!("Expected text frame, got {:?}", other),
+    }
+}
+
+async fn send_frame(peer: &mut WebSocketStream<TcpStream>, message: &RpcMessage) {
+    let text = serde_json::to_string(message).unwrap();
+    peer.send(WsMessage::Text(text)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_initialize_over_websocket() {
+    let (url, peer) = websocket_peer().await;
+    let client = Client::connect_websocket(&url).await.unwrap();
+    let mut peer = peer.await.unwrap();
+
+    let respond = async {
+        let request = match next_frame(&mut peer).await {
+            RpcMessage::Request(request) => request,
+            other => panic!("Expected initialize request, got {:?}", other),
+        };
+        assert_eq!(request.method, "initialize");
+        let result = serde_json::to_value(test_initialize_result()).unwrap();
+        send_frame(
+            &mut peer,
+            &RpcMessage::Response(ResponseMessage::success(request.id, result)),
+        )
+        .await;
+    };
+    let (result, _) = tokio::join!(client.initialize(test_init_params()), respond);
+    assert_eq!(result.unwrap().server_info.unwrap().name, "Test Server");
+
+    client.initialized().await.unwrap();
+    match next_frame(&mut peer).await {
+        RpcMessage::Notification(notification) => assert_eq!(notification.method, "initialized"),
+        other => panic!("Expected initialized notification, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_server_notifications_and_close_over_websocket() {
+    let (url, peer) = websocket_peer().await;
+    let connect = ConnectConfig::default().with_retries(3, Duration::from_millis(10));
+    let mut client = Client::connect_websocket_with_config(&url, connect, ClientConfig::default())
+        .await
+        .unwrap();
+    let mut peer = peer.await.unwrap();
+
+    let notification = NotificationMessage::with_params(
+        "window/logMessage",
+        json!({"type": 3, "message": "hello"}),
+    );
+    send_frame(&mut peer, &RpcMessage::Notification(notification)).await;
+    match timeout(Duration::from_secs(1), client.receive_message()).await {
+        Ok(Some(RpcMessage::Notification(notification))) => {
+            assert_eq!(notification.params.unwrap()["message"], "hello");
+        }
+        other => panic!("Expected notification, got {:?}", other),
+    }
+
+    // A close frame closes the connection like the end of a stdio stream
+    peer.close(None).await.unwrap();
+    timeout(Duration::from_secs(1), client.closed())
+        .await
+        .expect("Client should notice the closed connection");
+}